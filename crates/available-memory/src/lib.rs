@@ -0,0 +1,19 @@
+use std::cmp;
+use std::fs;
+
+const MAX_AVAILABLE_MEMORY_MB: usize = 129_024;
+const BYTES_PER_MB: usize = 1_048_576;
+
+/// Reads the memory limit (in MB) from the cgroup v2 or v1 controller files. Returns `None` when
+/// no limit can be detected (e.g. the file is missing or cgroup v2 reports `max`).
+#[must_use]
+pub fn available_memory_mb() -> Option<usize> {
+    [
+        "/sys/fs/cgroup/memory.max",
+        "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+    ]
+    .iter()
+    .find_map(|path| fs::read_to_string(path).ok())
+    .and_then(|contents| contents.trim().parse().ok())
+    .map(|max_bytes: usize| cmp::min(MAX_AVAILABLE_MEMORY_MB, max_bytes / BYTES_PER_MB))
+}
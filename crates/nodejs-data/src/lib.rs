@@ -159,6 +159,18 @@ impl VersionRange {
     pub fn min_version(&self) -> Option<Version> {
         self.range.min_version().map(Version)
     }
+
+    /// Returns the range of versions that satisfy both ranges, or `None` when no version can
+    /// satisfy both.
+    #[must_use]
+    pub fn intersect(&self, other: &VersionRange) -> Option<VersionRange> {
+        self.range
+            .intersect(&other.range)
+            .map(|range| VersionRange {
+                original: range.to_string(),
+                range,
+            })
+    }
 }
 
 impl TryFrom<String> for VersionRange {
@@ -302,4 +314,27 @@ mod tests {
         let result = VersionRange::parse("12.%");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn intersect_overlapping_ranges() {
+        let result = VersionRange::parse(">=20")
+            .unwrap()
+            .intersect(&VersionRange::parse("^22 || ^24").unwrap());
+
+        assert!(result.is_some());
+        if let Some(reqs) = result {
+            assert!(reqs.satisfies(&Version::new(22, 11, 0)));
+            assert!(reqs.satisfies(&Version::new(24, 1, 0)));
+            assert!(!reqs.satisfies(&Version::new(20, 18, 0)));
+            assert!(!reqs.satisfies(&Version::new(26, 0, 0)));
+        }
+    }
+
+    #[test]
+    fn intersect_returns_none_for_disjoint_ranges() {
+        let result = VersionRange::parse("<20")
+            .unwrap()
+            .intersect(&VersionRange::parse(">=22").unwrap());
+        assert!(result.is_none());
+    }
 }
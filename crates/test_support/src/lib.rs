@@ -90,7 +90,7 @@ pub fn print_build_env_buildpack() -> BuildpackReference {
             .build(indoc! { r#"
                 #!/usr/bin/env bash
                 printenv \
-                    | grep -E "^(PATH=|HEROKU_AVAILABLE_PARALLELISM=)" \
+                    | grep -E "^(PATH=|HEROKU_AVAILABLE_PARALLELISM=|NODE_ENV=)" \
                     | sed 's/\(HEROKU_AVAILABLE_PARALLELISM=\)[0-9]\+/\1<number>/' \
                     | sort
             "# })
@@ -183,6 +183,10 @@ pub(super) fn create_snapshot_filters() -> Vec<(String, String)> {
     //        Collapsing space-only lines to empty makes the output deterministic either way.
     filters.push((r"(?m)^[ \t]+$", ""));
 
+    // [buildpack] Cache sizes reported after dependency installation. e.g.;
+    // - npm cache size: `0.0 MiB` → `12.3 MiB`
+    filters.push((r"`\d+\.\d MiB`", "`<size>`"));
+
     // [npm] Summary of added packages with no audit information. e.g.;
     // - added 12 packages in 27s
     // - added 3 packages in 1.13ms
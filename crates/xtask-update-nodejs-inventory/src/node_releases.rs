@@ -21,6 +21,10 @@ static BASE_NODEJS_RELEASE_URL: LazyLock<Url> = LazyLock::new(|| {
     Url::parse("https://nodejs.org/download/release/").expect("base url should be valid")
 });
 
+/// Archive formats in order of preference. Older releases were only published as `.tar.gz` and the
+/// checksum of whichever archive is chosen is recorded with the artifact.
+const BINARY_FILE_EXTENSIONS: [&str; 2] = ["tar.xz", "tar.gz"];
+
 static STARTING_NODE_VERSION: LazyLock<Version> =
     LazyLock::new(|| Version::parse("0.8.6").expect("Starting Node.js version should be valid"));
 
@@ -88,8 +92,14 @@ pub(super) async fn from_upstream(
                 )
             });
 
-            let checksum = release_checksums
-                .get(&node_release.binary_file_name(supported_platform))
+            let (binary_file_name, checksum) = BINARY_FILE_EXTENSIONS
+                .iter()
+                .map(|extension| node_release.binary_file_name(supported_platform, extension))
+                .find_map(|binary_file_name| {
+                    release_checksums
+                        .get(&binary_file_name)
+                        .map(|checksum| (binary_file_name, checksum))
+                })
                 .unwrap_or_else(|| {
                     panic!(
                         "Should have found checksum for Node.js version {} ({})",
@@ -99,7 +109,7 @@ pub(super) async fn from_upstream(
                 });
 
             upstream_artifacts.push(NodejsArtifact {
-                url: node_release.binary_url(supported_platform).to_string(),
+                url: node_release.binary_url(&binary_file_name).to_string(),
                 version: node_release.version.clone(),
                 checksum: format!("sha256:{checksum}")
                     .parse::<Checksum<Sha256>>()
@@ -247,22 +257,23 @@ impl NodeRelease {
             })
     }
 
-    fn binary_url(&self, supported_platform: &SupportedNodeReleasePlatform) -> Url {
+    fn binary_url(&self, binary_file_name: &str) -> Url {
         self.base_version_url()
-            .join(&self.binary_file_name(supported_platform))
+            .join(binary_file_name)
             .unwrap_or_else(|_| panic!("Binary URL should be valid for {}", self.version))
     }
 
     fn binary_file_name(
         &self,
         supported_node_release_platform: &SupportedNodeReleasePlatform,
+        extension: &str,
     ) -> String {
         match supported_node_release_platform {
             SupportedNodeReleasePlatform::LinuxX64 => {
-                format!("node-v{}-{}.tar.gz", self.version, "linux-x64")
+                format!("node-v{}-{}.{extension}", self.version, "linux-x64")
             }
             SupportedNodeReleasePlatform::LinuxArm64 => {
-                format!("node-v{}-{}.tar.gz", self.version, "linux-arm64")
+                format!("node-v{}-{}.{extension}", self.version, "linux-arm64")
             }
         }
     }
@@ -7,7 +7,6 @@ use libcnb::exec_d::write_exec_d_program_output;
 use std::cmp;
 use std::collections::HashMap;
 use std::env;
-use std::fs;
 
 fn main() {
     write_exec_d_program_output(web_env(read_env("WEB_CONCURRENCY"), read_env("WEB_MEMORY")));
@@ -38,21 +37,10 @@ fn read_env(key: &str) -> Option<usize> {
     env::var(key).ok().and_then(|var| var.parse().ok())
 }
 
-const MAX_AVAILABLE_MEMORY_MB: usize = 129_024;
 const DEFAULT_AVAILABLE_MEMORY_MB: usize = 512;
-const BYTES_PER_MB: usize = 1_048_576;
 
 fn detect_available_memory() -> usize {
-    [
-        "/sys/fs/cgroup/memory.max",
-        "/sys/fs/cgroup/memory/memory.limit_in_bytes",
-    ]
-    .iter()
-    .find_map(|path| fs::read_to_string(path).ok())
-    .and_then(|contents| contents.trim().parse().ok())
-    .map_or(DEFAULT_AVAILABLE_MEMORY_MB, |max_bytes: usize| {
-        cmp::min(MAX_AVAILABLE_MEMORY_MB, max_bytes / BYTES_PER_MB)
-    })
+    available_memory::available_memory_mb().unwrap_or(DEFAULT_AVAILABLE_MEMORY_MB)
 }
 
 const DEFAULT_WEB_MEMORY_BREAKPOINT_MB: usize = 16384;
@@ -1,4 +1,7 @@
 use crate::BuildpackBuildContext;
+use crate::cache_reset::{CacheReset, RESETTABLE_LAYERS};
+use crate::committed_node_modules::CommittedNodeModulesMode;
+use crate::install_scripts::InstallScriptsMode;
 use crate::o11y::*;
 use crate::utils::error_handling::{
     ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message,
@@ -46,6 +49,19 @@ impl Display for ConfigValueSource {
 pub(crate) struct BuildpackConfig {
     pub(crate) build_scripts_enabled: Option<ConfigValue<bool>>,
     pub(crate) prune_dev_dependencies: Option<ConfigValue<bool>>,
+    pub(crate) install_scripts: Option<ConfigValue<InstallScriptsMode>>,
+    pub(crate) allowed_install_scripts: Option<ConfigValue<Vec<String>>>,
+    pub(crate) node_env: Option<ConfigValue<String>>,
+    pub(crate) compile_cache: Option<ConfigValue<bool>>,
+    pub(crate) compile_cache_modules: Option<ConfigValue<Vec<String>>>,
+    pub(crate) cache_node_modules: Option<ConfigValue<bool>>,
+    pub(crate) cache_native_modules: Option<ConfigValue<bool>>,
+    pub(crate) slim_runtime: Option<ConfigValue<bool>>,
+    pub(crate) ca_file: Option<ConfigValue<String>>,
+    pub(crate) cache_reset: Option<ConfigValue<CacheReset>>,
+    pub(crate) committed_node_modules: Option<ConfigValue<CommittedNodeModulesMode>>,
+    pub(crate) strict_dependency_engines: Option<ConfigValue<bool>>,
+    pub(crate) allow_major_upgrades: Option<ConfigValue<bool>>,
     errors: Vec<String>,
 }
 
@@ -59,6 +75,19 @@ pub(crate) struct BuildpackConfig {
 /// [com.heroku.buildpacks.nodejs]
 /// enabled = true
 /// actions.prune_dev_dependencies = false
+/// actions.install_scripts = "allow_list"
+/// actions.compile_cache = true
+/// actions.cache_node_modules = true
+/// actions.cache_native_modules = true
+/// actions.committed_node_modules = "rebuild"
+/// actions.strict_dependency_engines = true
+/// install_scripts.allowed = ["sharp", "esbuild"]
+/// compile_cache.modules = ["express", "./dist/routes.js"]
+/// runtime.node_env = "production"
+/// runtime.slim = true
+/// runtime.allow_major_upgrades = true
+/// network.ca_file = "certs/internal-ca.pem"
+/// cache.reset = ["yarn_cache"] # or `true` to clear every cached layer
 /// ```
 ///
 /// This namespacing is not necessary for buildplan entries as the contributing buildpack already has
@@ -79,6 +108,19 @@ impl BuildpackConfig {
             let BuildpackConfig {
                 build_scripts_enabled,
                 prune_dev_dependencies,
+                install_scripts,
+                allowed_install_scripts,
+                node_env,
+                compile_cache,
+                compile_cache_modules,
+                cache_node_modules,
+                cache_native_modules,
+                slim_runtime,
+                ca_file,
+                cache_reset,
+                committed_node_modules,
+                strict_dependency_engines,
+                allow_major_upgrades,
                 errors,
             } = config;
             if build_scripts_enabled.is_some() {
@@ -87,6 +129,45 @@ impl BuildpackConfig {
             if prune_dev_dependencies.is_some() {
                 merged_config.prune_dev_dependencies = prune_dev_dependencies;
             }
+            if install_scripts.is_some() {
+                merged_config.install_scripts = install_scripts;
+            }
+            if allowed_install_scripts.is_some() {
+                merged_config.allowed_install_scripts = allowed_install_scripts;
+            }
+            if node_env.is_some() {
+                merged_config.node_env = node_env;
+            }
+            if compile_cache.is_some() {
+                merged_config.compile_cache = compile_cache;
+            }
+            if compile_cache_modules.is_some() {
+                merged_config.compile_cache_modules = compile_cache_modules;
+            }
+            if cache_node_modules.is_some() {
+                merged_config.cache_node_modules = cache_node_modules;
+            }
+            if cache_native_modules.is_some() {
+                merged_config.cache_native_modules = cache_native_modules;
+            }
+            if slim_runtime.is_some() {
+                merged_config.slim_runtime = slim_runtime;
+            }
+            if ca_file.is_some() {
+                merged_config.ca_file = ca_file;
+            }
+            if cache_reset.is_some() {
+                merged_config.cache_reset = cache_reset;
+            }
+            if committed_node_modules.is_some() {
+                merged_config.committed_node_modules = committed_node_modules;
+            }
+            if strict_dependency_engines.is_some() {
+                merged_config.strict_dependency_engines = strict_dependency_engines;
+            }
+            if allow_major_upgrades.is_some() {
+                merged_config.allow_major_upgrades = allow_major_upgrades;
+            }
             merged_config.errors.extend(errors);
         }
         merged_config
@@ -118,6 +199,84 @@ impl TryFrom<&BuildpackBuildContext> for BuildpackConfig {
                     "buildpack_config"
                 );
             }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.install_scripts {
+                tracing::info!(
+                    { CONFIG_INSTALL_SCRIPTS_SOURCE } = source.to_string(),
+                    { CONFIG_INSTALL_SCRIPTS_VALUE } = value.to_string(),
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.node_env {
+                tracing::info!(
+                    { CONFIG_NODE_ENV_SOURCE } = source.to_string(),
+                    { CONFIG_NODE_ENV_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.compile_cache {
+                tracing::info!(
+                    { CONFIG_COMPILE_CACHE_SOURCE } = source.to_string(),
+                    { CONFIG_COMPILE_CACHE_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.cache_node_modules {
+                tracing::info!(
+                    { CONFIG_CACHE_NODE_MODULES_SOURCE } = source.to_string(),
+                    { CONFIG_CACHE_NODE_MODULES_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.cache_native_modules {
+                tracing::info!(
+                    { CONFIG_CACHE_NATIVE_MODULES_SOURCE } = source.to_string(),
+                    { CONFIG_CACHE_NATIVE_MODULES_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.slim_runtime {
+                tracing::info!(
+                    { CONFIG_SLIM_RUNTIME_SOURCE } = source.to_string(),
+                    { CONFIG_SLIM_RUNTIME_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.ca_file {
+                tracing::info!(
+                    { CONFIG_CA_FILE_SOURCE } = source.to_string(),
+                    { CONFIG_CA_FILE_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.cache_reset {
+                tracing::info!(
+                    { CONFIG_CACHE_RESET_SOURCE } = source.to_string(),
+                    { CONFIG_CACHE_RESET_VALUE } = value.to_string(),
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.committed_node_modules {
+                tracing::info!(
+                    { CONFIG_COMMITTED_NODE_MODULES_SOURCE } = source.to_string(),
+                    { CONFIG_COMMITTED_NODE_MODULES_VALUE } = value.to_string(),
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.strict_dependency_engines
+            {
+                tracing::info!(
+                    { CONFIG_STRICT_DEPENDENCY_ENGINES_SOURCE } = source.to_string(),
+                    { CONFIG_STRICT_DEPENDENCY_ENGINES_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
+            if let Some(ConfigValue { value, source }) = &buildpack_config.allow_major_upgrades {
+                tracing::info!(
+                    { CONFIG_ALLOW_MAJOR_UPGRADES_SOURCE } = source.to_string(),
+                    { CONFIG_ALLOW_MAJOR_UPGRADES_VALUE } = value,
+                    "buildpack_config"
+                );
+            }
         }
         buildpack_config
     }
@@ -217,6 +376,7 @@ impl TryFrom<(&ConfigValueSource, &Table)> for BuildpackConfig {
 impl TryFrom<(&ConfigValueSource, &dyn TableLike)> for BuildpackConfig {
     type Error = ErrorMessage;
 
+    #[allow(clippy::too_many_lines)]
     #[instrument(name = "buildpack_config_source", skip_all)]
     fn try_from(value: (&ConfigValueSource, &dyn TableLike)) -> Result<Self, Self::Error> {
         let (source, table) = value;
@@ -261,14 +421,269 @@ impl TryFrom<(&ConfigValueSource, &dyn TableLike)> for BuildpackConfig {
                 "buildpack_config"
             );
         }
+        let install_scripts = table
+            .get("actions")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("install_scripts"))
+            .and_then(toml_edit::Item::as_str)
+            .map(|value| {
+                value
+                    .parse::<InstallScriptsMode>()
+                    .map(|value| ConfigValue {
+                        value,
+                        source: source.clone(),
+                    })
+                    .map_err(|()| create_invalid_install_scripts_mode_error_message(source, value))
+            })
+            .transpose()?;
+        let allowed_install_scripts = table
+            .get("install_scripts")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("allowed"))
+            .and_then(toml_edit::Item::as_array)
+            .map(|values| ConfigValue {
+                value: values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(ToString::to_string))
+                    .collect(),
+                source: source.clone(),
+            });
+        let node_env = table
+            .get("runtime")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("node_env"))
+            .and_then(toml_edit::Item::as_str)
+            .map(|value| ConfigValue {
+                value: value.to_string(),
+                source: source.clone(),
+            });
+        let compile_cache = table
+            .get("actions")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("compile_cache"))
+            .and_then(toml_edit::Item::as_bool)
+            .map(|value| ConfigValue {
+                value,
+                source: source.clone(),
+            });
+        let compile_cache_modules = table
+            .get("compile_cache")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("modules"))
+            .and_then(toml_edit::Item::as_array)
+            .map(|values| ConfigValue {
+                value: values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(ToString::to_string))
+                    .collect(),
+                source: source.clone(),
+            });
+        let cache_node_modules = table
+            .get("actions")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("cache_node_modules"))
+            .and_then(toml_edit::Item::as_bool)
+            .map(|value| ConfigValue {
+                value,
+                source: source.clone(),
+            });
+        let cache_native_modules = table
+            .get("actions")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("cache_native_modules"))
+            .and_then(toml_edit::Item::as_bool)
+            .map(|value| ConfigValue {
+                value,
+                source: source.clone(),
+            });
+        let slim_runtime = table
+            .get("runtime")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("slim"))
+            .and_then(toml_edit::Item::as_bool)
+            .map(|value| ConfigValue {
+                value,
+                source: source.clone(),
+            });
+        let ca_file = table
+            .get("network")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("ca_file"))
+            .and_then(toml_edit::Item::as_str)
+            .map(|value| ConfigValue {
+                value: value.to_string(),
+                source: source.clone(),
+            });
+        let cache_reset = match table
+            .get("cache")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("reset"))
+        {
+            Some(item) if item.as_bool() == Some(true) => Some(CacheReset::All),
+            Some(item) => item
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_str())
+                        .map(|layer| {
+                            if RESETTABLE_LAYERS.contains(&layer) {
+                                Ok(layer.to_string())
+                            } else {
+                                Err(create_invalid_cache_reset_layer_error_message(
+                                    source, layer,
+                                ))
+                            }
+                        })
+                        .collect::<Result<_, _>>()
+                        .map(CacheReset::Layers)
+                })
+                .transpose()?,
+            None => None,
+        }
+        .map(|value| ConfigValue {
+            value,
+            source: source.clone(),
+        });
+        let committed_node_modules = table
+            .get("actions")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("committed_node_modules"))
+            .and_then(toml_edit::Item::as_str)
+            .map(|value| {
+                value
+                    .parse::<CommittedNodeModulesMode>()
+                    .map(|value| ConfigValue {
+                        value,
+                        source: source.clone(),
+                    })
+                    .map_err(|()| {
+                        create_invalid_committed_node_modules_mode_error_message(source, value)
+                    })
+            })
+            .transpose()?;
+        let strict_dependency_engines = table
+            .get("actions")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("strict_dependency_engines"))
+            .and_then(toml_edit::Item::as_bool)
+            .map(|value| ConfigValue {
+                value,
+                source: source.clone(),
+            });
+        let allow_major_upgrades = table
+            .get("runtime")
+            .and_then(|v| v.as_table_like())
+            .and_then(|v| v.get("allow_major_upgrades"))
+            .and_then(toml_edit::Item::as_bool)
+            .map(|value| ConfigValue {
+                value,
+                source: source.clone(),
+            });
         Ok(BuildpackConfig {
             build_scripts_enabled,
             prune_dev_dependencies,
+            install_scripts,
+            allowed_install_scripts,
+            node_env,
+            compile_cache,
+            compile_cache_modules,
+            cache_node_modules,
+            cache_native_modules,
+            slim_runtime,
+            ca_file,
+            cache_reset,
+            committed_node_modules,
+            strict_dependency_engines,
+            allow_major_upgrades,
             errors: Vec::new(),
         })
     }
 }
 
+fn create_invalid_install_scripts_mode_error_message(
+    source: &ConfigValueSource,
+    value: &str,
+) -> ErrorMessage {
+    let install_scripts = style::value("actions.install_scripts");
+    let value = style::value(value);
+    let all = style::value(InstallScriptsMode::All.to_string());
+    let allow_list = style::value(InstallScriptsMode::AllowList.to_string());
+    error_message()
+        .id("config/install_scripts/invalid")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header(format!("Invalid {install_scripts} configuration"))
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack read {value} for {install_scripts} from the {source} \
+            configuration but this is not a supported value.
+
+            Suggestions:
+            - Use {all} to run every dependency install script (default).
+            - Use {allow_list} to only run install scripts for allow-listed packages.
+        " })
+        .create()
+}
+
+fn create_invalid_committed_node_modules_mode_error_message(
+    source: &ConfigValueSource,
+    value: &str,
+) -> ErrorMessage {
+    let committed_node_modules = style::value("actions.committed_node_modules");
+    let value = style::value(value);
+    let reinstall = style::value(CommittedNodeModulesMode::Reinstall.to_string());
+    let rebuild = style::value(CommittedNodeModulesMode::Rebuild.to_string());
+    error_message()
+        .id("config/committed_node_modules/invalid")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header(format!("Invalid {committed_node_modules} configuration"))
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack read {value} for {committed_node_modules} from the {source} \
+            configuration but this is not a supported value.
+
+            Suggestions:
+            - Use {reinstall} to remove a committed node_modules and install dependencies (default).
+            - Use {rebuild} to keep a committed node_modules and rebuild its packages.
+        " })
+        .create()
+}
+
+fn create_invalid_cache_reset_layer_error_message(
+    source: &ConfigValueSource,
+    layer: &str,
+) -> ErrorMessage {
+    let cache_reset = style::value("cache.reset");
+    let layer = style::value(layer);
+    let resettable_layers = RESETTABLE_LAYERS
+        .iter()
+        .map(|layer| format!("  - {}", style::value(*layer)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let all = style::value("true");
+    error_message()
+        .id("config/cache_reset/invalid")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header(format!("Invalid {cache_reset} configuration"))
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack read {layer} for {cache_reset} from the {source} \
+            configuration but this is not a cached layer that can be reset.
+
+            Suggestions:
+            - Use {all} to clear every cached layer.
+            - List any of the following cached layers to clear them:
+            {resettable_layers}
+        " })
+        .create()
+}
+
 fn create_read_project_toml_error_message(error: &std::io::Error) -> ErrorMessage {
     let project_toml = style::value("project.toml");
     error_message()
@@ -441,6 +856,325 @@ mod tests {
         assert_error_snapshot(&error);
     }
 
+    #[test]
+    fn config_install_scripts_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.install_scripts = \"allow_list\"
+                install_scripts.allowed = [\"sharp\", \"esbuild\"]
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.install_scripts,
+            Some(ConfigValue {
+                value: InstallScriptsMode::AllowList,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+        assert_eq!(
+            config.allowed_install_scripts,
+            Some(ConfigValue {
+                value: vec!["sharp".to_string(), "esbuild".to_string()],
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_compile_cache_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.compile_cache = true
+                compile_cache.modules = [\"express\", \"./dist/routes.js\"]
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.compile_cache,
+            Some(ConfigValue {
+                value: true,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+        assert_eq!(
+            config.compile_cache_modules,
+            Some(ConfigValue {
+                value: vec!["express".to_string(), "./dist/routes.js".to_string()],
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_node_env_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                runtime.node_env = \"staging\"
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.node_env,
+            Some(ConfigValue {
+                value: "staging".to_string(),
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_cache_node_modules_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.cache_node_modules = true
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.cache_node_modules,
+            Some(ConfigValue {
+                value: true,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_cache_native_modules_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.cache_native_modules = true
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.cache_native_modules,
+            Some(ConfigValue {
+                value: true,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_committed_node_modules_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.committed_node_modules = \"rebuild\"
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.committed_node_modules,
+            Some(ConfigValue {
+                value: CommittedNodeModulesMode::Rebuild,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_when_committed_node_modules_mode_is_invalid() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.committed_node_modules = \"keep\"
+            " },
+        )
+        .unwrap();
+        let error = BuildpackConfig::try_from(&project_toml_path).unwrap_err();
+        assert_error_snapshot(&error);
+    }
+
+    #[test]
+    fn config_strict_dependency_engines_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.strict_dependency_engines = true
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.strict_dependency_engines,
+            Some(ConfigValue {
+                value: true,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_slim_runtime_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                runtime.slim = true
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.slim_runtime,
+            Some(ConfigValue {
+                value: true,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_allow_major_upgrades_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                runtime.allow_major_upgrades = true
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.allow_major_upgrades,
+            Some(ConfigValue {
+                value: true,
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_ca_file_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                network.ca_file = \"certs/internal-ca.pem\"
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.ca_file,
+            Some(ConfigValue {
+                value: "certs/internal-ca.pem".to_string(),
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+    }
+
+    #[test]
+    fn config_cache_reset_from_project_toml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                cache.reset = [\"yarn_cache\", \"dist\"]
+            " },
+        )
+        .unwrap();
+        let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+        assert_eq!(
+            config.cache_reset,
+            Some(ConfigValue {
+                value: CacheReset::Layers(["dist".to_string(), "yarn_cache".to_string()].into()),
+                source: ConfigValueSource::ProjectToml
+            })
+        );
+
+        for (value, expected) in [("true", Some(CacheReset::All)), ("false", None)] {
+            std::fs::write(
+                &project_toml_path,
+                formatdoc! { "
+                    [{NAMESPACED_CONFIG}]
+                    cache.reset = {value}
+                " },
+            )
+            .unwrap();
+            let config = BuildpackConfig::try_from(&project_toml_path).unwrap();
+            assert_eq!(config.cache_reset.map(|config| config.value), expected);
+        }
+    }
+
+    #[test]
+    fn config_when_cache_reset_layer_is_invalid() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                cache.reset = [\"node_modules\"]
+            " },
+        )
+        .unwrap();
+        let error = BuildpackConfig::try_from(&project_toml_path).unwrap_err();
+        assert_error_snapshot(&error);
+    }
+
+    #[test]
+    fn config_when_install_scripts_mode_is_invalid() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project_toml_path = temp_dir.path().join("project.toml");
+        std::fs::write(
+            &project_toml_path,
+            formatdoc! { "
+                [{NAMESPACED_CONFIG}]
+                actions.install_scripts = \"none\"
+            " },
+        )
+        .unwrap();
+        let error = BuildpackConfig::try_from(&project_toml_path).unwrap_err();
+        assert_error_snapshot(&error);
+    }
+
     #[test]
     fn config_when_project_toml_cannot_be_read() {
         let temp_dir = tempfile::TempDir::new().unwrap();
@@ -0,0 +1,432 @@
+use crate::NodeJsBuildpack;
+use crate::utils::fs::{directory_size, format_bytes};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use libcnb::layer::{EmptyLayerCause, LayerRef, LayerState, RestoredLayerAction};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const MEBIBYTE: u64 = 1024 * 1024;
+pub(crate) const DAY: Duration = Duration::from_hours(24);
+
+/// The limits a package manager cache layer is held to. A restored cache that is over any limit
+/// is cleared before the install, and a cache that is kept gets a targeted prune every
+/// `prune_interval` builds.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CachePolicy {
+    /// How the cache is referred to in the build log.
+    pub(crate) name: &'static str,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) max_uses: Option<u32>,
+    pub(crate) prune_interval: Option<u32>,
+}
+
+/// Usage of a cache layer, recorded in its metadata.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CacheUsage {
+    // Using floats here due to [an issue with lifecycle's handling of integers](https://github.com/buildpacks/lifecycle/issues/884)
+    created_at: f64,
+    use_count: f32,
+    /// The size measured at the end of the last build so a restored cache doesn't have to be
+    /// measured again. Missing for usage migrated from older metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<f64>,
+}
+
+impl CacheUsage {
+    fn new(now: SystemTime) -> Self {
+        Self {
+            created_at: unix_seconds(now),
+            use_count: 0.0,
+            size: None,
+        }
+    }
+
+    /// Usage for a cache layer from before usage was recorded. Its age is counted from now since
+    /// when it was created isn't known.
+    pub(crate) fn migrated(use_count: f32) -> Self {
+        Self {
+            use_count,
+            ..Self::new(SystemTime::now())
+        }
+    }
+
+    fn next(&self) -> Self {
+        Self {
+            use_count: self.use_count + 1.0,
+            ..self.clone()
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn use_count(&self) -> u32 {
+        self.use_count as u32
+    }
+
+    fn age(&self, now: SystemTime) -> Duration {
+        Duration::from_secs_f64((unix_seconds(now) - self.created_at).max(0.0))
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn size(&self) -> Option<u64> {
+        self.size.map(|size| size as u64)
+    }
+}
+
+/// A cache layer checked against its policy by [`CachePolicy::restored_layer_action`].
+pub(crate) type CacheLayerRef = LayerRef<NodeJsBuildpack, (), CacheRestore>;
+
+/// Why a restored cache was cleared.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Eviction {
+    Incompatible,
+    TooLarge { size: u64, max_size: u64 },
+    TooOld { max_age: Duration },
+    TooManyUses { max_uses: u32 },
+}
+
+impl fmt::Display for Eviction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Eviction::Incompatible => write!(f, "created by an incompatible build"),
+            Eviction::TooLarge { size, max_size } => write!(
+                f,
+                "{} is over the {} limit",
+                format_bytes(*size),
+                format_bytes(*max_size)
+            ),
+            Eviction::TooOld { max_age } => {
+                write!(f, "older than {} days", max_age.as_secs() / DAY.as_secs())
+            }
+            Eviction::TooManyUses { max_uses } => write!(f, "used by {max_uses} builds"),
+        }
+    }
+}
+
+/// The outcome of checking a restored cache layer against its policy.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CacheRestore {
+    Keep { usage: CacheUsage, size: u64 },
+    Evict(Eviction),
+}
+
+impl CachePolicy {
+    /// Decides whether a restored cache layer is kept. `is_compatible` is the layer's own check of
+    /// its metadata (layer version, target, etc.), the policy limits are only checked after it.
+    pub(crate) fn restored_layer_action(
+        &self,
+        is_compatible: bool,
+        usage: &CacheUsage,
+        layer_dir: &Path,
+    ) -> (RestoredLayerAction, CacheRestore) {
+        if !is_compatible {
+            return (
+                RestoredLayerAction::DeleteLayer,
+                CacheRestore::Evict(Eviction::Incompatible),
+            );
+        }
+        // an unreadable cache is kept and left to the package manager to sort out
+        let size = usage
+            .size()
+            .unwrap_or_else(|| directory_size(layer_dir).unwrap_or_default());
+        match self.check(usage, size, SystemTime::now()) {
+            Ok(()) => (
+                RestoredLayerAction::KeepLayer,
+                CacheRestore::Keep {
+                    usage: usage.clone(),
+                    size,
+                },
+            ),
+            Err(eviction) => (
+                RestoredLayerAction::DeleteLayer,
+                CacheRestore::Evict(eviction),
+            ),
+        }
+    }
+
+    fn check(&self, usage: &CacheUsage, size: u64, now: SystemTime) -> Result<(), Eviction> {
+        if let Some(max_uses) = self.max_uses
+            && usage.use_count() >= max_uses
+        {
+            return Err(Eviction::TooManyUses { max_uses });
+        }
+        if let Some(max_age) = self.max_age
+            && usage.age(now) > max_age
+        {
+            return Err(Eviction::TooOld { max_age });
+        }
+        if let Some(max_size) = self.max_size
+            && size > max_size
+        {
+            return Err(Eviction::TooLarge { size, max_size });
+        }
+        Ok(())
+    }
+
+    /// Starts tracking this build's use of a cache layer, logging why a restored cache was cleared.
+    pub(crate) fn track<MAC>(
+        &self,
+        state: &LayerState<MAC, CacheRestore>,
+        layer_dir: &Path,
+    ) -> TrackedCache {
+        let restored = match state {
+            LayerState::Restored {
+                cause: CacheRestore::Keep { usage, size },
+            } => Some((usage.next(), *size)),
+            LayerState::Empty {
+                cause:
+                    EmptyLayerCause::RestoredLayerAction {
+                        cause: CacheRestore::Evict(eviction),
+                    },
+            } => {
+                print::sub_bullet(format!("Clearing {} ({eviction})", self.name));
+                None
+            }
+            _ => None,
+        };
+        let (usage, size_before, is_restored) = match restored {
+            Some((usage, size)) => (usage, size, true),
+            None => (CacheUsage::new(SystemTime::now()).next(), 0, false),
+        };
+        TrackedCache {
+            policy: *self,
+            layer_dir: layer_dir.to_path_buf(),
+            usage,
+            size_before,
+            is_restored,
+        }
+    }
+}
+
+/// A cache layer in use by this build.
+pub(crate) struct TrackedCache {
+    policy: CachePolicy,
+    layer_dir: PathBuf,
+    usage: CacheUsage,
+    size_before: u64,
+    is_restored: bool,
+}
+
+impl TrackedCache {
+    /// The usage to record in the layer metadata, including this build and the size recorded by
+    /// [`TrackedCache::record_size`].
+    pub(crate) fn usage(&self) -> CacheUsage {
+        self.usage.clone()
+    }
+
+    /// A restored cache is pruned on every `prune_interval`th reuse.
+    pub(crate) fn is_prune_due(&self) -> bool {
+        self.is_restored
+            && self
+                .policy
+                .prune_interval
+                .is_some_and(|interval| (self.usage.use_count() - 1).is_multiple_of(interval))
+    }
+
+    /// Measures the cache after the install and logs it against the size it was restored with.
+    /// The size is kept in the usage so the next build can check it without measuring again.
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn record_size(&mut self) {
+        let size_after = directory_size(&self.layer_dir).unwrap_or_default();
+        print::sub_bullet(format!(
+            "{} size: {} → {}",
+            self.policy.name,
+            style::value(format_bytes(self.size_before)),
+            style::value(format_bytes(size_after))
+        ));
+        self.usage.size = Some(size_after as f64);
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: CachePolicy = CachePolicy {
+        name: "test cache",
+        max_size: Some(10 * MEBIBYTE),
+        max_age: Some(DAY.saturating_mul(30)),
+        max_uses: Some(3),
+        prune_interval: Some(2),
+    };
+
+    fn usage(use_count: f32, now: SystemTime, age: Duration) -> CacheUsage {
+        CacheUsage {
+            created_at: unix_seconds(now - age),
+            use_count,
+            size: None,
+        }
+    }
+
+    #[test]
+    fn check_within_limits() {
+        let now = SystemTime::now();
+        assert_eq!(POLICY.check(&usage(2.0, now, DAY), MEBIBYTE, now), Ok(()));
+    }
+
+    #[test]
+    fn check_evicts_over_any_limit() {
+        let now = SystemTime::now();
+        assert_eq!(
+            POLICY.check(&usage(3.0, now, DAY), MEBIBYTE, now),
+            Err(Eviction::TooManyUses { max_uses: 3 })
+        );
+        assert_eq!(
+            POLICY.check(&usage(1.0, now, DAY * 31), MEBIBYTE, now),
+            Err(Eviction::TooOld { max_age: DAY * 30 })
+        );
+        assert_eq!(
+            POLICY.check(&usage(1.0, now, DAY), 11 * MEBIBYTE, now),
+            Err(Eviction::TooLarge {
+                size: 11 * MEBIBYTE,
+                max_size: 10 * MEBIBYTE
+            })
+        );
+    }
+
+    #[test]
+    fn eviction_display() {
+        assert_eq!(
+            Eviction::TooLarge {
+                size: 11 * MEBIBYTE,
+                max_size: 10 * MEBIBYTE
+            }
+            .to_string(),
+            "11.0 MiB is over the 10.0 MiB limit"
+        );
+        assert_eq!(
+            Eviction::TooOld { max_age: DAY * 30 }.to_string(),
+            "older than 30 days"
+        );
+        assert_eq!(
+            Eviction::TooManyUses { max_uses: 150 }.to_string(),
+            "used by 150 builds"
+        );
+    }
+
+    #[test]
+    fn restored_layer_action_checks_compatibility_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = usage(0.0, SystemTime::now(), DAY);
+        assert!(matches!(
+            POLICY.restored_layer_action(false, &usage, dir.path()),
+            (
+                RestoredLayerAction::DeleteLayer,
+                CacheRestore::Evict(Eviction::Incompatible)
+            )
+        ));
+        assert!(matches!(
+            POLICY.restored_layer_action(true, &usage, dir.path()),
+            (
+                RestoredLayerAction::KeepLayer,
+                CacheRestore::Keep { size: 0, .. }
+            )
+        ));
+    }
+
+    #[test]
+    fn restored_layer_action_uses_recorded_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("archive.tgz"), "contents").unwrap();
+        let mut usage = usage(0.0, SystemTime::now(), DAY);
+
+        assert!(matches!(
+            POLICY.restored_layer_action(true, &usage, dir.path()),
+            (
+                RestoredLayerAction::KeepLayer,
+                CacheRestore::Keep { size: 8, .. }
+            )
+        ));
+
+        usage.size = Some(11.0 * 1024.0 * 1024.0);
+        assert!(matches!(
+            POLICY.restored_layer_action(true, &usage, dir.path()),
+            (
+                RestoredLayerAction::DeleteLayer,
+                CacheRestore::Evict(Eviction::TooLarge { .. })
+            )
+        ));
+    }
+
+    #[test]
+    fn record_size_measures_cache_after_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tracked = POLICY.track(
+            &LayerState::<(), _>::Empty {
+                cause: EmptyLayerCause::NewlyCreated,
+            },
+            dir.path(),
+        );
+        std::fs::write(dir.path().join("archive.tgz"), "contents").unwrap();
+        bullet_stream::global::with_locked_writer(Vec::<u8>::new(), || tracked.record_size());
+        assert_eq!(tracked.usage().size(), Some(8));
+    }
+
+    #[test]
+    fn prune_is_due_on_every_interval_of_reuse() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let prune_due = |use_count: f32| {
+            POLICY
+                .track(
+                    &LayerState::<(), _>::Restored {
+                        cause: CacheRestore::Keep {
+                            usage: usage(use_count, now, DAY),
+                            size: 0,
+                        },
+                    },
+                    dir.path(),
+                )
+                .is_prune_due()
+        };
+        assert!(!prune_due(1.0));
+        assert!(prune_due(2.0));
+        assert!(!prune_due(3.0));
+        assert!(prune_due(4.0));
+
+        let new_cache = POLICY.track(
+            &LayerState::<(), _>::Empty {
+                cause: EmptyLayerCause::NewlyCreated,
+            },
+            dir.path(),
+        );
+        assert!(!new_cache.is_prune_due());
+        assert_eq!(new_cache.usage().use_count(), 1);
+    }
+
+    #[test]
+    fn migrated_usage_keeps_prune_schedule() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = CacheUsage::migrated(4.0);
+        assert_eq!(usage.size(), None);
+        let tracked = POLICY.track(
+            &LayerState::<(), _>::Restored {
+                cause: CacheRestore::Keep { usage, size: 0 },
+            },
+            dir.path(),
+        );
+        assert_eq!(tracked.usage().use_count(), 5);
+        assert!(tracked.is_prune_due());
+    }
+
+    #[test]
+    fn usage_metadata_roundtrip() {
+        let mut usage = usage(4.0, SystemTime::now(), DAY);
+        let toml = toml::to_string(&usage).unwrap();
+        assert_eq!(toml::from_str::<CacheUsage>(&toml).unwrap(), usage);
+
+        usage.size = Some(1024.0);
+        let toml = toml::to_string(&usage).unwrap();
+        assert_eq!(toml::from_str::<CacheUsage>(&toml).unwrap(), usage);
+    }
+}
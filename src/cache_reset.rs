@@ -0,0 +1,254 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::o11y::*;
+use crate::utils::error_handling::ErrorType::Internal;
+use crate::utils::error_handling::{ErrorMessage, error_message};
+use crate::{BuildpackBuildContext, BuildpackResult};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::formatdoc;
+use libcnb::Env;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The cached layers that can be cleared with `cache.reset`, in the order they are cleared.
+pub(crate) const RESETTABLE_LAYERS: [&str; 15] = [
+    "dist",
+    "npm_packument",
+    "yarn_packument",
+    "pnpm_packument",
+    "npm_engine",
+    "yarn",
+    "pnpm",
+    "npm_cache",
+    "yarn_cache",
+    "addressable",
+    "node_modules_cache",
+    "native_modules",
+    "puppeteer_cache",
+    "playwright_browsers",
+    "cypress_cache",
+];
+
+/// Setting `NODE_MODULES_CACHE=false` clears every cached layer, as it did with the classic buildpack.
+const NODE_MODULES_CACHE: &str = "NODE_MODULES_CACHE";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CacheReset {
+    All,
+    Layers(BTreeSet<String>),
+}
+
+impl CacheReset {
+    fn layers(&self) -> impl Iterator<Item = &'static str> {
+        RESETTABLE_LAYERS
+            .into_iter()
+            .filter(move |layer| match self {
+                CacheReset::All => true,
+                CacheReset::Layers(layers) => layers.contains(*layer),
+            })
+            // the build-only files split out of a slim runtime belong to the distribution
+            .flat_map(|layer| match layer {
+                "dist" => vec!["dist", "dist_build"],
+                _ => vec![layer],
+            })
+    }
+}
+
+impl fmt::Display for CacheReset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheReset::All => write!(f, "all"),
+            CacheReset::Layers(layers) => {
+                write!(
+                    f,
+                    "{}",
+                    layers.iter().cloned().collect::<Vec<_>>().join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Clears the requested cached layers before any of them are restored, so each one is rebuilt
+/// from scratch during this build.
+pub(crate) fn reset_cached_layers(
+    context: &BuildpackBuildContext,
+    env: &Env,
+    buildpack_config: &BuildpackConfig,
+) -> BuildpackResult<()> {
+    let Some((cache_reset, reason)) = requested_cache_reset(env, buildpack_config) else {
+        return Ok(());
+    };
+
+    print::bullet("Resetting cached layers");
+    let mut cleared = vec![];
+    for layer in cache_reset.layers() {
+        if clear_layer(&context.layers_dir, layer)
+            .map_err(|e| create_clear_layer_error(layer, &e))?
+        {
+            print::sub_bullet(format!("Cleared {} ({reason})", style::value(layer)));
+            cleared.push(layer);
+        }
+    }
+    if cleared.is_empty() {
+        print::sub_bullet(format!("No cached layers to clear ({reason})"));
+    }
+
+    tracing::info!(
+        { CACHE_RESET_REQUESTED } = cache_reset.to_string(),
+        { CACHE_RESET_REASON } = reason,
+        { CACHE_RESET_CLEARED_LAYERS } = cleared.join(", "),
+        "cache_reset"
+    );
+
+    Ok(())
+}
+
+fn requested_cache_reset(
+    env: &Env,
+    buildpack_config: &BuildpackConfig,
+) -> Option<(CacheReset, String)> {
+    if env
+        .get(NODE_MODULES_CACHE)
+        .is_some_and(|value| value.eq_ignore_ascii_case("false"))
+    {
+        return Some((
+            CacheReset::All,
+            format!("requested by {NODE_MODULES_CACHE}=false"),
+        ));
+    }
+    buildpack_config
+        .cache_reset
+        .as_ref()
+        .map(|ConfigValue { value, source }| {
+            (
+                value.clone(),
+                format!("requested by cache.reset in {source}"),
+            )
+        })
+}
+
+/// Removes a layer's contents and metadata. Returns `false` if the layer wasn't restored.
+fn clear_layer(layers_dir: &Path, layer: &str) -> io::Result<bool> {
+    let mut cleared = false;
+    match fs::remove_file(layers_dir.join(format!("{layer}.toml"))) {
+        Ok(()) => cleared = true,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    match fs::remove_dir_all(layers_dir.join(layer)) {
+        Ok(()) => cleared = true,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    Ok(cleared)
+}
+
+fn create_clear_layer_error(layer: &str, error: &io::Error) -> ErrorMessage {
+    let layer = style::value(layer);
+    error_message()
+        .id("cache_reset/clear_layer")
+        .error_type(Internal)
+        .header("Failed to reset cached layer")
+        .body(formatdoc! { "
+            An unexpected I/O error occurred while clearing the cached layer {layer} for a \
+            requested cache reset.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildpack_config::ConfigValueSource;
+    use crate::utils::error_handling::test_util::assert_error_snapshot;
+
+    #[test]
+    fn cache_reset_layers() {
+        assert_eq!(
+            CacheReset::All.layers().collect::<Vec<_>>(),
+            [
+                "dist",
+                "dist_build",
+                "npm_packument",
+                "yarn_packument",
+                "pnpm_packument",
+                "npm_engine",
+                "yarn",
+                "pnpm",
+                "npm_cache",
+                "yarn_cache",
+                "addressable",
+                "node_modules_cache",
+                "native_modules",
+                "puppeteer_cache",
+                "playwright_browsers",
+                "cypress_cache"
+            ]
+        );
+        assert_eq!(
+            CacheReset::Layers(BTreeSet::from([
+                "yarn_cache".to_string(),
+                "dist".to_string()
+            ]))
+            .layers()
+            .collect::<Vec<_>>(),
+            ["dist", "dist_build", "yarn_cache"]
+        );
+    }
+
+    #[test]
+    fn node_modules_cache_env_var_resets_everything() {
+        let mut env = Env::new();
+        let mut buildpack_config = BuildpackConfig::default();
+        buildpack_config.cache_reset = Some(ConfigValue {
+            value: CacheReset::Layers(BTreeSet::from(["yarn_cache".to_string()])),
+            source: ConfigValueSource::ProjectToml,
+        });
+        assert_eq!(
+            requested_cache_reset(&env, &buildpack_config),
+            Some((
+                CacheReset::Layers(BTreeSet::from(["yarn_cache".to_string()])),
+                "requested by cache.reset in project.toml".to_string()
+            ))
+        );
+
+        env.insert(NODE_MODULES_CACHE, "false");
+        assert_eq!(
+            requested_cache_reset(&env, &buildpack_config),
+            Some((
+                CacheReset::All,
+                "requested by NODE_MODULES_CACHE=false".to_string()
+            ))
+        );
+
+        env.insert(NODE_MODULES_CACHE, "true");
+        buildpack_config.cache_reset = None;
+        assert_eq!(requested_cache_reset(&env, &buildpack_config), None);
+    }
+
+    #[test]
+    fn clear_layer_removes_contents_and_metadata() {
+        let layers_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(layers_dir.path().join("yarn_cache/v6")).unwrap();
+        fs::write(layers_dir.path().join("yarn_cache/v6/archive.zip"), "").unwrap();
+        fs::write(layers_dir.path().join("yarn_cache.toml"), "[metadata]").unwrap();
+
+        assert!(clear_layer(layers_dir.path(), "yarn_cache").unwrap());
+        assert!(!layers_dir.path().join("yarn_cache").exists());
+        assert!(!layers_dir.path().join("yarn_cache.toml").exists());
+        assert!(!clear_layer(layers_dir.path(), "yarn_cache").unwrap());
+    }
+
+    #[test]
+    fn clear_layer_error() {
+        assert_error_snapshot(&create_clear_layer_error(
+            "yarn_cache",
+            &io::Error::other("Permission denied"),
+        ));
+    }
+}
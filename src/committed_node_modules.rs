@@ -0,0 +1,287 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::install_scripts::{InstallScriptsPolicy, run_deferred_install_scripts};
+use crate::o11y::*;
+use crate::package_manager::InstalledPackageManager;
+use crate::package_managers::{npm, pnpm, yarn};
+use crate::utils::error_handling::ErrorType::Internal;
+use crate::utils::error_handling::{
+    ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
+};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::formatdoc;
+use libcnb::Env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Controls how a `node_modules` directory that was committed with the app source is handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CommittedNodeModulesMode {
+    /// The directory is removed and dependencies are installed from the lockfile (default).
+    Reinstall,
+    /// The installation is skipped and the committed packages are rebuilt for the installed Node.js.
+    Rebuild,
+}
+
+impl FromStr for CommittedNodeModulesMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reinstall" => Ok(CommittedNodeModulesMode::Reinstall),
+            "rebuild" => Ok(CommittedNodeModulesMode::Rebuild),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for CommittedNodeModulesMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommittedNodeModulesMode::Reinstall => write!(f, "reinstall"),
+            CommittedNodeModulesMode::Rebuild => write!(f, "rebuild"),
+        }
+    }
+}
+
+/// Checks the app source for a populated `node_modules` directory before dependencies are
+/// installed. Returns how it should be handled, or `None` when there isn't one.
+pub(crate) fn detect_committed_node_modules(
+    app_dir: &Path,
+    buildpack_config: &BuildpackConfig,
+) -> Option<CommittedNodeModulesMode> {
+    if !has_committed_node_modules(app_dir) {
+        return None;
+    }
+
+    let mode = match &buildpack_config.committed_node_modules {
+        Some(ConfigValue { value, .. }) => *value,
+        None => CommittedNodeModulesMode::Reinstall,
+    };
+
+    tracing::info!(
+        { COMMITTED_NODE_MODULES_DETECTED } = true,
+        { COMMITTED_NODE_MODULES_MODE } = mode.to_string(),
+        "committed_node_modules"
+    );
+
+    let node_modules = file_value("node_modules");
+    let committed_node_modules = style::value("actions.committed_node_modules");
+    let project_toml = file_value("project.toml");
+    let explanation = formatdoc! { "
+        Warning: A {node_modules} directory was found in the app source.
+
+        Dependencies should be installed during the build instead of being committed. Packages in \
+        a committed {node_modules} were installed for another platform or Node.js version, and any \
+        native modules they contain may fail to load at runtime.
+    " };
+    print::warning(match mode {
+        CommittedNodeModulesMode::Reinstall => formatdoc! { "
+            {explanation}
+            The committed {node_modules} will be removed and dependencies installed from the \
+            lockfile. To keep the committed packages and only rebuild them for this Node.js \
+            version, set {committed_node_modules} to {rebuild} in {project_toml}. To avoid this \
+            warning, add {node_modules} to your {gitignore} file and remove it from the repository.
+        ", rebuild = style::value("rebuild"), gitignore = file_value(".gitignore") },
+        CommittedNodeModulesMode::Rebuild => formatdoc! { "
+            {explanation}
+            Dependency installation will be skipped and the committed packages rebuilt for this \
+            Node.js version, as configured by {committed_node_modules} in {project_toml}.
+        " },
+    });
+
+    Some(mode)
+}
+
+fn has_committed_node_modules(app_dir: &Path) -> bool {
+    fs::read_dir(app_dir.join("node_modules")).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+pub(crate) fn remove_committed_node_modules(app_dir: &Path) -> Result<(), ErrorMessage> {
+    print::bullet("Removing committed node_modules");
+    fs::remove_dir_all(app_dir.join("node_modules"))
+        .map_err(|e| create_remove_committed_node_modules_error(&e))
+}
+
+/// Runs the install scripts of the committed packages so native modules are compiled for the
+/// installed Node.js version. When `actions.install_scripts = "allow_list"` is configured only the
+/// allow-listed packages are rebuilt.
+pub(crate) fn rebuild_committed_node_modules(
+    app_dir: &Path,
+    env: &Env,
+    package_manager: &InstalledPackageManager,
+    install_scripts: &InstallScriptsPolicy,
+) -> Result<(), ErrorMessage> {
+    print::bullet("Rebuilding committed node_modules");
+    rebuild_packages(app_dir, install_scripts, |packages| match package_manager {
+        InstalledPackageManager::Npm(_) => npm::rebuild_command(env, packages),
+        InstalledPackageManager::Yarn(version) => yarn::rebuild_command(env, version, packages),
+        InstalledPackageManager::Pnpm(_) => pnpm::rebuild_command(env, packages),
+    })
+}
+
+fn rebuild_packages(
+    app_dir: &Path,
+    install_scripts: &InstallScriptsPolicy,
+    rebuild_command: impl FnOnce(&[String]) -> Command,
+) -> Result<(), ErrorMessage> {
+    match install_scripts {
+        InstallScriptsPolicy::RunAll => {
+            print::sub_stream_cmd(rebuild_command(&[]))
+                .map_err(|e| create_rebuild_committed_node_modules_error(&e))?;
+            Ok(())
+        }
+        InstallScriptsPolicy::AllowList(_) => run_deferred_install_scripts(
+            install_scripts,
+            None,
+            &[
+                app_dir.join("node_modules"),
+                app_dir.join(".yarn/unplugged"),
+            ],
+            rebuild_command,
+        ),
+    }
+}
+
+fn create_remove_committed_node_modules_error(error: &io::Error) -> ErrorMessage {
+    let node_modules = file_value("node_modules");
+    error_message()
+        .id("committed_node_modules/remove")
+        .error_type(Internal)
+        .header("Failed to remove committed node_modules")
+        .body(formatdoc! { "
+            An unexpected I/O error occurred while removing the {node_modules} directory found in \
+            the app source before installing dependencies.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+fn create_rebuild_committed_node_modules_error(error: &fun_run::CmdError) -> ErrorMessage {
+    let rebuild_command = style::command(error.name());
+    let node_modules = file_value("node_modules");
+    let committed_node_modules = style::value("actions.committed_node_modules");
+    let project_toml = file_value("project.toml");
+    error_message()
+        .id("committed_node_modules/rebuild")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::Yes,
+            SuggestSubmitIssue::No,
+        ))
+        .header("Failed to rebuild committed node_modules")
+        .body(formatdoc! { "
+            The buildpack ran {rebuild_command} to rebuild the packages in the committed \
+            {node_modules} directory for this Node.js version. This command failed and the \
+            buildpack cannot continue. See the log output above for more information.
+
+            Suggestions:
+            - Remove {node_modules} from the repository and add it to your {gitignore} file so \
+            dependencies are installed from the lockfile.
+            - Remove {committed_node_modules} from {project_toml} so the committed {node_modules} \
+            is replaced by a clean install.
+        ", gitignore = file_value(".gitignore") })
+        .debug_info(error.to_string())
+        .create()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildpack_config::ConfigValueSource;
+    use crate::utils::error_handling::test_util::{assert_error_snapshot, create_cmd_error};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn parse_committed_node_modules_mode() {
+        assert_eq!("reinstall".parse(), Ok(CommittedNodeModulesMode::Reinstall));
+        assert_eq!("rebuild".parse(), Ok(CommittedNodeModulesMode::Rebuild));
+        assert_eq!("keep".parse::<CommittedNodeModulesMode>(), Err(()));
+    }
+
+    #[test]
+    fn detect_committed_node_modules_defaults_to_reinstall() {
+        let app_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(app_dir.path().join("node_modules/express")).unwrap();
+        assert_eq!(
+            detect_committed_node_modules(app_dir.path(), &BuildpackConfig::default()),
+            Some(CommittedNodeModulesMode::Reinstall)
+        );
+
+        let mut buildpack_config = BuildpackConfig::default();
+        buildpack_config.committed_node_modules = Some(ConfigValue {
+            value: CommittedNodeModulesMode::Rebuild,
+            source: ConfigValueSource::ProjectToml,
+        });
+        assert_eq!(
+            detect_committed_node_modules(app_dir.path(), &buildpack_config),
+            Some(CommittedNodeModulesMode::Rebuild)
+        );
+    }
+
+    #[test]
+    fn detect_committed_node_modules_ignores_missing_or_empty_directory() {
+        let app_dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            detect_committed_node_modules(app_dir.path(), &BuildpackConfig::default()),
+            None
+        );
+        fs::create_dir(app_dir.path().join("node_modules")).unwrap();
+        assert_eq!(
+            detect_committed_node_modules(app_dir.path(), &BuildpackConfig::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn rebuild_committed_node_modules_only_rebuilds_allow_listed_packages() {
+        let app_dir = tempfile::tempdir().unwrap();
+        for name in ["esbuild", "malicious"] {
+            let package_dir = app_dir.path().join("node_modules").join(name);
+            fs::create_dir_all(&package_dir).unwrap();
+            fs::write(
+                package_dir.join("package.json"),
+                serde_json::json!({ "name": name, "scripts": { "postinstall": "node install.js" } })
+                    .to_string(),
+            )
+            .unwrap();
+        }
+
+        let mut rebuilt_packages = None;
+        rebuild_packages(
+            app_dir.path(),
+            &InstallScriptsPolicy::AllowList(BTreeSet::from(["esbuild".to_string()])),
+            |packages| {
+                rebuilt_packages = Some(packages.to_vec());
+                Command::new("true")
+            },
+        )
+        .unwrap();
+        assert_eq!(rebuilt_packages, Some(vec!["esbuild".to_string()]));
+
+        let mut rebuilt_packages = None;
+        rebuild_packages(app_dir.path(), &InstallScriptsPolicy::RunAll, |packages| {
+            rebuilt_packages = Some(packages.to_vec());
+            Command::new("true")
+        })
+        .unwrap();
+        assert_eq!(rebuilt_packages, Some(vec![]));
+    }
+
+    #[test]
+    fn remove_committed_node_modules_error() {
+        assert_error_snapshot(&create_remove_committed_node_modules_error(
+            &io::Error::other("Test I/O error"),
+        ));
+    }
+
+    #[test]
+    fn rebuild_committed_node_modules_error() {
+        assert_error_snapshot(&create_rebuild_committed_node_modules_error(
+            &create_cmd_error("npm rebuild"),
+        ));
+    }
+}
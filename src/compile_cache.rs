@@ -0,0 +1,275 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::runtimes::nodejs::get_node_version;
+use crate::utils::error_handling::ErrorType::Internal;
+use crate::utils::error_handling::{ErrorMessage, error_message};
+use crate::{BuildpackBuildContext, BuildpackResult};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::{formatdoc, indoc};
+use libcnb::Env;
+use libcnb::data::layer_name;
+use libcnb::layer::UncachedLayerDefinition;
+use libcnb::layer_env::{LayerEnv, ModificationBehavior, Scope};
+use nodejs_data::VersionRange;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use tracing::instrument;
+
+/// `module.enableCompileCache()` was added in Node.js 22.1.0.
+const MINIMUM_NODE_VERSION: &str = ">=22.1.0";
+
+/// Loads each listed module with the compile cache enabled so the V8 code cache for every module
+/// evaluated while loading it is written to the cache directory. Modules are resolved from the app
+/// directory and imported (which handles both CJS and ESM). The app entrypoint is never loaded
+/// since evaluating it at build time would run its top-level side effects like starting a server
+/// or connecting to a database, so only modules the user lists as free of side effects are loaded.
+const WARM_UP_SCRIPT: &str = indoc! { "
+    const { createRequire, enableCompileCache, flushCompileCache } = require('node:module');
+    const { join } = require('node:path');
+    const { pathToFileURL } = require('node:url');
+    const [cacheDir, appDir, ...specifiers] = process.argv.slice(1);
+    const exit = (code) => {
+        flushCompileCache?.();
+        process.exit(code);
+    };
+    enableCompileCache(cacheDir);
+    setTimeout(() => {
+        console.error('Timed out after 30 seconds loading modules');
+        exit(1);
+    }, 30_000);
+    const appRequire = createRequire(join(appDir, 'package.json'));
+    (async () => {
+        for (const specifier of specifiers) {
+            await import(pathToFileURL(appRequire.resolve(specifier)).href);
+        }
+        exit(0);
+    })().catch((error) => {
+        console.error(error);
+        exit(1);
+    });
+" };
+
+#[instrument(skip_all)]
+pub(crate) fn warm_compile_cache(
+    context: &BuildpackBuildContext,
+    env: &Env,
+    buildpack_config: &BuildpackConfig,
+) -> BuildpackResult<()> {
+    if !matches!(
+        buildpack_config.compile_cache,
+        Some(ConfigValue { value: true, .. })
+    ) {
+        return Ok(());
+    }
+
+    print::bullet("Warming V8 compile cache");
+
+    let node_version = get_node_version(env)?;
+    let minimum_node_version =
+        VersionRange::parse(MINIMUM_NODE_VERSION).expect("Version range should be valid");
+    if !minimum_node_version.satisfies(&node_version) {
+        print::sub_bullet(format!(
+            "Skipping as Node.js {} does not support the compile cache (requires {})",
+            style::value(node_version.to_string()),
+            style::value(MINIMUM_NODE_VERSION)
+        ));
+        return Ok(());
+    }
+
+    let modules = match &buildpack_config.compile_cache_modules {
+        Some(ConfigValue { value, .. }) if !value.is_empty() => value,
+        _ => {
+            print::sub_bullet(format!(
+                "Skipping as no modules are listed in {}",
+                style::value("compile_cache.modules")
+            ));
+            return Ok(());
+        }
+    };
+
+    let compile_cache_layer = context.uncached_layer(
+        layer_name!("compile_cache"),
+        UncachedLayerDefinition {
+            build: false,
+            launch: true,
+        },
+    )?;
+
+    let node_env = match &buildpack_config.node_env {
+        Some(ConfigValue { value, .. }) => value.as_str(),
+        None => "production",
+    };
+
+    // warming the cache is an optimization so failures to load the modules are not fatal but the
+    // layer is removed so a partially written cache isn't exported
+    if let Err(error) = print::sub_stream_cmd(warm_up_command(
+        &context.app_dir,
+        &compile_cache_layer.path(),
+        modules,
+        env,
+        node_env,
+    )) {
+        print::warning(create_warm_up_failed_warning(&error));
+        remove_layer(&compile_cache_layer.path())
+            .map_err(|e| create_remove_compile_cache_layer_error(&e))?;
+        return Ok(());
+    }
+
+    compile_cache_layer.write_env(LayerEnv::new().chainable_insert(
+        Scope::Launch,
+        ModificationBehavior::Default,
+        "NODE_COMPILE_CACHE",
+        compile_cache_layer.path(),
+    ))?;
+
+    Ok(())
+}
+
+/// Modules are loaded with the same `NODE_ENV` the app defaults to at launch so code that branches
+/// on it compiles the same paths.
+fn warm_up_command(
+    app_dir: &Path,
+    cache_dir: &Path,
+    modules: &[String],
+    env: &Env,
+    node_env: &str,
+) -> Command {
+    let mut command = Command::new("node");
+    command
+        .args(["--eval", WARM_UP_SCRIPT])
+        .arg(cache_dir)
+        .arg(app_dir)
+        .args(modules)
+        .current_dir(app_dir)
+        .envs(env);
+    if env.get("NODE_ENV").is_none() {
+        command.env("NODE_ENV", node_env);
+    }
+    command
+}
+
+/// Removes the layer's contents and metadata.
+fn remove_layer(layer_dir: &Path) -> io::Result<()> {
+    fs::remove_dir_all(layer_dir)?;
+    match fs::remove_file(layer_dir.with_extension("toml")) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn create_remove_compile_cache_layer_error(error: &io::Error) -> ErrorMessage {
+    let layer = style::value("compile_cache");
+    error_message()
+        .id("compile_cache/remove_layer")
+        .error_type(Internal)
+        .header("Failed to remove compile cache")
+        .body(formatdoc! { "
+            An unexpected I/O error occurred while removing the layer {layer} after the compile \
+            cache could not be warmed.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+fn create_warm_up_failed_warning(error: &fun_run::CmdError) -> String {
+    let command = style::command(error.name());
+    let compile_cache_modules = style::value("compile_cache.modules");
+    formatdoc! { "
+        The compile cache could not be warmed because {command} failed. The application will still \
+        start but modules will be compiled on boot. See the log output above for more information.
+
+        Confirm that every module in {compile_cache_modules} in project.toml can be resolved from \
+        the app directory and loaded at build time.
+    " }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::error_handling::test_util::assert_error_snapshot;
+
+    #[test]
+    fn warm_up_command_loads_listed_modules() {
+        let command = warm_up_command(
+            Path::new("/workspace"),
+            Path::new("/layers/compile_cache"),
+            &["express".to_string(), "./lib/routes.js".to_string()],
+            &Env::new(),
+            "production",
+        );
+        assert_eq!(
+            command.get_args().skip(2).collect::<Vec<_>>(),
+            [
+                "/layers/compile_cache",
+                "/workspace",
+                "express",
+                "./lib/routes.js"
+            ]
+        );
+        assert!(
+            command
+                .get_envs()
+                .any(|(name, value)| name == "NODE_ENV" && value == Some("production".as_ref()))
+        );
+    }
+
+    #[test]
+    fn warm_up_command_keeps_user_node_env() {
+        let mut env = Env::new();
+        env.insert("NODE_ENV", "staging");
+        let command = warm_up_command(
+            Path::new("/workspace"),
+            Path::new("/layers/compile_cache"),
+            &["express".to_string()],
+            &env,
+            "production",
+        );
+        assert!(
+            command
+                .get_envs()
+                .any(|(name, value)| name == "NODE_ENV" && value == Some("staging".as_ref()))
+        );
+    }
+
+    #[test]
+    fn warm_up_command_uses_launch_node_env() {
+        let command = warm_up_command(
+            Path::new("/workspace"),
+            Path::new("/layers/compile_cache"),
+            &["express".to_string()],
+            &Env::new(),
+            "staging",
+        );
+        assert!(
+            command
+                .get_envs()
+                .any(|(name, value)| name == "NODE_ENV" && value == Some("staging".as_ref()))
+        );
+    }
+
+    #[test]
+    fn remove_layer_removes_contents_and_metadata() {
+        let layers_dir = tempfile::tempdir().unwrap();
+        let compile_cache_dir = layers_dir.path().join("compile_cache");
+        fs::create_dir_all(compile_cache_dir.join("v22.14.0-x64-5d5ee2b0-0")).unwrap();
+        fs::write(
+            compile_cache_dir.join("v22.14.0-x64-5d5ee2b0-0/partial"),
+            "",
+        )
+        .unwrap();
+        fs::write(layers_dir.path().join("compile_cache.toml"), "[types]").unwrap();
+
+        remove_layer(&compile_cache_dir).unwrap();
+        assert!(!compile_cache_dir.exists());
+        assert!(!layers_dir.path().join("compile_cache.toml").exists());
+    }
+
+    #[test]
+    fn remove_compile_cache_layer_error() {
+        assert_error_snapshot(&create_remove_compile_cache_layer_error(&io::Error::other(
+            "Permission denied",
+        )));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::install_scripts::is_installed_package_dir;
+use crate::o11y::*;
+use crate::runtimes::nodejs;
+use crate::utils::error_handling::{
+    ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
+};
+use crate::{BuildpackBuildContext, BuildpackResult};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::formatdoc;
+use libcnb::Env;
+use nodejs_data::{Version, VersionRange};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// The number of packages listed before the rest are summarized as a count.
+const MAX_LISTED_PACKAGES: usize = 10;
+
+#[derive(Debug, PartialEq)]
+struct IncompatiblePackage {
+    name: String,
+    version: String,
+    node_engine: String,
+}
+
+/// Compares the `engines.node` range declared by each installed dependency with the installed
+/// Node.js version. This runs after dev dependencies are pruned so only the packages that are
+/// loaded at runtime are reported. Mismatches are printed as a warning, or fail the build when
+/// `actions.strict_dependency_engines` is enabled.
+pub(crate) fn check_dependency_engines(
+    context: &BuildpackBuildContext,
+    env: &Env,
+    buildpack_config: &BuildpackConfig,
+) -> BuildpackResult<()> {
+    let node_version = nodejs::get_node_version(env)?;
+    let incompatible_packages =
+        find_incompatible_packages(&search_dirs(&context.app_dir, env), &node_version);
+
+    tracing::info!(
+        { DEPENDENCY_ENGINES_INCOMPATIBLE } = incompatible_packages
+            .iter()
+            .map(|package| format!("{}@{}", package.name, package.version))
+            .collect::<Vec<_>>()
+            .join(","),
+        "dependency_engines"
+    );
+
+    if incompatible_packages.is_empty() {
+        return Ok(());
+    }
+
+    if matches!(
+        buildpack_config.strict_dependency_engines,
+        Some(ConfigValue { value: true, .. })
+    ) {
+        Err(create_incompatible_dependency_engines_error(
+            &incompatible_packages,
+            &node_version,
+        ))?;
+    }
+
+    print::warning(create_incompatible_dependency_engines_warning(
+        &incompatible_packages,
+        &node_version,
+    ));
+    Ok(())
+}
+
+/// pnpm installs packages into a virtual store outside the app directory and links them into
+/// `node_modules`, so the store is searched directly instead of following those links.
+fn search_dirs(app_dir: &Path, env: &Env) -> Vec<PathBuf> {
+    let mut search_dirs = vec![
+        app_dir.join("node_modules"),
+        app_dir.join(".yarn/unplugged"),
+    ];
+    search_dirs.extend(
+        [
+            "pnpm_config_virtual_store_dir",
+            "npm_config_virtual_store_dir",
+        ]
+        .iter()
+        .filter_map(|name| env.get(name))
+        .map(PathBuf::from),
+    );
+    search_dirs
+}
+
+fn find_incompatible_packages(
+    search_dirs: &[PathBuf],
+    node_version: &Version,
+) -> Vec<IncompatiblePackage> {
+    let mut incompatible_packages = BTreeMap::new();
+    for search_dir in search_dirs {
+        for entry in WalkDir::new(search_dir)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file() && entry.file_name() == "package.json")
+        {
+            let Some(package_dir) = entry.path().parent() else {
+                continue;
+            };
+            if !is_installed_package_dir(package_dir) {
+                continue;
+            }
+            if let Some(package) = read_incompatible_package(package_dir, node_version) {
+                incompatible_packages
+                    .insert((package.name.clone(), package.version.clone()), package);
+            }
+        }
+    }
+    incompatible_packages.into_values().collect()
+}
+
+fn read_incompatible_package(
+    package_dir: &Path,
+    node_version: &Version,
+) -> Option<IncompatiblePackage> {
+    // installed package manifests are read directly instead of through `PackageJson` to avoid
+    // emitting telemetry for every dependency
+    let package_json = std::fs::read_to_string(package_dir.join("package.json"))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())?;
+    // ranges that don't parse are ignored, as package managers only warn about them
+    let node_engine = package_json["engines"]["node"].as_str()?;
+    if VersionRange::parse(node_engine)
+        .ok()?
+        .satisfies(node_version)
+    {
+        return None;
+    }
+    Some(IncompatiblePackage {
+        name: package_json["name"].as_str()?.to_string(),
+        version: package_json["version"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        node_engine: node_engine.to_string(),
+    })
+}
+
+fn format_incompatible_packages(incompatible_packages: &[IncompatiblePackage]) -> String {
+    let mut lines = incompatible_packages
+        .iter()
+        .take(MAX_LISTED_PACKAGES)
+        .map(|package| {
+            format!(
+                "- {} requires Node.js {}",
+                style::value(format!("{}@{}", package.name, package.version)),
+                style::value(&package.node_engine)
+            )
+        })
+        .collect::<Vec<_>>();
+    if incompatible_packages.len() > MAX_LISTED_PACKAGES {
+        lines.push(format!(
+            "- and {} more",
+            incompatible_packages.len() - MAX_LISTED_PACKAGES
+        ));
+    }
+    lines.join("\n")
+}
+
+fn create_incompatible_dependency_engines_warning(
+    incompatible_packages: &[IncompatiblePackage],
+    node_version: &Version,
+) -> String {
+    let packages = format_incompatible_packages(incompatible_packages);
+    let node_version = style::value(node_version.to_string());
+    let engines_node = style::value("engines.node");
+    let package_json = file_value("package.json");
+    let strict_dependency_engines = style::value("actions.strict_dependency_engines");
+    let project_toml = file_value("project.toml");
+    formatdoc! { "
+        Warning: Dependencies require a different Node.js version
+
+        The following installed dependencies declare an {engines_node} range that Node.js \
+        {node_version} does not satisfy. They may fail when the app runs.
+
+        {packages}
+
+        Request a Node.js version that satisfies these ranges with {engines_node} in \
+        {package_json}, or upgrade these dependencies to releases that support Node.js \
+        {node_version}. To fail the build instead of printing this warning, set \
+        {strict_dependency_engines} to {true_value} in {project_toml}.
+    ", true_value = style::value("true") }
+}
+
+fn create_incompatible_dependency_engines_error(
+    incompatible_packages: &[IncompatiblePackage],
+    node_version: &Version,
+) -> ErrorMessage {
+    let packages = format_incompatible_packages(incompatible_packages);
+    let node_version = style::value(node_version.to_string());
+    let engines_node = style::value("engines.node");
+    let package_json = file_value("package.json");
+    let strict_dependency_engines = style::value("actions.strict_dependency_engines");
+    let project_toml = file_value("project.toml");
+    error_message()
+        .id("dependency_engines/incompatible")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header("Dependencies require a different Node.js version")
+        .body(formatdoc! { "
+            The following installed dependencies declare an {engines_node} range that Node.js \
+            {node_version} does not satisfy. The build was stopped because \
+            {strict_dependency_engines} is enabled in {project_toml}.
+
+            {packages}
+
+            Suggestions:
+            - Request a Node.js version that satisfies these ranges with {engines_node} in {package_json}.
+            - Upgrade these dependencies to releases that support Node.js {node_version}.
+        " })
+        .create()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::error_handling::test_util::assert_error_snapshot;
+    use std::fs;
+
+    fn write_package(dir: &Path, name: &str, version: &str, node_engine: Option<&str>) {
+        fs::create_dir_all(dir).unwrap();
+        let mut package_json = serde_json::json!({ "name": name, "version": version });
+        if let Some(node_engine) = node_engine {
+            package_json["engines"] = serde_json::json!({ "node": node_engine });
+        }
+        fs::write(dir.join("package.json"), package_json.to_string()).unwrap();
+    }
+
+    fn incompatible_package(name: &str, version: &str, node_engine: &str) -> IncompatiblePackage {
+        IncompatiblePackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            node_engine: node_engine.to_string(),
+        }
+    }
+
+    #[test]
+    fn find_incompatible_packages_in_installed_tree() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let node_modules = app_dir.path().join("node_modules");
+        write_package(
+            &node_modules.join("compatible"),
+            "compatible",
+            "1.0.0",
+            Some(">=18"),
+        );
+        write_package(
+            &node_modules.join("no-engines"),
+            "no-engines",
+            "1.0.0",
+            None,
+        );
+        write_package(
+            &node_modules.join("invalid"),
+            "invalid",
+            "1.0.0",
+            Some("not a range"),
+        );
+        write_package(
+            &node_modules.join("@scope/newer"),
+            "@scope/newer",
+            "2.0.0",
+            Some(">=24"),
+        );
+        write_package(
+            &node_modules.join("compatible/node_modules/nested"),
+            "nested",
+            "3.0.0",
+            Some("^20 || ^24"),
+        );
+        // fixtures shipped inside a package aren't installed packages
+        write_package(
+            &node_modules.join("compatible/test/fixture"),
+            "fixture",
+            "1.0.0",
+            Some(">=99"),
+        );
+
+        assert_eq!(
+            find_incompatible_packages(&[node_modules], &Version::new(22, 11, 0)),
+            vec![
+                incompatible_package("@scope/newer", "2.0.0", ">=24"),
+                incompatible_package("nested", "3.0.0", "^20 || ^24"),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_incompatible_packages_reports_each_version_once() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let node_modules = app_dir.path().join("node_modules");
+        write_package(&node_modules.join("newer"), "newer", "2.0.0", Some(">=24"));
+        write_package(
+            &node_modules.join("a/node_modules/newer"),
+            "newer",
+            "2.0.0",
+            Some(">=24"),
+        );
+        assert_eq!(
+            find_incompatible_packages(&[node_modules], &Version::new(22, 11, 0)),
+            vec![incompatible_package("newer", "2.0.0", ">=24")]
+        );
+    }
+
+    #[test]
+    fn incompatible_dependency_engines_error() {
+        let incompatible_packages = (1..=12)
+            .map(|n| incompatible_package(&format!("package-{n:02}"), "1.0.0", ">=24"))
+            .collect::<Vec<_>>();
+        assert_error_snapshot(&create_incompatible_dependency_engines_error(
+            &incompatible_packages,
+            &Version::new(22, 11, 0),
+        ));
+    }
+}
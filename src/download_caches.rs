@@ -0,0 +1,434 @@
+use crate::BuildpackBuildContext;
+use crate::BuildpackResult;
+use crate::o11y::*;
+use crate::target::TargetMetadata;
+use bullet_stream::global::print;
+use bullet_stream::style;
+use libcnb::Env;
+use libcnb::data::layer::LayerName;
+use libcnb::data::layer_name;
+use libcnb::layer::{
+    CachedLayerDefinition, EmptyLayerCause, InvalidMetadataAction, LayerState, RestoredLayerAction,
+};
+use libcnb::layer_env::{LayerEnv, ModificationBehavior, Scope};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+const LAYER_VERSION: &str = "1";
+
+/// A well-known dependency that downloads binaries from its install script into a directory that
+/// can be moved with an environment variable.
+///
+/// Prisma engines and the libvips used by older versions of sharp aren't included since their
+/// download caches can only be moved with settings shared by other tools (`XDG_CACHE_HOME` and
+/// `npm_config_cache`).
+struct DownloadCache {
+    /// How the cache is referred to in the build log.
+    name: &'static str,
+    /// Any of these packages in the lockfile enables the cache and their versions are its key.
+    packages: &'static [&'static str],
+    layer_name: LayerName,
+    env_var: &'static str,
+    /// Whether the downloaded binaries are also needed by the app at runtime.
+    launch: bool,
+}
+
+fn download_caches() -> [DownloadCache; 3] {
+    [
+        DownloadCache {
+            name: "Puppeteer browser cache",
+            packages: &["puppeteer"],
+            layer_name: layer_name!("puppeteer_cache"),
+            env_var: "PUPPETEER_CACHE_DIR",
+            launch: true,
+        },
+        DownloadCache {
+            name: "Playwright browser cache",
+            packages: &[
+                "playwright",
+                "playwright-core",
+                "playwright-chromium",
+                "playwright-firefox",
+                "playwright-webkit",
+                "@playwright/test",
+            ],
+            layer_name: layer_name!("playwright_browsers"),
+            env_var: "PLAYWRIGHT_BROWSERS_PATH",
+            launch: true,
+        },
+        DownloadCache {
+            name: "Cypress binary cache",
+            packages: &["cypress"],
+            layer_name: layer_name!("cypress_cache"),
+            env_var: "CYPRESS_CACHE_FOLDER",
+            launch: false,
+        },
+    ]
+}
+
+/// Points the download directories of well-known dependencies found in the lockfile at cached
+/// layers so the binaries they download during install are reused by later builds. A cache is
+/// cleared whenever the locked version of its packages changes. Returns the names of the caches
+/// that are empty for this build since their binaries are only downloaded by a fresh install.
+pub(crate) fn configure_download_caches(
+    context: &BuildpackBuildContext,
+    env: &mut Env,
+) -> BuildpackResult<Vec<String>> {
+    let locked_packages = locked_packages(&context.app_dir);
+    let target = TargetMetadata::from(&context.target);
+    let mut detected = vec![];
+    let mut empty = vec![];
+
+    for download_cache in download_caches() {
+        let packages = locked_packages
+            .iter()
+            .filter(|(name, _)| download_cache.packages.contains(&name.as_str()))
+            .map(|(name, version)| format!("{name}@{version}"))
+            .collect::<BTreeSet<_>>();
+        if packages.is_empty() {
+            continue;
+        }
+        if detected.is_empty() {
+            print::bullet("Configuring binary download caches");
+        }
+        detected.push(download_cache.layer_name.to_string());
+
+        if env.contains_key(download_cache.env_var) {
+            print::sub_bullet(format!(
+                "Skipping {} as {} is already set",
+                download_cache.name,
+                style::value(download_cache.env_var)
+            ));
+            continue;
+        }
+
+        let new_metadata = DownloadCacheMetadata {
+            layer_version: LAYER_VERSION.to_string(),
+            target: target.clone(),
+            packages: packages.into_iter().collect(),
+        };
+        let cache_layer = context.cached_layer(
+            download_cache.layer_name.clone(),
+            CachedLayerDefinition {
+                build: true,
+                launch: download_cache.launch,
+                invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
+                restored_layer_action: &|old_metadata: &DownloadCacheMetadata, _| {
+                    if old_metadata == &new_metadata {
+                        RestoredLayerAction::KeepLayer
+                    } else {
+                        RestoredLayerAction::DeleteLayer
+                    }
+                },
+            },
+        )?;
+
+        let packages = new_metadata
+            .packages
+            .iter()
+            .map(style::value)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if matches!(cache_layer.state, LayerState::Empty { .. }) {
+            empty.push(download_cache.layer_name.to_string());
+        }
+        match cache_layer.state {
+            LayerState::Restored { .. } => {
+                print::sub_bullet(format!("Restoring {} for {packages}", download_cache.name));
+            }
+            LayerState::Empty {
+                cause: EmptyLayerCause::RestoredLayerAction { .. },
+            } => {
+                print::sub_bullet(format!(
+                    "Clearing {} (package version changed)",
+                    download_cache.name
+                ));
+                print::sub_bullet(format!("Creating {} for {packages}", download_cache.name));
+            }
+            LayerState::Empty { .. } => {
+                print::sub_bullet(format!("Creating {} for {packages}", download_cache.name));
+            }
+        }
+
+        cache_layer.write_env(LayerEnv::new().chainable_insert(
+            if download_cache.launch {
+                Scope::All
+            } else {
+                Scope::Build
+            },
+            ModificationBehavior::Override,
+            download_cache.env_var,
+            cache_layer.path(),
+        ))?;
+        cache_layer.write_metadata(new_metadata)?;
+        env.clone_from(&cache_layer.read_env()?.apply(Scope::Build, env));
+    }
+
+    if !detected.is_empty() {
+        tracing::info!(
+            { DOWNLOAD_CACHES_DETECTED } = detected.join(","),
+            "download_caches"
+        );
+    }
+
+    Ok(empty)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct DownloadCacheMetadata {
+    layer_version: String,
+    target: TargetMetadata,
+    packages: Vec<String>,
+}
+
+/// The name and version of every package in the app's lockfile. A lockfile that can't be read
+/// results in no packages since the package manager reports lockfile problems during the install.
+fn locked_packages(app_dir: &Path) -> BTreeSet<(String, String)> {
+    if let Ok(contents) = fs::read_to_string(app_dir.join("package-lock.json")) {
+        return package_lock_packages(&contents);
+    }
+    if let Ok(contents) = fs::read_to_string(app_dir.join("pnpm-lock.yaml")) {
+        return pnpm_lock_packages(&contents);
+    }
+    if let Ok(contents) = fs::read_to_string(app_dir.join("yarn.lock")) {
+        return yarn_lock_packages(&contents);
+    }
+    BTreeSet::new()
+}
+
+/// Reads the `packages` entries of a v2+ lockfile (`node_modules/<name>`) or the top-level
+/// `dependencies` of a v1 lockfile.
+fn package_lock_packages(contents: &str) -> BTreeSet<(String, String)> {
+    let Ok(package_lock) = serde_json::from_str::<serde_json::Value>(contents) else {
+        return BTreeSet::new();
+    };
+    let packages = package_lock["packages"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(path, package)| {
+            let (_, name) = path.rsplit_once("node_modules/")?;
+            Some((name.to_string(), package["version"].as_str()?.to_string()))
+        });
+    let dependencies = package_lock["dependencies"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, package)| {
+            Some((name.clone(), package["version"].as_str()?.to_string()))
+        });
+    packages.chain(dependencies).collect()
+}
+
+/// Reads the keys of the `packages` section which are formatted as `/<name>/<version>` (v5),
+/// `/<name>@<version>` (v6), or `<name>@<version>` (v9), optionally followed by peer dependency
+/// information.
+fn pnpm_lock_packages(contents: &str) -> BTreeSet<(String, String)> {
+    let is_v5 = contents.lines().any(|line| {
+        line.strip_prefix("lockfileVersion: ")
+            .is_some_and(|version| version.trim_matches(['\'', '"']).starts_with('5'))
+    });
+    contents
+        .lines()
+        .skip_while(|line| *line != "packages:")
+        .skip(1)
+        .take_while(|line| line.is_empty() || line.starts_with(' '))
+        .filter_map(|line| {
+            let key = line.strip_prefix("  ")?.strip_suffix(':')?;
+            if key.starts_with(' ') {
+                return None;
+            }
+            let key = key.trim_matches(['\'', '"']);
+            let key = key.strip_prefix('/').unwrap_or(key);
+            let (name, version) = if is_v5 {
+                let key = key.split_once('_').map_or(key, |(key, _)| key);
+                key.rsplit_once('/')?
+            } else {
+                let key = key.split_once('(').map_or(key, |(key, _)| key);
+                let index = key.get(1..)?.rfind('@')?;
+                (&key[..=index], &key[index + 2..])
+            };
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// Reads the entries of a Yarn 1.x (`version "1.0.0"`) or Yarn 2+ (`version: 1.0.0`) lockfile.
+/// Each entry starts with a line listing the descriptors it resolves, e.g.;
+/// `"puppeteer@^19.0.0", "puppeteer@^19.2.0":`.
+fn yarn_lock_packages(contents: &str) -> BTreeSet<(String, String)> {
+    let mut packages = BTreeSet::new();
+    let mut entry_name = None;
+    for line in contents.lines() {
+        if !line.starts_with([' ', '#']) && line.ends_with(':') {
+            entry_name = line
+                .trim_end_matches(':')
+                .split(", ")
+                .next()
+                .map(|descriptor| descriptor.trim_matches('"'))
+                .filter(|descriptor| *descriptor != "__metadata")
+                .and_then(|descriptor| {
+                    let index = descriptor.get(1..)?.find('@')?;
+                    Some(descriptor[..=index].to_string())
+                });
+        } else if let Some(version) = line
+            .strip_prefix("  version ")
+            .or_else(|| line.strip_prefix("  version: "))
+            && let Some(name) = entry_name.take()
+        {
+            packages.insert((name, version.trim_matches('"').to_string()));
+        }
+    }
+    packages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    fn packages(values: &[(&str, &str)]) -> BTreeSet<(String, String)> {
+        values
+            .iter()
+            .map(|(name, version)| ((*name).to_string(), (*version).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn package_lock_packages_from_v3_and_v1_lockfiles() {
+        let v3 = serde_json::json!({
+            "lockfileVersion": 3,
+            "packages": {
+                "": { "name": "app" },
+                "node_modules/puppeteer": { "version": "19.2.0" },
+                "node_modules/puppeteer/node_modules/@puppeteer/browsers": { "version": "0.1.0" }
+            }
+        });
+        assert_eq!(
+            package_lock_packages(&v3.to_string()),
+            packages(&[("@puppeteer/browsers", "0.1.0"), ("puppeteer", "19.2.0")])
+        );
+
+        let v1 = serde_json::json!({
+            "lockfileVersion": 1,
+            "dependencies": { "cypress": { "version": "12.0.0" } }
+        });
+        assert_eq!(
+            package_lock_packages(&v1.to_string()),
+            packages(&[("cypress", "12.0.0")])
+        );
+    }
+
+    #[test]
+    fn pnpm_lock_packages_from_each_key_format() {
+        let v9 = indoc! { "
+            lockfileVersion: '9.0'
+
+            packages:
+
+              '@playwright/test@1.40.0':
+                resolution: {integrity: sha512-abc}
+
+              puppeteer@19.2.0(typescript@5.0.0):
+                resolution: {integrity: sha512-abc}
+
+            snapshots:
+
+              cypress@12.0.0:
+                dependencies: {}
+        " };
+        assert_eq!(
+            pnpm_lock_packages(v9),
+            packages(&[("@playwright/test", "1.40.0"), ("puppeteer", "19.2.0")])
+        );
+
+        let v6 = indoc! { "
+            packages:
+              /@playwright/test@1.40.0:
+                dev: true
+        " };
+        assert_eq!(
+            pnpm_lock_packages(v6),
+            packages(&[("@playwright/test", "1.40.0")])
+        );
+
+        let v5 = indoc! { "
+            lockfileVersion: 5.4
+
+            packages:
+              /@playwright/test/1.40.0:
+                dev: true
+              /puppeteer/19.2.0_typescript@5.0.0:
+                dev: false
+        " };
+        assert_eq!(
+            pnpm_lock_packages(v5),
+            packages(&[("@playwright/test", "1.40.0"), ("puppeteer", "19.2.0")])
+        );
+    }
+
+    #[test]
+    fn yarn_lock_packages_from_v1_and_berry_lockfiles() {
+        let v1 = indoc! { r#"
+            # yarn lockfile v1
+
+
+            "@playwright/test@^1.40.0":
+              version "1.40.0"
+              resolved "https://registry.yarnpkg.com/@playwright/test/-/test-1.40.0.tgz"
+
+            puppeteer@^19.0.0, puppeteer@^19.2.0:
+              version "19.2.0"
+        "# };
+        assert_eq!(
+            yarn_lock_packages(v1),
+            packages(&[("@playwright/test", "1.40.0"), ("puppeteer", "19.2.0")])
+        );
+
+        let berry = indoc! { r#"
+            __metadata:
+              version: 8
+              cacheKey: 10c0
+
+            "cypress@npm:^12.0.0":
+              version: 12.0.0
+              resolution: "cypress@npm:12.0.0"
+        "# };
+        assert_eq!(
+            yarn_lock_packages(berry),
+            packages(&[("cypress", "12.0.0")])
+        );
+    }
+
+    #[test]
+    fn locked_packages_from_app_dir() {
+        let app_dir = tempfile::tempdir().unwrap();
+        assert!(locked_packages(app_dir.path()).is_empty());
+
+        fs::write(
+            app_dir.path().join("yarn.lock"),
+            "cypress@^12.0.0:\n  version \"12.0.0\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            locked_packages(app_dir.path()),
+            packages(&[("cypress", "12.0.0")])
+        );
+    }
+
+    #[test]
+    fn download_caches_are_valid_layers() {
+        let download_caches = download_caches();
+        let layer_names = download_caches
+            .iter()
+            .map(|download_cache| download_cache.layer_name.to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(layer_names.len(), download_caches.len());
+        for layer_name in layer_names {
+            assert!(crate::cache_reset::RESETTABLE_LAYERS.contains(&layer_name.as_str()));
+        }
+    }
+}
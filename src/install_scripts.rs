@@ -0,0 +1,663 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::native_modules_cache::NativeModulesCache;
+use crate::o11y::*;
+use crate::package_json::PackageJson;
+use crate::utils::error_handling::{
+    ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
+};
+use crate::workspace_packages::find_workspace_package_dirs;
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::formatdoc;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+/// Controls whether dependency lifecycle scripts (`preinstall`, `install`, `postinstall`) are
+/// executed during dependency installation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InstallScriptsMode {
+    /// Every dependency install script runs (package manager default).
+    All,
+    /// Dependencies are installed with scripts disabled, then only allow-listed packages are built.
+    AllowList,
+}
+
+impl FromStr for InstallScriptsMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(InstallScriptsMode::All),
+            "allow_list" => Ok(InstallScriptsMode::AllowList),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for InstallScriptsMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstallScriptsMode::All => write!(f, "all"),
+            InstallScriptsMode::AllowList => write!(f, "allow_list"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum InstallScriptsPolicy {
+    RunAll,
+    AllowList(BTreeSet<String>),
+}
+
+impl InstallScriptsPolicy {
+    pub(crate) fn is_allow_list(&self) -> bool {
+        matches!(self, InstallScriptsPolicy::AllowList(_))
+    }
+}
+
+/// The allow-list is the union of:
+/// - `install_scripts.allowed` from the buildpack configuration
+/// - `pnpm.onlyBuiltDependencies` from `package.json`
+/// - `dependenciesMeta` entries marked `"built": true` from `package.json`
+pub(crate) fn determine_install_scripts_policy(
+    package_json: &PackageJson,
+    buildpack_config: &BuildpackConfig,
+) -> InstallScriptsPolicy {
+    match &buildpack_config.install_scripts {
+        Some(ConfigValue {
+            value: InstallScriptsMode::AllowList,
+            ..
+        }) => {
+            let mut allowed = BTreeSet::new();
+            if let Some(ConfigValue { value, .. }) = &buildpack_config.allowed_install_scripts {
+                allowed.extend(value.iter().cloned());
+            }
+            allowed.extend(package_json.pnpm_only_built_dependencies());
+            allowed.extend(package_json.built_dependencies_meta());
+            tracing::info!(
+                { INSTALL_SCRIPTS_ALLOWED } = allowed.iter().cloned().collect::<Vec<_>>().join(","),
+                "install_scripts"
+            );
+            InstallScriptsPolicy::AllowList(allowed)
+        }
+        _ => InstallScriptsPolicy::RunAll,
+    }
+}
+
+/// Install scripts are deferred until after the install when they are limited to an allow-list or
+/// when compiled native modules can be restored from the cache instead of being rebuilt.
+pub(crate) fn are_install_scripts_deferred(
+    install_scripts: &InstallScriptsPolicy,
+    native_modules_cache: Option<&NativeModulesCache>,
+) -> bool {
+    install_scripts.is_allow_list() || native_modules_cache.is_some()
+}
+
+/// Runs the deferred install scripts after dependencies were installed with scripts disabled. The
+/// `search_dirs` are scanned for installed packages that declare install scripts so the build log
+/// can report which packages were built, restored from the native modules cache, or skipped.
+pub(crate) fn run_deferred_install_scripts(
+    install_scripts: &InstallScriptsPolicy,
+    native_modules_cache: Option<&NativeModulesCache>,
+    search_dirs: &[PathBuf],
+    rebuild_command: impl FnOnce(&[String]) -> Command,
+) -> Result<(), ErrorMessage> {
+    if !are_install_scripts_deferred(install_scripts, native_modules_cache) {
+        return Ok(());
+    }
+
+    let allowed = match install_scripts {
+        InstallScriptsPolicy::AllowList(allowed) => {
+            print::bullet("Running allow-listed dependency install scripts");
+            Some(allowed)
+        }
+        InstallScriptsPolicy::RunAll => {
+            print::bullet("Running dependency install scripts");
+            None
+        }
+    };
+
+    let (packages, skipped): (Vec<_>, Vec<_>) = find_packages_with_install_scripts(search_dirs)
+        .into_iter()
+        .partition(|package| allowed.is_none_or(|allowed| allowed.contains(&package.name)));
+    let skipped = package_names(skipped.iter());
+
+    if !skipped.is_empty() {
+        print::sub_bullet(format!(
+            "Skipped install scripts for packages not in the allow-list: {}",
+            format_package_list(&skipped)
+        ));
+    }
+
+    let restored = native_modules_cache
+        .map(|native_modules_cache| native_modules_cache.restore(&packages))
+        .unwrap_or_default();
+    if !restored.is_empty() {
+        print::sub_bullet(format!(
+            "Restored compiled native modules from cache: {}",
+            format_package_list(&restored.iter().cloned().collect::<Vec<_>>())
+        ));
+    }
+
+    let (built_packages, _): (Vec<_>, Vec<_>) = packages
+        .iter()
+        .partition(|package| !restored.contains(&package.name));
+    let built = package_names(built_packages.iter().copied());
+
+    tracing::info!(
+        { INSTALL_SCRIPTS_BUILT } = built.join(","),
+        { INSTALL_SCRIPTS_SKIPPED } = skipped.join(","),
+        { INSTALL_SCRIPTS_RESTORED } = restored.iter().cloned().collect::<Vec<_>>().join(","),
+        "install_scripts"
+    );
+
+    if built.is_empty() {
+        if allowed.is_some() {
+            print::sub_bullet("No allow-listed packages require building");
+        } else {
+            print::sub_bullet("No packages require building");
+        }
+    } else {
+        print::sub_stream_cmd(rebuild_command(&built))
+            .map_err(|e| create_run_deferred_install_scripts_error(&e, install_scripts))?;
+
+        if allowed.is_some() {
+            print::sub_bullet(format!(
+                "Built allow-listed packages: {}",
+                format_package_list(&built)
+            ));
+        } else {
+            print::sub_bullet(format!("Built packages: {}", format_package_list(&built)));
+        }
+    }
+
+    if let Some(native_modules_cache) = native_modules_cache {
+        match native_modules_cache.save(&built_packages, &packages) {
+            Ok(0) => {}
+            Ok(saved) => print::sub_bullet(format!(
+                "Saved {saved} compiled native {} to cache",
+                if saved == 1 { "module" } else { "modules" }
+            )),
+            Err(error) => print::warning(formatdoc! { "
+                The compiled native modules could not be saved to the cache: {error}
+            " }),
+        }
+    }
+
+    Ok(())
+}
+
+/// The lifecycle scripts of the root package that npm, pnpm, and Yarn 1 run as part of the install,
+/// in the order they run.
+pub(crate) const ROOT_LIFECYCLE_SCRIPTS: [&str; 4] =
+    ["preinstall", "install", "postinstall", "prepare"];
+
+/// Yarn 2+ doesn't run `prepare` for the root package on install.
+pub(crate) const YARN_BERRY_ROOT_LIFECYCLE_SCRIPTS: [&str; 3] =
+    ["preinstall", "install", "postinstall"];
+
+/// Runs the app's own lifecycle scripts after dependencies were installed with scripts disabled for
+/// the allow-list or the native modules cache, or restored from the `node_modules` cache. Disabling
+/// scripts for the install also suppresses these but they belong to the app, not a dependency, so
+/// they aren't subject to the allow-list. They run after the install, so a `preinstall` script
+/// sees the installed dependencies.
+///
+/// Workspace packages are linked into `node_modules` and have their scripts suppressed the same
+/// way, so their scripts run too, from the package's directory and before the root package's like
+/// the package managers do.
+pub(crate) fn run_app_lifecycle_scripts(
+    app_dir: &Path,
+    package_json: &PackageJson,
+    scripts: &[&str],
+    run_script: impl Fn(&str) -> Command,
+) -> Result<(), ErrorMessage> {
+    let mut packages = find_workspace_package_dirs(app_dir, package_json)
+        .into_iter()
+        .map(|workspace_dir| {
+            // workspace manifests are read directly instead of through `PackageJson` to avoid
+            // emitting telemetry for them
+            let workspace_package_json =
+                std::fs::read_to_string(app_dir.join(&workspace_dir).join("package.json"))
+                    .ok()
+                    .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+                    .unwrap_or_default();
+            let workspace_scripts = scripts
+                .iter()
+                .copied()
+                .filter(|script| workspace_package_json["scripts"][*script].is_string())
+                .collect::<Vec<_>>();
+            (Some(workspace_dir), workspace_scripts)
+        })
+        .collect::<Vec<_>>();
+    packages.push((
+        None,
+        scripts
+            .iter()
+            .copied()
+            .filter(|script| package_json.script(script).is_some())
+            .collect(),
+    ));
+    packages.retain(|(_, package_scripts)| !package_scripts.is_empty());
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    print::bullet("Running app install scripts");
+    for (workspace_dir, package_scripts) in packages {
+        let package_dir = match &workspace_dir {
+            Some(workspace_dir) => {
+                print::sub_bullet(format!("Workspace package {}", file_value(workspace_dir)));
+                app_dir.join(workspace_dir)
+            }
+            None => app_dir.to_path_buf(),
+        };
+        let manifest = workspace_dir.unwrap_or_default().join("package.json");
+        for script in package_scripts {
+            print::sub_stream_cmd(run_script(script).current_dir(&package_dir))
+                .map_err(|e| create_run_lifecycle_script_error(script, &manifest, &e))?;
+        }
+    }
+    Ok(())
+}
+
+fn package_names<'a>(packages: impl Iterator<Item = &'a InstalledPackage>) -> Vec<String> {
+    packages
+        .map(|package| package.name.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn format_package_list(names: &[String]) -> String {
+    names
+        .iter()
+        .map(style::value)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// An installed dependency that declares install scripts.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InstalledPackage {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) dir: PathBuf,
+    /// Packages with a `binding.gyp` are compiled with node-gyp.
+    pub(crate) has_binding_gyp: bool,
+}
+
+/// Walks the given directories looking for installed packages (`node_modules/<name>` or
+/// `node_modules/@scope/<name>`) that declare install scripts. A `binding.gyp` file counts as an
+/// install script since package managers run `node-gyp rebuild` for it implicitly.
+fn find_packages_with_install_scripts(search_dirs: &[PathBuf]) -> Vec<InstalledPackage> {
+    search_dirs
+        .iter()
+        .flat_map(|search_dir| {
+            WalkDir::new(search_dir)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file() && entry.file_name() == "package.json")
+                .filter_map(|entry| {
+                    let package_dir = entry.path().parent()?;
+                    if is_installed_package_dir(package_dir) {
+                        read_package_with_install_script(package_dir)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Whether the directory is a package installed directly under a `node_modules` directory, either
+/// unscoped or within an `@scope` directory.
+pub(crate) fn is_installed_package_dir(package_dir: &Path) -> bool {
+    let Some(parent) = package_dir.parent() else {
+        return false;
+    };
+    if parent
+        .file_name()
+        .is_some_and(|name| name == "node_modules")
+    {
+        return true;
+    }
+    parent
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('@'))
+        && parent
+            .parent()
+            .and_then(Path::file_name)
+            .is_some_and(|name| name == "node_modules")
+}
+
+fn read_package_with_install_script(package_dir: &Path) -> Option<InstalledPackage> {
+    // installed package manifests are read directly instead of through `PackageJson` to avoid
+    // emitting telemetry for every dependency
+    let package_json = std::fs::read_to_string(package_dir.join("package.json"))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())?;
+    let has_binding_gyp = package_dir.join("binding.gyp").is_file();
+    let has_install_script = ["preinstall", "install", "postinstall"]
+        .iter()
+        .any(|script| package_json["scripts"][script].is_string())
+        || has_binding_gyp;
+    if !has_install_script {
+        return None;
+    }
+    let name = package_json["name"]
+        .as_str()
+        .map(ToString::to_string)
+        .or_else(|| {
+            package_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })?;
+    Some(InstalledPackage {
+        name,
+        version: package_json["version"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        dir: package_dir.to_path_buf(),
+        has_binding_gyp,
+    })
+}
+
+fn create_run_lifecycle_script_error(
+    script: &str,
+    manifest: &Path,
+    error: &fun_run::CmdError,
+) -> ErrorMessage {
+    let script = style::value(script);
+    let run_command = style::command(error.name());
+    let package_json = file_value(manifest);
+    error_message()
+        .id("install_scripts/root_lifecycle_script")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header(format!("Failed to run the app's {script} script"))
+        .body(formatdoc! { "
+            Dependencies were installed with install scripts disabled or restored from the cache \
+            so the buildpack ran the {script} script from {package_json} afterwards with \
+            {run_command}. This command failed \
+            and the buildpack cannot continue. See the log output above for more information.
+
+            Suggestions:
+            - Ensure that this command runs locally without error (exit status = 0).
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+fn create_run_deferred_install_scripts_error(
+    error: &fun_run::CmdError,
+    install_scripts: &InstallScriptsPolicy,
+) -> ErrorMessage {
+    let rebuild_command = style::command(error.name());
+    let error_message =
+        error_message()
+            .id("install_scripts/rebuild")
+            .error_type(ErrorType::UserFacing(
+                SuggestRetryBuild::Yes,
+                SuggestSubmitIssue::No,
+            ));
+    match install_scripts {
+        InstallScriptsPolicy::AllowList(_) => error_message
+            .header("Failed to run allow-listed dependency install scripts")
+            .body(formatdoc! { "
+                Dependencies were installed with install scripts disabled and the buildpack then ran \
+                {rebuild_command} to build the allow-listed packages. This command failed and the buildpack \
+                cannot continue. See the log output above for more information.
+
+                Suggestions:
+                - Ensure that this command runs locally without error (exit status = 0).
+                - Confirm that every package the build depends on is in the allow-list.
+            " }),
+        InstallScriptsPolicy::RunAll => error_message
+            .header("Failed to run dependency install scripts")
+            .body(formatdoc! { "
+                Dependencies were installed with install scripts disabled so compiled native modules \
+                could be restored from the cache. The buildpack then ran {rebuild_command} to build the \
+                remaining packages. This command failed and the buildpack cannot continue. See the log \
+                output above for more information.
+
+                Suggestions:
+                - Ensure that this command runs locally without error (exit status = 0).
+                - Disable the native modules cache by removing {cache_native_modules} from {project_toml}.
+            ", cache_native_modules = style::value("actions.cache_native_modules"), project_toml = file_value("project.toml") }),
+    }
+    .debug_info(error.to_string())
+    .create()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildpack_config::ConfigValueSource;
+    use crate::utils::error_handling::test_util::{assert_error_snapshot, create_cmd_error};
+    use std::fs;
+
+    fn write_package(dir: &Path, package_json: &serde_json::Value) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("package.json"), package_json.to_string()).unwrap();
+    }
+
+    fn package_json(value: &serde_json::Value) -> PackageJson {
+        let dir = tempfile::tempdir().unwrap();
+        write_package(dir.path(), value);
+        PackageJson::try_from(dir.path().join("package.json")).unwrap()
+    }
+
+    #[test]
+    fn parse_install_scripts_mode() {
+        assert_eq!("all".parse(), Ok(InstallScriptsMode::All));
+        assert_eq!("allow_list".parse(), Ok(InstallScriptsMode::AllowList));
+        assert_eq!("none".parse::<InstallScriptsMode>(), Err(()));
+    }
+
+    #[test]
+    fn policy_defaults_to_run_all() {
+        let package_json = package_json(&serde_json::json!({
+            "pnpm": { "onlyBuiltDependencies": ["esbuild"] }
+        }));
+        assert_eq!(
+            determine_install_scripts_policy(&package_json, &BuildpackConfig::default()),
+            InstallScriptsPolicy::RunAll
+        );
+    }
+
+    #[test]
+    fn policy_allow_list_merges_all_sources() {
+        let package_json = package_json(&serde_json::json!({
+            "pnpm": { "onlyBuiltDependencies": ["esbuild"] },
+            "dependenciesMeta": { "sharp@0.33.0": { "built": true } }
+        }));
+        let mut buildpack_config = BuildpackConfig::default();
+        buildpack_config.install_scripts = Some(ConfigValue {
+            value: InstallScriptsMode::AllowList,
+            source: ConfigValueSource::ProjectToml,
+        });
+        buildpack_config.allowed_install_scripts = Some(ConfigValue {
+            value: vec!["bcrypt".to_string()],
+            source: ConfigValueSource::ProjectToml,
+        });
+        assert_eq!(
+            determine_install_scripts_policy(&package_json, &buildpack_config),
+            InstallScriptsPolicy::AllowList(BTreeSet::from([
+                "bcrypt".to_string(),
+                "esbuild".to_string(),
+                "sharp".to_string(),
+            ]))
+        );
+    }
+
+    #[test]
+    fn finds_packages_with_install_scripts() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let node_modules = app_dir.path().join("node_modules");
+        write_package(
+            &node_modules.join("esbuild"),
+            &serde_json::json!({ "name": "esbuild", "scripts": { "postinstall": "node install.js" } }),
+        );
+        write_package(
+            &node_modules.join("@swc/core"),
+            &serde_json::json!({ "name": "@swc/core", "scripts": { "install": "node postinstall.js" } }),
+        );
+        write_package(
+            &node_modules.join("dtrace-provider"),
+            &serde_json::json!({ "name": "dtrace-provider", "version": "0.8.8" }),
+        );
+        fs::write(node_modules.join("dtrace-provider/binding.gyp"), "{}").unwrap();
+        write_package(
+            &node_modules.join("left-pad"),
+            &serde_json::json!({ "name": "left-pad", "scripts": { "test": "tap" } }),
+        );
+        // nested package.json files inside a package are not installed packages
+        write_package(
+            &node_modules.join("left-pad/lib"),
+            &serde_json::json!({ "name": "nested", "scripts": { "install": "exit 1" } }),
+        );
+
+        let packages = find_packages_with_install_scripts(&[node_modules]);
+        assert_eq!(
+            package_names(packages.iter()),
+            Vec::from([
+                "@swc/core".to_string(),
+                "dtrace-provider".to_string(),
+                "esbuild".to_string(),
+            ])
+        );
+        let dtrace_provider = packages
+            .iter()
+            .find(|package| package.name == "dtrace-provider")
+            .unwrap();
+        assert_eq!(dtrace_provider.version, "0.8.8");
+        assert!(dtrace_provider.has_binding_gyp);
+    }
+
+    #[test]
+    fn runs_root_lifecycle_scripts_in_order() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let package_json = package_json(&serde_json::json!({
+            "name": "app",
+            "scripts": {
+                "prepare": "husky",
+                "postinstall": "prisma generate",
+                "build": "tsc",
+                "preinstall": "node check.js"
+            }
+        }));
+
+        let scripts = std::cell::RefCell::new(vec![]);
+        run_app_lifecycle_scripts(
+            app_dir.path(),
+            &package_json,
+            &ROOT_LIFECYCLE_SCRIPTS,
+            |script| {
+                scripts.borrow_mut().push(script.to_string());
+                Command::new("true")
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            scripts.into_inner(),
+            ["preinstall", "postinstall", "prepare"]
+        );
+
+        let scripts = std::cell::RefCell::new(vec![]);
+        run_app_lifecycle_scripts(
+            app_dir.path(),
+            &package_json,
+            &YARN_BERRY_ROOT_LIFECYCLE_SCRIPTS,
+            |script| {
+                scripts.borrow_mut().push(script.to_string());
+                Command::new("true")
+            },
+        )
+        .unwrap();
+        assert_eq!(scripts.into_inner(), ["preinstall", "postinstall"]);
+    }
+
+    #[test]
+    fn runs_workspace_package_lifecycle_scripts_before_root() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let root = serde_json::json!({
+            "name": "app",
+            "workspaces": ["packages/*"],
+            "scripts": { "postinstall": "node setup.js" }
+        });
+        write_package(app_dir.path(), &root);
+        write_package(
+            &app_dir.path().join("packages/api"),
+            &serde_json::json!({
+                "name": "api",
+                "scripts": { "prepare": "tsc", "postinstall": "prisma generate" }
+            }),
+        );
+        write_package(
+            &app_dir.path().join("packages/web"),
+            &serde_json::json!({ "name": "web", "scripts": { "build": "vite build" } }),
+        );
+
+        // each script logs its name to the directory it ran in
+        let scripts = std::cell::RefCell::new(vec![]);
+        run_app_lifecycle_scripts(
+            app_dir.path(),
+            &package_json(&root),
+            &ROOT_LIFECYCLE_SCRIPTS,
+            |script| {
+                scripts.borrow_mut().push(script.to_string());
+                let mut command = Command::new("sh");
+                command.args(["-c", &format!("echo {script} >> scripts.log")]);
+                command
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            scripts.into_inner(),
+            ["postinstall", "prepare", "postinstall"]
+        );
+        assert_eq!(
+            fs::read_to_string(app_dir.path().join("packages/api/scripts.log")).unwrap(),
+            "postinstall\nprepare\n"
+        );
+        assert!(!app_dir.path().join("packages/web/scripts.log").exists());
+        assert_eq!(
+            fs::read_to_string(app_dir.path().join("scripts.log")).unwrap(),
+            "postinstall\n"
+        );
+    }
+
+    #[test]
+    fn run_root_lifecycle_script_error() {
+        assert_error_snapshot(&create_run_lifecycle_script_error(
+            "postinstall",
+            Path::new("package.json"),
+            &create_cmd_error("npm run postinstall"),
+        ));
+    }
+
+    #[test]
+    fn run_allowed_install_scripts_error() {
+        assert_error_snapshot(&create_run_deferred_install_scripts_error(
+            &create_cmd_error("npm rebuild esbuild"),
+            &InstallScriptsPolicy::AllowList(BTreeSet::from(["esbuild".to_string()])),
+        ));
+    }
+
+    #[test]
+    fn run_deferred_install_scripts_error() {
+        assert_error_snapshot(&create_run_deferred_install_scripts_error(
+            &create_cmd_error("npm rebuild dtrace-provider"),
+            &InstallScriptsPolicy::RunAll,
+        ));
+    }
+}
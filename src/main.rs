@@ -1,5 +1,6 @@
 use crate::buildpack_config::{ConfigValue, ConfigValueSource};
 use crate::cleanup::run_post_build_cleanup_tasks;
+use crate::committed_node_modules::CommittedNodeModulesMode;
 use crate::context::NodeJsBuildContext;
 use crate::o11y::*;
 use crate::package_manager::InstalledPackageManager;
@@ -20,16 +21,29 @@ use libcnb_test as _;
 use toml::Table;
 
 mod buildpack_config;
+mod cache_policy;
+mod cache_reset;
 mod cleanup;
+mod committed_node_modules;
+mod compile_cache;
 mod context;
+mod dependency_engines;
+mod download_caches;
+mod install_scripts;
+mod native_modules_cache;
+mod network;
+mod node_modules_cache;
 mod o11y;
 mod package_json;
 mod package_manager;
 mod package_managers;
+mod prune_check;
 mod runtime;
 mod runtimes;
 mod support_status;
+mod target;
 mod utils;
+mod workspace_packages;
 
 type BuildpackDetectContext = libcnb::detect::DetectContext<NodeJsBuildpack>;
 type BuildpackBuildContext = NodeJsBuildContext;
@@ -99,22 +113,60 @@ impl libcnb::Buildpack for NodeJsBuildpack {
                 None => Table::new(),
             },
         };
+        // carried over into the pnpm store's layer metadata when the layer is restored
+        store
+            .metadata
+            .remove(package_managers::pnpm::LEGACY_STORE_USE_COUNT_KEY);
         let mut build_result_builder = BuildResultBuilder::new();
         let mut env = Env::from_current();
 
         let buildpack_config = buildpack_config::BuildpackConfig::try_from(&context)?;
+        let target = target::TargetMetadata::from(&context.target);
+
+        cache_reset::reset_cached_layers(&context, &env, &buildpack_config)?;
+
+        network::configure_network(&context, &mut env, &buildpack_config)?;
 
         let package_json =
             package_json::PackageJson::try_from(context.app_dir.join("package.json"))?;
 
+        let requested_package_manager =
+            package_manager::determine_package_manager(&context.app_dir, &package_json);
+
         print::bullet("Checking Node.js version");
-        Ok(runtime::determine_runtime(&package_json))
+        Ok(runtime::determine_runtime(&context.app_dir, &package_json))
             .inspect(runtime::log_requested_runtime)
-            .and_then(runtime::resolve_runtime)
+            .and_then(|requested_runtime| {
+                runtime::resolve_runtime(
+                    requested_runtime,
+                    &target,
+                    runtime::read_previous_runtime_version(&store).as_ref(),
+                    &buildpack_config,
+                )
+            })
             .inspect(runtime::log_resolved_runtime)
+            .inspect(|resolved_runtime| {
+                runtime::record_resolved_runtime(&mut store, resolved_runtime);
+            })
             .and_then(runtime::check_runtime_support_status)
+            .inspect(|resolved_runtime| match resolved_runtime {
+                // overlap the package manager downloads with the Node.js installation
+                runtime::ResolvedRuntime::Nodejs(artifact) => {
+                    package_manager::prefetch_package_manager(
+                        &context,
+                        &requested_package_manager,
+                        &artifact.version,
+                    );
+                }
+            })
             .and_then(|resolved_runtime| {
-                runtime::install_runtime(&context, &mut env, resolved_runtime)
+                runtime::install_runtime(
+                    &context,
+                    &mut env,
+                    resolved_runtime,
+                    &buildpack_config,
+                    &requested_package_manager,
+                )
             })?;
 
         // TODO: this code could be moved to the start of the build execution but will remain here until the package managers are cleaned up
@@ -137,6 +189,29 @@ impl libcnb::Buildpack for NodeJsBuildpack {
             available_parallelism::env_value(),
         )?;
 
+        // NODE_OPTIONS is only defaulted for the commands this buildpack runs so the heap limit
+        // doesn't carry over to the processes of later buildpacks.
+        if let Some(available_memory_mb) = available_memory::available_memory_mb()
+            && !env.contains_key("NODE_OPTIONS")
+        {
+            env.insert(
+                "NODE_OPTIONS",
+                utils::build_env::max_old_space_size_node_options(available_memory_mb),
+            );
+        }
+
+        // NODE_ENV is only defaulted for the launch environment. Dependency installation expects dev
+        // dependencies to be installed (they are pruned afterward) and npm and pnpm both skip them
+        // when NODE_ENV=production is set during the build.
+        utils::runtime_env::set_default_launch_env_var(
+            &context,
+            "NODE_ENV",
+            match &buildpack_config.node_env {
+                Some(ConfigValue { value, .. }) => value.as_str(),
+                None => "production",
+            },
+        )?;
+
         // TODO: this code should be moved to the end of the build execution but can't until the package managers are cleaned up
         if let Some(path) = ["server.js", "index.js"]
             .map(|name| context.app_dir.join(name))
@@ -158,30 +233,72 @@ impl libcnb::Buildpack for NodeJsBuildpack {
         }
 
         // install package manager
-        let installed_package_manager = Ok(package_manager::determine_package_manager(
-            &context.app_dir,
-            &package_json,
-        ))
-        .inspect(package_manager::log_requested_package_manager)
-        .and_then(|requested_package_manager| {
-            package_manager::resolve_package_manager(&context, &mut env, &requested_package_manager)
-        })
-        .inspect(package_manager::log_resolved_package_manager)
-        .and_then(|resolved_package_manager| {
-            package_manager::install_package_manager(&context, &mut env, &resolved_package_manager)
-        })?;
+        let installed_package_manager = Ok(requested_package_manager)
+            .inspect(package_manager::log_requested_package_manager)
+            .and_then(|requested_package_manager| {
+                package_manager::resolve_package_manager(
+                    &context,
+                    &mut env,
+                    &requested_package_manager,
+                )
+            })
+            .inspect(package_manager::log_resolved_package_manager)
+            .and_then(|resolved_package_manager| {
+                package_manager::install_package_manager(
+                    &context,
+                    &mut env,
+                    &resolved_package_manager,
+                )
+            })?;
 
         // dependency installation & process registration
         if ["pnpm-lock.yaml", "yarn.lock", "package-lock.json"]
             .iter()
             .any(|lockfile| context.app_dir.join(lockfile).exists())
         {
-            package_manager::install_dependencies(
-                &context,
-                &mut env,
-                &mut store,
-                &installed_package_manager,
-            )?;
+            let committed_node_modules = committed_node_modules::detect_committed_node_modules(
+                &context.app_dir,
+                &buildpack_config,
+            );
+            let empty_download_caches =
+                download_caches::configure_download_caches(&context, &mut env)?;
+            let install_scripts_policy =
+                install_scripts::determine_install_scripts_policy(&package_json, &buildpack_config);
+            if committed_node_modules == Some(CommittedNodeModulesMode::Rebuild) {
+                committed_node_modules::rebuild_committed_node_modules(
+                    &context.app_dir,
+                    &env,
+                    &installed_package_manager,
+                    &install_scripts_policy,
+                )?;
+            } else {
+                if committed_node_modules == Some(CommittedNodeModulesMode::Reinstall) {
+                    committed_node_modules::remove_committed_node_modules(&context.app_dir)?;
+                }
+                let node_modules_cache = node_modules_cache::NodeModulesCache::new(
+                    &context,
+                    &env,
+                    &package_json,
+                    &buildpack_config,
+                    &installed_package_manager,
+                    &install_scripts_policy,
+                    &empty_download_caches,
+                );
+                let native_modules_cache = native_modules_cache::NativeModulesCache::new(
+                    &context,
+                    &env,
+                    &buildpack_config,
+                )?;
+                package_manager::install_dependencies(
+                    &context,
+                    &mut env,
+                    &installed_package_manager,
+                    &package_json,
+                    &install_scripts_policy,
+                    node_modules_cache.as_ref(),
+                    native_modules_cache.as_ref(),
+                )?;
+            }
             package_manager::run_build_scripts(
                 &env,
                 &installed_package_manager,
@@ -192,8 +309,10 @@ impl libcnb::Buildpack for NodeJsBuildpack {
                 &context,
                 &env,
                 &installed_package_manager,
+                &package_json,
                 &buildpack_config,
             )?;
+            dependency_engines::check_dependency_engines(&context, &env, &buildpack_config)?;
 
             build_result_builder = package_manager::configure_default_processes(
                 &context,
@@ -212,7 +331,7 @@ impl libcnb::Buildpack for NodeJsBuildpack {
                 )?;
             }
 
-            if let Some(ConfigValue { source, .. }) = buildpack_config.prune_dev_dependencies {
+            if let Some(ConfigValue { source, .. }) = &buildpack_config.prune_dev_dependencies {
                 match source {
                     ConfigValueSource::Buildplan(_) => {
                         print::warning(indoc! { "
@@ -230,6 +349,8 @@ impl libcnb::Buildpack for NodeJsBuildpack {
             }
         }
 
+        compile_cache::warm_compile_cache(&context, &env, &buildpack_config)?;
+
         let node_module_bins_layer = context.uncached_layer(
             layer_name!("z_node_module_bins"),
             UncachedLayerDefinition {
@@ -0,0 +1,276 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::install_scripts::InstalledPackage;
+use crate::node_modules_cache::node_abi;
+use crate::target::TargetMetadata;
+use crate::utils::fs::copy_dir;
+use crate::{BuildpackBuildContext, BuildpackResult};
+use bullet_stream::global::print;
+use indoc::formatdoc;
+use libcnb::Env;
+use libcnb::data::layer_name;
+use libcnb::layer::{CachedLayerDefinition, InvalidMetadataAction, RestoredLayerAction};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const LAYER_VERSION: &str = "1";
+
+/// An opt-in cache of the `.node` files compiled for native dependencies, enabled with
+/// `actions.cache_native_modules`. Each entry is keyed on the package name and version, the
+/// Node.js ABI, and the architecture so a package restored from the cache isn't recompiled.
+pub(crate) struct NativeModulesCache {
+    cache_dir: PathBuf,
+    node_abi: String,
+    arch: String,
+}
+
+impl NativeModulesCache {
+    pub(crate) fn new(
+        context: &BuildpackBuildContext,
+        env: &Env,
+        buildpack_config: &BuildpackConfig,
+    ) -> BuildpackResult<Option<Self>> {
+        if !matches!(
+            buildpack_config.cache_native_modules,
+            Some(ConfigValue { value: true, .. })
+        ) {
+            return Ok(None);
+        }
+
+        let node_abi = match node_abi(env) {
+            Ok(node_abi) => node_abi,
+            Err(error) => {
+                print::warning(formatdoc! { "
+                    The native modules cache is disabled for this build because the Node.js ABI \
+                    version could not be determined: {error}
+                " });
+                return Ok(None);
+            }
+        };
+
+        let target = TargetMetadata::from(&context.target);
+        let cache_layer = context.cached_layer(
+            layer_name!("native_modules"),
+            CachedLayerDefinition {
+                build: false,
+                launch: false,
+                invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
+                restored_layer_action: &|old_metadata: &NativeModulesCacheMetadata, _| {
+                    // binaries built against a different distribution may link other system libraries
+                    if old_metadata.layer_version == LAYER_VERSION && old_metadata.target == target
+                    {
+                        RestoredLayerAction::KeepLayer
+                    } else {
+                        RestoredLayerAction::DeleteLayer
+                    }
+                },
+            },
+        )?;
+        cache_layer.write_metadata(NativeModulesCacheMetadata {
+            layer_version: LAYER_VERSION.to_string(),
+            target: target.clone(),
+        })?;
+
+        Ok(Some(Self {
+            cache_dir: cache_layer.path(),
+            node_abi,
+            arch: target.arch,
+        }))
+    }
+
+    fn entry_dir(&self, package: &InstalledPackage) -> PathBuf {
+        self.cache_dir.join(format!(
+            "{}@{}-node-{}-{}",
+            package.name.replace('/', "+"),
+            package.version,
+            self.node_abi,
+            self.arch
+        ))
+    }
+
+    /// Copies the cached artifacts into each compiled package. Returns the names of the packages
+    /// where every installed copy was restored, the others still need to be rebuilt.
+    pub(crate) fn restore(&self, packages: &[InstalledPackage]) -> BTreeSet<String> {
+        let mut restored = BTreeSet::new();
+        let mut missed = BTreeSet::new();
+        for package in packages {
+            let entry_dir = self.entry_dir(package);
+            let is_restored = package.has_binding_gyp
+                && entry_dir.is_dir()
+                && copy_dir(&entry_dir, &package.dir)
+                    .inspect_err(|error| {
+                        print::warning(formatdoc! { "
+                            The cached native module for {name}@{version} could not be restored so \
+                            it will be rebuilt: {error}
+                        ", name = package.name, version = package.version });
+                    })
+                    .is_ok();
+            if is_restored {
+                restored.insert(package.name.clone());
+            } else {
+                missed.insert(package.name.clone());
+            }
+        }
+        restored.difference(&missed).cloned().collect()
+    }
+
+    /// Saves the `.node` files compiled for each package and drops the entries of packages that
+    /// are no longer installed. Returns the number of packages saved.
+    pub(crate) fn save(
+        &self,
+        compiled: &[&InstalledPackage],
+        installed: &[InstalledPackage],
+    ) -> io::Result<usize> {
+        let mut saved = 0;
+        for package in compiled.iter().filter(|package| package.has_binding_gyp) {
+            let artifacts = find_compiled_artifacts(&package.dir)?;
+            if artifacts.is_empty() {
+                continue;
+            }
+            let entry_dir = self.entry_dir(package);
+            if entry_dir.exists() {
+                fs::remove_dir_all(&entry_dir)?;
+            }
+            for artifact in artifacts {
+                let destination = entry_dir.join(&artifact);
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(package.dir.join(&artifact), destination)?;
+            }
+            saved += 1;
+        }
+
+        let current_entries = installed
+            .iter()
+            .map(|package| self.entry_dir(package))
+            .collect::<BTreeSet<_>>();
+        for entry in fs::read_dir(&self.cache_dir)? {
+            let path = entry?.path();
+            if path.is_dir() && !current_entries.contains(&path) {
+                fs::remove_dir_all(path)?;
+            }
+        }
+
+        Ok(saved)
+    }
+}
+
+/// Finds the `.node` files under a package directory, relative to it. The dependencies of the
+/// package are skipped since they are cached under their own entries.
+fn find_compiled_artifacts(package_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut artifacts = vec![];
+    for entry in WalkDir::new(package_dir)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != "node_modules")
+    {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "node")
+        {
+            artifacts.push(
+                entry
+                    .path()
+                    .strip_prefix(package_dir)
+                    .expect("Walked path should be within the package directory")
+                    .to_path_buf(),
+            );
+        }
+    }
+    Ok(artifacts)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct NativeModulesCacheMetadata {
+    layer_version: String,
+    target: TargetMetadata,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed_package(dir: &Path, name: &str, version: &str) -> InstalledPackage {
+        let package_dir = dir.join("node_modules").join(name);
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("binding.gyp"), "{}").unwrap();
+        InstalledPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            dir: package_dir,
+            has_binding_gyp: true,
+        }
+    }
+
+    fn native_modules_cache(cache_dir: &Path) -> NativeModulesCache {
+        NativeModulesCache {
+            cache_dir: cache_dir.to_path_buf(),
+            node_abi: "127".to_string(),
+            arch: "amd64".to_string(),
+        }
+    }
+
+    #[test]
+    fn entries_are_keyed_on_package_abi_and_arch() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let cache = native_modules_cache(Path::new("/layers/native_modules"));
+        assert_eq!(
+            cache.entry_dir(&installed_package(app_dir.path(), "@scope/native", "1.2.3")),
+            Path::new("/layers/native_modules/@scope+native@1.2.3-node-127-amd64")
+        );
+    }
+
+    #[test]
+    fn save_and_restore_compiled_artifacts() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = native_modules_cache(cache_dir.path());
+        let native = installed_package(app_dir.path(), "native", "1.0.0");
+        fs::create_dir_all(native.dir.join("build/Release/obj.target")).unwrap();
+        fs::write(native.dir.join("build/Release/native.node"), "binary").unwrap();
+        fs::write(
+            native.dir.join("build/Release/obj.target/native.o"),
+            "object",
+        )
+        .unwrap();
+        let uncompiled = installed_package(app_dir.path(), "uncompiled", "1.0.0");
+
+        let installed = [native.clone(), uncompiled.clone()];
+        assert_eq!(cache.save(&[&native, &uncompiled], &installed).unwrap(), 1);
+
+        fs::remove_dir_all(native.dir.join("build")).unwrap();
+        assert_eq!(
+            cache.restore(&installed),
+            BTreeSet::from(["native".to_string()])
+        );
+        assert_eq!(
+            fs::read_to_string(native.dir.join("build/Release/native.node")).unwrap(),
+            "binary"
+        );
+        assert!(!native.dir.join("build/Release/obj.target").exists());
+    }
+
+    #[test]
+    fn save_removes_entries_for_packages_no_longer_installed() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = native_modules_cache(cache_dir.path());
+        let old = installed_package(app_dir.path(), "native", "1.0.0");
+        fs::create_dir_all(old.dir.join("build/Release")).unwrap();
+        fs::write(old.dir.join("build/Release/native.node"), "old").unwrap();
+        cache.save(&[&old], std::slice::from_ref(&old)).unwrap();
+
+        let new = InstalledPackage {
+            version: "2.0.0".to_string(),
+            ..old.clone()
+        };
+        fs::write(new.dir.join("build/Release/native.node"), "new").unwrap();
+        cache.save(&[&new], std::slice::from_ref(&new)).unwrap();
+
+        assert!(!cache.entry_dir(&old).exists());
+        assert!(cache.entry_dir(&new).exists());
+    }
+}
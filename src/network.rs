@@ -0,0 +1,401 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::utils::error_handling::{
+    ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message,
+};
+use crate::utils::http::{self, ClientConfig};
+use crate::{BuildpackBuildContext, BuildpackResult};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::formatdoc;
+use libcnb::Env;
+use libcnb::data::layer_name;
+use libcnb::layer::UncachedLayerDefinition;
+use libcnb::layer_env::{LayerEnv, ModificationBehavior, Scope};
+use reqwest::{Certificate, NoProxy, Proxy};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+/// Environment variables that point at a bundle of extra certificate authorities. Node.js reads
+/// `NODE_EXTRA_CA_CERTS` and `SSL_CERT_FILE` is the convention for most other tools.
+const CA_FILE_ENV_VARS: [&str; 2] = ["NODE_EXTRA_CA_CERTS", "SSL_CERT_FILE"];
+
+/// Configures the proxies and certificate authorities used for every download made by the
+/// buildpack, and exposes the certificate authorities to the package manager commands.
+#[instrument(skip_all)]
+pub(crate) fn configure_network(
+    context: &BuildpackBuildContext,
+    env: &mut Env,
+    buildpack_config: &BuildpackConfig,
+) -> BuildpackResult<()> {
+    let ca_bundle = read_ca_files(&ca_files(&context.app_dir, env, buildpack_config))?;
+
+    if !ca_bundle.certificates.is_empty() {
+        // Node.js only reads extra certificates from a single file so every source is combined
+        let ca_certificates_layer = context.uncached_layer(
+            layer_name!("ca_certificates"),
+            UncachedLayerDefinition {
+                build: true,
+                launch: false,
+            },
+        )?;
+        let ca_bundle_path = ca_certificates_layer.path().join("ca-bundle.pem");
+        fs::write(&ca_bundle_path, &ca_bundle.pem)
+            .map_err(|e| create_write_ca_bundle_error_message(&e))?;
+        ca_certificates_layer.write_env(LayerEnv::new().chainable_insert(
+            Scope::Build,
+            ModificationBehavior::Override,
+            "NODE_EXTRA_CA_CERTS",
+            &ca_bundle_path,
+        ))?;
+        env.clone_from(&ca_certificates_layer.read_env()?.apply(Scope::Build, env));
+    }
+
+    http::configure_clients(ClientConfig {
+        proxies: proxies(env)?,
+        ca_certificates: ca_bundle.certificates,
+    });
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum CaFile {
+    Configured(PathBuf),
+    EnvVar(&'static str, PathBuf),
+}
+
+impl CaFile {
+    fn path(&self) -> &Path {
+        match self {
+            CaFile::Configured(path) | CaFile::EnvVar(_, path) => path,
+        }
+    }
+
+    fn source(&self) -> String {
+        match self {
+            CaFile::Configured(_) => "network.ca_file".to_string(),
+            CaFile::EnvVar(name, _) => (*name).to_string(),
+        }
+    }
+}
+
+fn ca_files(app_dir: &Path, env: &Env, buildpack_config: &BuildpackConfig) -> Vec<CaFile> {
+    buildpack_config
+        .ca_file
+        .as_ref()
+        .map(|ConfigValue { value, .. }| CaFile::Configured(app_dir.join(value)))
+        .into_iter()
+        .chain(CA_FILE_ENV_VARS.into_iter().filter_map(|name| {
+            env.get(name)
+                .filter(|value| !value.is_empty())
+                .map(|value| CaFile::EnvVar(name, PathBuf::from(value)))
+        }))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct CaBundle {
+    pem: Vec<u8>,
+    certificates: Vec<Certificate>,
+}
+
+fn read_ca_files(ca_files: &[CaFile]) -> Result<CaBundle, ErrorMessage> {
+    let mut ca_bundle = CaBundle::default();
+    for ca_file in ca_files {
+        let pem = match (ca_file, fs::read(ca_file.path())) {
+            (_, Ok(pem)) => pem,
+            // environment variables may be set for the whole builder so a missing file isn't fatal
+            (CaFile::EnvVar(name, path), Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                print::warning(create_missing_ca_file_warning(name, path));
+                continue;
+            }
+            (_, Err(e)) => return Err(create_read_ca_file_error_message(ca_file, &e)),
+        };
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .ok()
+            .filter(|certificates| !certificates.is_empty())
+            .ok_or_else(|| create_invalid_ca_file_error_message(ca_file))?;
+        ca_bundle.certificates.extend(certificates);
+        ca_bundle.pem.extend(pem);
+        if !ca_bundle.pem.ends_with(b"\n") {
+            ca_bundle.pem.push(b'\n');
+        }
+    }
+    Ok(ca_bundle)
+}
+
+/// Proxies are configured from the standard environment variables, which are accepted in either
+/// upper or lower case.
+fn proxies(env: &Env) -> Result<Vec<Proxy>, ErrorMessage> {
+    let mut proxies = Vec::new();
+    if let Some(url) = proxy_env_var(env, "HTTP_PROXY") {
+        proxies.push(
+            Proxy::http(url).map_err(|e| create_invalid_proxy_error_message("HTTP_PROXY", &e))?,
+        );
+    }
+    if let Some(url) = proxy_env_var(env, "HTTPS_PROXY") {
+        proxies.push(
+            Proxy::https(url).map_err(|e| create_invalid_proxy_error_message("HTTPS_PROXY", &e))?,
+        );
+    }
+    let no_proxy = proxy_env_var(env, "NO_PROXY");
+    Ok(proxies
+        .into_iter()
+        .map(|proxy| proxy.no_proxy(no_proxy.as_deref().and_then(NoProxy::from_string)))
+        .collect())
+}
+
+fn proxy_env_var(env: &Env, name: &str) -> Option<String> {
+    [name.to_string(), name.to_lowercase()]
+        .into_iter()
+        .filter_map(|name| env.get_string_lossy(name))
+        .find(|value| !value.is_empty())
+}
+
+fn create_missing_ca_file_warning(name: &str, path: &Path) -> String {
+    let name = style::value(name);
+    let path = style::value(path.to_string_lossy());
+    formatdoc! { "
+        {name} is set to {path} but the file does not exist. Downloads will only trust the default \
+        certificate authorities.
+    " }
+}
+
+fn create_read_ca_file_error_message(ca_file: &CaFile, error: &std::io::Error) -> ErrorMessage {
+    let source = style::value(ca_file.source());
+    let path = style::value(ca_file.path().to_string_lossy());
+    error_message()
+        .id("network/ca_file/read")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::Yes,
+            SuggestSubmitIssue::No,
+        ))
+        .header("Error reading certificate authority file")
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack reads extra certificate authorities from {path}, which was \
+            set by {source}, but the file can't be read.
+
+            Suggestions:
+            - Ensure the file exists and has read permissions.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+fn create_invalid_ca_file_error_message(ca_file: &CaFile) -> ErrorMessage {
+    let source = style::value(ca_file.source());
+    let path = style::value(ca_file.path().to_string_lossy());
+    error_message()
+        .id("network/ca_file/invalid")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header("Invalid certificate authority file")
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack reads extra certificate authorities from {path}, which was \
+            set by {source}, but the file does not contain any PEM encoded certificates.
+
+            Suggestions:
+            - Ensure the file contains one or more certificates between \
+            `-----BEGIN CERTIFICATE-----` and `-----END CERTIFICATE-----` lines.
+        " })
+        .create()
+}
+
+fn create_write_ca_bundle_error_message(error: &std::io::Error) -> ErrorMessage {
+    error_message()
+        .id("network/ca_bundle/write")
+        .error_type(ErrorType::Internal)
+        .header("Error writing certificate authority bundle")
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack combines extra certificate authorities into a single file \
+            for the package manager but the file could not be written.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+fn create_invalid_proxy_error_message(name: &str, error: &reqwest::Error) -> ErrorMessage {
+    let name = style::value(name);
+    error_message()
+        .id("network/proxy/invalid")
+        .error_type(ErrorType::UserFacing(
+            SuggestRetryBuild::No,
+            SuggestSubmitIssue::No,
+        ))
+        .header(format!("Invalid {name} proxy"))
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack sends downloads through the proxy set by {name} but the \
+            value is not a valid proxy URL.
+
+            Suggestions:
+            - Set {name} to a URL like `http://proxy.example.com:8080`.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buildpack_config::ConfigValueSource;
+    use crate::utils::error_handling::test_util::assert_error_snapshot;
+    use std::time::Duration;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn ca_fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ca-certificates/internal-ca.pem")
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Env {
+        let mut env = Env::new();
+        for (name, value) in vars {
+            env.insert(name, value);
+        }
+        env
+    }
+
+    fn start_proxy_stand_in(path_suffix: &str) -> MockServer {
+        rustls::crypto::ring::default_provider()
+            .install_default()
+            .ok();
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+                Mock::given(method("GET"))
+                    .and(path(format!("/{path_suffix}")))
+                    .respond_with(ResponseTemplate::new(200).set_body_string("proxied"))
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+                server
+            })
+    }
+
+    fn get_with_proxies(env: &Env, url: &str) -> reqwest::Result<String> {
+        http::build_client(
+            Duration::from_secs(5),
+            &ClientConfig {
+                proxies: proxies(env).unwrap(),
+                ca_certificates: vec![],
+            },
+        )?
+        .get(url)
+        .send()?
+        .text()
+    }
+
+    #[test]
+    fn test_ca_files_from_config_and_env() {
+        let mut buildpack_config = BuildpackConfig::default();
+        buildpack_config.ca_file = Some(ConfigValue {
+            value: "certs/ca.pem".to_string(),
+            source: ConfigValueSource::ProjectToml,
+        });
+        let env = env(&[
+            ("NODE_EXTRA_CA_CERTS", "/etc/node-ca.pem"),
+            ("SSL_CERT_FILE", ""),
+        ]);
+        assert_eq!(
+            ca_files(Path::new("/workspace"), &env, &buildpack_config),
+            vec![
+                CaFile::Configured(PathBuf::from("/workspace/certs/ca.pem")),
+                CaFile::EnvVar("NODE_EXTRA_CA_CERTS", PathBuf::from("/etc/node-ca.pem")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_ca_files_combines_bundles() {
+        let ca_bundle = read_ca_files(&[
+            CaFile::Configured(ca_fixture()),
+            CaFile::EnvVar("SSL_CERT_FILE", ca_fixture()),
+        ])
+        .unwrap();
+        let pem = fs::read(ca_fixture()).unwrap();
+        assert_eq!(ca_bundle.certificates.len(), 2);
+        assert_eq!(ca_bundle.pem, [pem.as_slice(), pem.as_slice()].concat());
+    }
+
+    #[test]
+    fn test_read_ca_files_skips_missing_env_var_file() {
+        let ca_bundle = read_ca_files(&[CaFile::EnvVar(
+            "SSL_CERT_FILE",
+            PathBuf::from("/does/not/exist.pem"),
+        )])
+        .unwrap();
+        assert!(ca_bundle.certificates.is_empty());
+    }
+
+    #[test]
+    fn test_proxies_route_requests_through_proxy() {
+        let proxy = start_proxy_stand_in("proxied");
+        let env = env(&[("http_proxy", &proxy.uri())]);
+        // the host doesn't resolve so the request only succeeds if it's sent through the proxy
+        assert_eq!(
+            get_with_proxies(&env, "http://registry.example.invalid/proxied").unwrap(),
+            "proxied"
+        );
+    }
+
+    #[test]
+    fn test_proxies_skip_hosts_in_no_proxy() {
+        let server = start_proxy_stand_in("direct");
+        let unused_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let env = env(&[
+            ("HTTP_PROXY", &format!("http://127.0.0.1:{unused_port}")),
+            ("NO_PROXY", "127.0.0.1"),
+        ]);
+        assert_eq!(
+            get_with_proxies(&env, &format!("{}/direct", server.uri())).unwrap(),
+            "proxied"
+        );
+    }
+
+    #[test]
+    fn test_configure_network_read_ca_file_error() {
+        assert_error_snapshot(&create_read_ca_file_error_message(
+            &CaFile::Configured(PathBuf::from("/workspace/certs/ca.pem")),
+            &std::io::Error::other("Permission denied"),
+        ));
+    }
+
+    #[test]
+    fn test_configure_network_invalid_ca_file_error() {
+        let not_a_certificate = tempfile::NamedTempFile::new().unwrap();
+        fs::write(not_a_certificate.path(), "not a certificate").unwrap();
+        let ca_file = CaFile::EnvVar("SSL_CERT_FILE", not_a_certificate.path().to_path_buf());
+        let error = read_ca_files(&[ca_file]).unwrap_err();
+        assert_error_snapshot(&create_invalid_ca_file_error_message(&CaFile::EnvVar(
+            "SSL_CERT_FILE",
+            PathBuf::from("/etc/ssl/ca.pem"),
+        )));
+        assert!(
+            error
+                .to_string()
+                .contains("Invalid certificate authority file")
+        );
+    }
+
+    #[test]
+    fn test_configure_network_write_ca_bundle_error() {
+        assert_error_snapshot(&create_write_ca_bundle_error_message(
+            &std::io::Error::other("No space left on device"),
+        ));
+    }
+
+    #[test]
+    fn test_configure_network_invalid_proxy_error() {
+        let error = proxies(&env(&[("HTTPS_PROXY", "http://[invalid")])).unwrap_err();
+        assert_error_snapshot(&error);
+    }
+}
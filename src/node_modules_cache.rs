@@ -0,0 +1,485 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
+use crate::install_scripts::InstallScriptsPolicy;
+use crate::package_json::PackageJson;
+use crate::package_manager::InstalledPackageManager;
+use crate::target::TargetMetadata;
+use crate::utils::error_handling::ErrorType::Internal;
+use crate::utils::error_handling::{ErrorMessage, error_message};
+use crate::utils::fs::copy_dir;
+use crate::{BuildpackBuildContext, BuildpackResult};
+use bullet_stream::global::print;
+use bullet_stream::style;
+use fun_run::CommandWithName;
+use indoc::formatdoc;
+use libcnb::Env;
+use libcnb::data::layer_name;
+use libcnb::layer::{
+    CachedLayerDefinition, EmptyLayerCause, InvalidMetadataAction, LayerState, RestoredLayerAction,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+
+const LOCKFILES: [&str; 3] = ["package-lock.json", "yarn.lock", "pnpm-lock.yaml"];
+
+/// Files that change how the package manager lays out the installed tree.
+const CONFIG_FILES: [&str; 6] = [
+    ".npmrc",
+    ".yarnrc",
+    ".yarnrc.yml",
+    ".pnpmfile.cjs",
+    "pnpm-workspace.yaml",
+    "package.json",
+];
+
+/// Directories of patches applied to dependencies during the install by `patch-package`, pnpm's
+/// `patchedDependencies`, and Yarn's `patch:` protocol.
+const PATCH_DIRS: [&str; 2] = ["patches", ".yarn/patches"];
+
+const LAYER_VERSION: &str = "1";
+
+/// An opt-in cache of the installed dependency tree, enabled with `actions.cache_node_modules`.
+/// The tree is restored instead of running the install when every input to the install is unchanged.
+pub(crate) struct NodeModulesCache {
+    inputs: BTreeMap<String, String>,
+    /// Download caches that are empty for this build. The binaries they hold are downloaded by
+    /// dependency install scripts, which only run when the tree is installed.
+    empty_download_caches: Vec<String>,
+}
+
+/// Whether the dependencies were installed or restored from the cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Installation {
+    Installed,
+    Restored,
+}
+
+/// A directory written by the install that is saved to and restored from the cache.
+pub(crate) struct CachedDirectory {
+    pub(crate) name: &'static str,
+    pub(crate) path: PathBuf,
+}
+
+impl NodeModulesCache {
+    pub(crate) fn new(
+        context: &BuildpackBuildContext,
+        env: &Env,
+        package_json: &PackageJson,
+        buildpack_config: &BuildpackConfig,
+        package_manager: &InstalledPackageManager,
+        install_scripts: &InstallScriptsPolicy,
+        empty_download_caches: &[String],
+    ) -> Option<Self> {
+        if !matches!(
+            buildpack_config.cache_node_modules,
+            Some(ConfigValue { value: true, .. })
+        ) {
+            return None;
+        }
+
+        // workspace packages get their own node_modules directories which aren't cached
+        if package_json.has_workspaces() || context.app_dir.join("pnpm-workspace.yaml").exists() {
+            print::sub_bullet("Skipping node_modules cache as workspaces are not supported");
+            return None;
+        }
+
+        let node_abi = match node_abi(env) {
+            Ok(node_abi) => node_abi,
+            Err(error) => {
+                print::warning(formatdoc! { "
+                    The node_modules cache is disabled for this build because the Node.js ABI \
+                    version could not be determined: {error}
+                " });
+                return None;
+            }
+        };
+
+        let mut inputs = BTreeMap::new();
+        inputs.insert(
+            "lockfile".to_string(),
+            hash_files(&context.app_dir, &LOCKFILES),
+        );
+        inputs.insert(
+            "package manager config".to_string(),
+            hash_files(&context.app_dir, &CONFIG_FILES),
+        );
+        inputs.insert(
+            "patches".to_string(),
+            hash_dirs(&context.app_dir, &PATCH_DIRS),
+        );
+        inputs.insert("node abi".to_string(), node_abi);
+        inputs.insert(
+            "package manager".to_string(),
+            match package_manager {
+                InstalledPackageManager::Npm(version) => format!("npm {version}"),
+                InstalledPackageManager::Yarn(version) => format!("yarn {version}"),
+                InstalledPackageManager::Pnpm(version) => format!("pnpm {version}"),
+            },
+        );
+        inputs.insert(
+            "install scripts".to_string(),
+            match install_scripts {
+                InstallScriptsPolicy::RunAll => "all".to_string(),
+                InstallScriptsPolicy::AllowList(allowed) => {
+                    let allowed = allowed.iter().map(String::as_str).collect::<Vec<_>>();
+                    format!("allow_list: {}", allowed.join(", "))
+                }
+            },
+        );
+        inputs.insert(
+            "NODE_ENV".to_string(),
+            env.get_string_lossy("NODE_ENV").unwrap_or_default(),
+        );
+
+        Some(Self {
+            inputs,
+            empty_download_caches: empty_download_caches.to_vec(),
+        })
+    }
+
+    /// Restores the directories on an exact match, otherwise runs the install and saves the
+    /// directories for the next build. Failing to restore or save is never fatal.
+    pub(crate) fn install(
+        &self,
+        context: &BuildpackBuildContext,
+        directories: &[CachedDirectory],
+        install: impl FnOnce() -> BuildpackResult<()>,
+    ) -> BuildpackResult<Installation> {
+        let new_metadata = NodeModulesCacheMetadata {
+            layer_version: LAYER_VERSION.to_string(),
+            target: TargetMetadata::from(&context.target),
+            inputs: self.inputs.clone(),
+        };
+
+        let cache_layer = context.cached_layer(
+            layer_name!("node_modules_cache"),
+            CachedLayerDefinition {
+                build: false,
+                launch: false,
+                invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
+                restored_layer_action: &|old_metadata: &NodeModulesCacheMetadata, _| {
+                    let causes = changed_inputs(old_metadata, &new_metadata)
+                        .into_iter()
+                        .map(|input| format!("{input} changed"))
+                        .chain(
+                            self.empty_download_caches
+                                .iter()
+                                .map(|name| format!("{name} was cleared")),
+                        )
+                        .collect::<Vec<_>>();
+                    if causes.is_empty() {
+                        (RestoredLayerAction::KeepLayer, causes)
+                    } else {
+                        (RestoredLayerAction::DeleteLayer, causes)
+                    }
+                },
+            },
+        )?;
+
+        match cache_layer.state {
+            LayerState::Restored { .. } => {
+                print::sub_bullet("Restoring node_modules from cache");
+                match restore_directories(&cache_layer.path(), directories) {
+                    Ok(()) => {
+                        print::sub_bullet("Skipping install as the dependencies are unchanged");
+                        return Ok(Installation::Restored);
+                    }
+                    Err(error) => {
+                        print::warning(formatdoc! { "
+                            The cached node_modules could not be restored so dependencies will be \
+                            installed instead: {error}
+                        " });
+                        clear_directories(directories)
+                            .map_err(|e| create_clear_directories_error(directories, &e))?;
+                    }
+                }
+            }
+            LayerState::Empty { ref cause } => match cause {
+                EmptyLayerCause::RestoredLayerAction { cause } => {
+                    print::sub_bullet(format!(
+                        "Cached node_modules is outdated ({})",
+                        cause.join(", ")
+                    ));
+                }
+                _ => print::sub_bullet("No cached node_modules found"),
+            },
+        }
+
+        install()?;
+
+        // a fresh save replaces anything left over from a failed restore
+        let cache_dir = cache_layer.path();
+        let saved = clear_directories(
+            &directories
+                .iter()
+                .map(|directory| CachedDirectory {
+                    name: directory.name,
+                    path: cache_dir.join(directory.name),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .and_then(|()| save_directories(&cache_dir, directories));
+        match saved {
+            Ok(()) => {
+                cache_layer.write_metadata(new_metadata)?;
+                print::sub_bullet("Saved node_modules to the cache");
+            }
+            Err(error) => print::warning(formatdoc! { "
+                The installed node_modules could not be saved to the cache: {error}
+            " }),
+        }
+
+        Ok(Installation::Installed)
+    }
+}
+
+/// Runs the install through the cache when it's enabled.
+pub(crate) fn install_with_cache(
+    node_modules_cache: Option<&NodeModulesCache>,
+    context: &BuildpackBuildContext,
+    directories: &[CachedDirectory],
+    install: impl FnOnce() -> BuildpackResult<()>,
+) -> BuildpackResult<Installation> {
+    match node_modules_cache {
+        Some(node_modules_cache) => node_modules_cache.install(context, directories, install),
+        None => install().map(|()| Installation::Installed),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct NodeModulesCacheMetadata {
+    layer_version: String,
+    target: TargetMetadata,
+    inputs: BTreeMap<String, String>,
+}
+
+fn changed_inputs(old: &NodeModulesCacheMetadata, new: &NodeModulesCacheMetadata) -> Vec<String> {
+    let mut changed = new
+        .inputs
+        .iter()
+        .filter(|(name, value)| old.inputs.get(*name) != Some(value))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    if old.target != new.target {
+        changed.push("build target".to_string());
+    }
+    if old.layer_version != new.layer_version {
+        changed.push("layer version".to_string());
+    }
+    changed
+}
+
+pub(crate) fn node_abi(env: &Env) -> Result<String, fun_run::CmdError> {
+    Command::new("node")
+        .args(["--print", "process.versions.modules"])
+        .envs(env)
+        .named_output()
+        .map(|output| output.stdout_lossy().trim().to_string())
+}
+
+/// Hashes the name and contents of each file that exists so adding or removing a file also changes
+/// the hash.
+fn hash_files(dir: &Path, names: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for name in names {
+        if let Ok(contents) = fs::read(dir.join(name)) {
+            hasher.update(name.as_bytes());
+            hasher.update(Sha256::digest(&contents));
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Hashes the relative path and contents of every file within the directories that exist.
+fn hash_dirs(dir: &Path, names: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for name in names {
+        for entry in WalkDir::new(dir.join(name))
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+        {
+            if let (Ok(path), Ok(contents)) =
+                (entry.path().strip_prefix(dir), fs::read(entry.path()))
+            {
+                hasher.update(path.to_string_lossy().as_bytes());
+                hasher.update(Sha256::digest(&contents));
+            }
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn restore_directories(cache_dir: &Path, directories: &[CachedDirectory]) -> io::Result<()> {
+    clear_directories(directories)?;
+    for directory in directories {
+        copy_dir(&cache_dir.join(directory.name), &directory.path)?;
+    }
+    Ok(())
+}
+
+fn save_directories(cache_dir: &Path, directories: &[CachedDirectory]) -> io::Result<()> {
+    for directory in directories {
+        copy_dir(&directory.path, &cache_dir.join(directory.name))?;
+    }
+    Ok(())
+}
+
+/// Empties each directory without removing it since some are created by the package manager
+/// setup (e.g.; the pnpm virtual store).
+fn clear_directories(directories: &[CachedDirectory]) -> io::Result<()> {
+    for directory in directories {
+        match fs::read_dir(&directory.path) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        fs::remove_dir_all(entry.path())?;
+                    } else {
+                        fs::remove_file(entry.path())?;
+                    }
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+fn create_clear_directories_error(
+    directories: &[CachedDirectory],
+    error: &io::Error,
+) -> ErrorMessage {
+    let paths = directories
+        .iter()
+        .map(|directory| style::value(directory.path.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    error_message()
+        .id("node_modules_cache/clear")
+        .error_type(Internal)
+        .header("Failed to clear partially restored node_modules")
+        .body(formatdoc! { "
+            An unexpected I/O error occurred while removing the partially restored contents of \
+            {paths} before installing dependencies.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::error_handling::test_util::assert_error_snapshot;
+
+    fn metadata(inputs: &[(&str, &str)]) -> NodeModulesCacheMetadata {
+        NodeModulesCacheMetadata {
+            layer_version: LAYER_VERSION.to_string(),
+            target: TargetMetadata {
+                os: "linux".to_string(),
+                arch: "amd64".to_string(),
+                arch_variant: None,
+                distro_name: "ubuntu".to_string(),
+                distro_version: "24.04".to_string(),
+            },
+            inputs: inputs
+                .iter()
+                .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn changed_inputs_are_reported() {
+        let old = metadata(&[("lockfile", "a"), ("node abi", "127")]);
+        let mut new = metadata(&[("lockfile", "b"), ("node abi", "127")]);
+        assert_eq!(changed_inputs(&old, &new), vec!["lockfile"]);
+
+        new.target.arch = "arm64".to_string();
+        assert_eq!(changed_inputs(&old, &new), vec!["lockfile", "build target"]);
+    }
+
+    #[test]
+    fn hash_files_changes_with_contents_and_presence() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let empty = hash_files(app_dir.path(), &LOCKFILES);
+
+        fs::write(app_dir.path().join("yarn.lock"), "a").unwrap();
+        let with_lockfile = hash_files(app_dir.path(), &LOCKFILES);
+        assert_ne!(empty, with_lockfile);
+
+        fs::write(app_dir.path().join("yarn.lock"), "b").unwrap();
+        assert_ne!(with_lockfile, hash_files(app_dir.path(), &LOCKFILES));
+
+        fs::write(app_dir.path().join("yarn.lock"), "a").unwrap();
+        assert_eq!(with_lockfile, hash_files(app_dir.path(), &LOCKFILES));
+    }
+
+    #[test]
+    fn hash_dirs_changes_with_patches() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let empty = hash_dirs(app_dir.path(), &PATCH_DIRS);
+
+        fs::create_dir_all(app_dir.path().join("patches")).unwrap();
+        fs::write(app_dir.path().join("patches/left-pad+1.3.0.patch"), "a").unwrap();
+        let with_patch = hash_dirs(app_dir.path(), &PATCH_DIRS);
+        assert_ne!(empty, with_patch);
+
+        fs::write(app_dir.path().join("patches/left-pad+1.3.0.patch"), "b").unwrap();
+        assert_ne!(with_patch, hash_dirs(app_dir.path(), &PATCH_DIRS));
+
+        fs::write(app_dir.path().join("patches/left-pad+1.3.0.patch"), "a").unwrap();
+        fs::create_dir_all(app_dir.path().join(".yarn/patches")).unwrap();
+        fs::write(app_dir.path().join(".yarn/patches/react.patch"), "c").unwrap();
+        assert_ne!(with_patch, hash_dirs(app_dir.path(), &PATCH_DIRS));
+    }
+
+    #[test]
+    fn save_and_restore_directories() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let node_modules = app_dir.path().join("node_modules");
+        fs::create_dir_all(node_modules.join("a/bin")).unwrap();
+        fs::write(node_modules.join("a/bin/cli.js"), "cli").unwrap();
+        fs::create_dir_all(node_modules.join(".bin")).unwrap();
+        std::os::unix::fs::symlink("../a/bin/cli.js", node_modules.join(".bin/a")).unwrap();
+        let directories = [CachedDirectory {
+            name: "node_modules",
+            path: node_modules.clone(),
+        }];
+
+        save_directories(cache_dir.path(), &directories).unwrap();
+        fs::remove_dir_all(node_modules.join("a")).unwrap();
+        fs::write(node_modules.join("stale.js"), "stale").unwrap();
+        restore_directories(cache_dir.path(), &directories).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(node_modules.join(".bin/a")).unwrap(),
+            "cli"
+        );
+        assert_eq!(
+            fs::read_link(node_modules.join(".bin/a")).unwrap(),
+            Path::new("../a/bin/cli.js")
+        );
+        assert!(!node_modules.join("stale.js").exists());
+    }
+
+    #[test]
+    fn clear_directories_error() {
+        assert_error_snapshot(&create_clear_directories_error(
+            &[CachedDirectory {
+                name: "node_modules",
+                path: PathBuf::from("/workspace/node_modules"),
+            }],
+            &io::Error::other("Test I/O error"),
+        ));
+    }
+}
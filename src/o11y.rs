@@ -18,6 +18,8 @@ pub(crate) const RUNTIME_REQUESTED_NAME: &str = formatcp!("{RUNTIME}.requested_n
 
 pub(crate) const RUNTIME_REQUESTED_VERSION: &str = formatcp!("{RUNTIME}.requested_version");
 
+pub(crate) const RUNTIME_REQUESTED_SOURCES: &str = formatcp!("{RUNTIME}.requested_sources");
+
 pub(crate) const RUNTIME_NAME: &str = formatcp!("{RUNTIME}.name");
 
 pub(crate) const RUNTIME_VERSION: &str = formatcp!("{RUNTIME}.version");
@@ -28,6 +30,12 @@ pub(crate) const RUNTIME_URL: &str = formatcp!("{RUNTIME}.url");
 
 pub(crate) const RUNTIME_SUPPORT_STATUS: &str = formatcp!("{RUNTIME}.support_status");
 
+pub(crate) const RUNTIME_PREVIOUS_VERSION: &str = formatcp!("{RUNTIME}.previous_version");
+
+pub(crate) const RUNTIME_LATEST_VERSION: &str = formatcp!("{RUNTIME}.latest_version");
+
+pub(crate) const RUNTIME_MAJOR_RESOLUTION: &str = formatcp!("{RUNTIME}.major_resolution");
+
 const PACKAGE_MANAGER: &str = formatcp!("{NAMESPACE}.package_manager");
 
 pub(crate) const PACKAGE_MANAGER_REQUESTED_SOURCE: &str =
@@ -51,6 +59,46 @@ pub(crate) const CONFIG_BUILD_SCRIPT_ENABLED_SOURCE: &str =
     formatcp!("{CONFIG}.build_script_enabled_source");
 pub(crate) const CONFIG_BUILD_SCRIPT_ENABLED_VALUE: &str =
     formatcp!("{CONFIG}.build_script_enabled_value");
+pub(crate) const CONFIG_INSTALL_SCRIPTS_SOURCE: &str = formatcp!("{CONFIG}.install_scripts_source");
+pub(crate) const CONFIG_INSTALL_SCRIPTS_VALUE: &str = formatcp!("{CONFIG}.install_scripts_value");
+pub(crate) const CONFIG_NODE_ENV_SOURCE: &str = formatcp!("{CONFIG}.node_env_source");
+pub(crate) const CONFIG_NODE_ENV_VALUE: &str = formatcp!("{CONFIG}.node_env_value");
+pub(crate) const CONFIG_COMPILE_CACHE_SOURCE: &str = formatcp!("{CONFIG}.compile_cache_source");
+pub(crate) const CONFIG_COMPILE_CACHE_VALUE: &str = formatcp!("{CONFIG}.compile_cache_value");
+pub(crate) const CONFIG_CACHE_NODE_MODULES_SOURCE: &str =
+    formatcp!("{CONFIG}.cache_node_modules_source");
+pub(crate) const CONFIG_CACHE_NODE_MODULES_VALUE: &str =
+    formatcp!("{CONFIG}.cache_node_modules_value");
+pub(crate) const CONFIG_CACHE_NATIVE_MODULES_SOURCE: &str =
+    formatcp!("{CONFIG}.cache_native_modules_source");
+pub(crate) const CONFIG_CACHE_NATIVE_MODULES_VALUE: &str =
+    formatcp!("{CONFIG}.cache_native_modules_value");
+pub(crate) const CONFIG_SLIM_RUNTIME_SOURCE: &str = formatcp!("{CONFIG}.slim_runtime_source");
+pub(crate) const CONFIG_SLIM_RUNTIME_VALUE: &str = formatcp!("{CONFIG}.slim_runtime_value");
+pub(crate) const CONFIG_CA_FILE_SOURCE: &str = formatcp!("{CONFIG}.ca_file_source");
+pub(crate) const CONFIG_CA_FILE_VALUE: &str = formatcp!("{CONFIG}.ca_file_value");
+pub(crate) const CONFIG_CACHE_RESET_SOURCE: &str = formatcp!("{CONFIG}.cache_reset_source");
+pub(crate) const CONFIG_CACHE_RESET_VALUE: &str = formatcp!("{CONFIG}.cache_reset_value");
+pub(crate) const CONFIG_COMMITTED_NODE_MODULES_SOURCE: &str =
+    formatcp!("{CONFIG}.committed_node_modules_source");
+pub(crate) const CONFIG_COMMITTED_NODE_MODULES_VALUE: &str =
+    formatcp!("{CONFIG}.committed_node_modules_value");
+pub(crate) const CONFIG_STRICT_DEPENDENCY_ENGINES_SOURCE: &str =
+    formatcp!("{CONFIG}.strict_dependency_engines_source");
+pub(crate) const CONFIG_STRICT_DEPENDENCY_ENGINES_VALUE: &str =
+    formatcp!("{CONFIG}.strict_dependency_engines_value");
+pub(crate) const CONFIG_ALLOW_MAJOR_UPGRADES_SOURCE: &str =
+    formatcp!("{CONFIG}.allow_major_upgrades_source");
+pub(crate) const CONFIG_ALLOW_MAJOR_UPGRADES_VALUE: &str =
+    formatcp!("{CONFIG}.allow_major_upgrades_value");
+
+const CACHE_RESET: &str = formatcp!("{NAMESPACE}.cache_reset");
+
+pub(crate) const CACHE_RESET_REQUESTED: &str = formatcp!("{CACHE_RESET}.requested");
+
+pub(crate) const CACHE_RESET_REASON: &str = formatcp!("{CACHE_RESET}.reason");
+
+pub(crate) const CACHE_RESET_CLEARED_LAYERS: &str = formatcp!("{CACHE_RESET}.cleared_layers");
 
 const BUILD_SCRIPTS: &str = formatcp!("{NAMESPACE}.build_scripts");
 
@@ -60,6 +108,36 @@ pub(crate) const BUILD_SCRIPTS_BUILD: &str = formatcp!("{BUILD_SCRIPTS}.build");
 
 pub(crate) const BUILD_SCRIPTS_POSTBUILD: &str = formatcp!("{BUILD_SCRIPTS}.postbuild");
 
+const INSTALL_SCRIPTS: &str = formatcp!("{NAMESPACE}.install_scripts");
+
+pub(crate) const INSTALL_SCRIPTS_ALLOWED: &str = formatcp!("{INSTALL_SCRIPTS}.allowed");
+
+pub(crate) const INSTALL_SCRIPTS_BUILT: &str = formatcp!("{INSTALL_SCRIPTS}.built");
+
+pub(crate) const INSTALL_SCRIPTS_SKIPPED: &str = formatcp!("{INSTALL_SCRIPTS}.skipped");
+
+pub(crate) const INSTALL_SCRIPTS_RESTORED: &str = formatcp!("{INSTALL_SCRIPTS}.restored");
+
+const DOWNLOAD_CACHES: &str = formatcp!("{NAMESPACE}.download_caches");
+
+pub(crate) const DOWNLOAD_CACHES_DETECTED: &str = formatcp!("{DOWNLOAD_CACHES}.detected");
+
+const COMMITTED_NODE_MODULES: &str = formatcp!("{NAMESPACE}.committed_node_modules");
+
+pub(crate) const COMMITTED_NODE_MODULES_DETECTED: &str =
+    formatcp!("{COMMITTED_NODE_MODULES}.detected");
+
+pub(crate) const COMMITTED_NODE_MODULES_MODE: &str = formatcp!("{COMMITTED_NODE_MODULES}.mode");
+
+const DEPENDENCY_ENGINES: &str = formatcp!("{NAMESPACE}.dependency_engines");
+
+pub(crate) const DEPENDENCY_ENGINES_INCOMPATIBLE: &str =
+    formatcp!("{DEPENDENCY_ENGINES}.incompatible");
+
+const PRUNE_CHECK: &str = formatcp!("{NAMESPACE}.prune_check");
+
+pub(crate) const PRUNE_CHECK_MISSING_BINARIES: &str = formatcp!("{PRUNE_CHECK}.missing_binaries");
+
 const DEPENDENCIES: &str = formatcp!("{NAMESPACE}.dependencies");
 
 pub(crate) const DEPENDENCIES_REQUESTED_PACKAGE_NAME: &str =
@@ -54,6 +54,83 @@ impl PackageJson {
             .map(PackageManagerField::from_str)
     }
 
+    pub(crate) fn has_workspaces(&self) -> bool {
+        self.0.get("workspaces").is_some()
+    }
+
+    /// The npm and Yarn `workspaces` patterns, from either the array form or the `packages` field
+    /// of the object form.
+    pub(crate) fn workspace_patterns(&self) -> Vec<String> {
+        self.0
+            .get("workspaces")
+            .and_then(|workspaces| {
+                workspaces
+                    .as_array()
+                    .or_else(|| workspaces["packages"].as_array())
+            })
+            .map(|patterns| {
+                patterns
+                    .iter()
+                    .filter_map(|pattern| pattern.as_str().map(ToString::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Package names listed in `pnpm.onlyBuiltDependencies`.
+    pub(crate) fn pnpm_only_built_dependencies(&self) -> Vec<String> {
+        self.0
+            .get("pnpm")
+            .and_then(|pnpm| pnpm.get("onlyBuiltDependencies"))
+            .and_then(|val| val.as_array())
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.as_str().map(ToString::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Package names from `dependenciesMeta` entries that are marked with `"built": true`. Entries
+    /// may be keyed as `name` or `name@version`, the version portion is dropped.
+    pub(crate) fn built_dependencies_meta(&self) -> Vec<String> {
+        self.0
+            .get("dependenciesMeta")
+            .and_then(|val| val.as_object())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|(_, meta)| {
+                        meta.get("built")
+                            .and_then(serde_json::Value::as_bool)
+                            .unwrap_or(false)
+                    })
+                    .map(|(key, _)| match key.rfind('@') {
+                        Some(index) if index > 0 => key[..index].to_string(),
+                        _ => key.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn dependencies(&self) -> Vec<String> {
+        self.dependency_names("dependencies")
+    }
+
+    pub(crate) fn dev_dependencies(&self) -> Vec<String> {
+        self.dependency_names("devDependencies")
+    }
+
+    fn dependency_names(&self, dependency_type: &str) -> Vec<String> {
+        self.0
+            .get(dependency_type)
+            .and_then(|val| val.as_object())
+            .map(|deps| deps.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
     pub(crate) fn script(&self, name: impl AsRef<str>) -> Option<(String, String)> {
         self.0
             .get("scripts")
@@ -300,6 +377,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn read_pnpm_only_built_dependencies() {
+        let package_json = PackageJson(json!({
+            "pnpm": {
+                "onlyBuiltDependencies": ["esbuild", "sharp", 1]
+            }
+        }));
+        assert_eq!(
+            package_json.pnpm_only_built_dependencies(),
+            vec!["esbuild".to_string(), "sharp".to_string()]
+        );
+        assert!(
+            PackageJson(json!({}))
+                .pnpm_only_built_dependencies()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn read_built_dependencies_meta() {
+        let package_json = PackageJson(json!({
+            "dependenciesMeta": {
+                "sharp": { "built": true },
+                "@swc/core@1.3.0": { "built": true },
+                "fsevents": { "optional": true },
+                "core-js": { "built": false }
+            }
+        }));
+        let mut built = package_json.built_dependencies_meta();
+        built.sort();
+        assert_eq!(built, vec!["@swc/core".to_string(), "sharp".to_string()]);
+    }
+
     #[test]
     fn read_error_message() {
         assert_error_snapshot(&package_json_read_error_message(
@@ -1,7 +1,11 @@
 use crate::buildpack_config::{BuildpackConfig, ConfigValue, ConfigValueSource};
+use crate::install_scripts::InstallScriptsPolicy;
+use crate::native_modules_cache::NativeModulesCache;
+use crate::node_modules_cache::NodeModulesCache;
 use crate::o11y::*;
 use crate::package_json::{PackageJson, PackageManagerField, PackageManagerFieldPackageManager};
 use crate::package_managers::{npm, pnpm, yarn};
+use crate::prune_check::{DevDependencyBinaries, verify_process_binaries};
 use crate::runtimes::nodejs;
 use crate::utils::error_handling::{
     ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message,
@@ -15,7 +19,6 @@ use libcnb::Env;
 use libcnb::build::BuildResultBuilder;
 use libcnb::data::launch::{LaunchBuilder, ProcessBuilder};
 use libcnb::data::process_type;
-use libcnb::data::store::Store;
 use nodejs_data::{Version, VersionRange};
 use std::path::{Path, PathBuf};
 use tracing::instrument;
@@ -195,6 +198,45 @@ pub(crate) fn log_requested_package_manager(requested_package_manager: &Requeste
     }
 }
 
+/// Starts downloading the packument and package tarball for the requested package manager in the
+/// background so they don't have to wait on the Node.js installation. The resolved Node.js version
+/// is used for the `engines.node` check since `node` isn't installed yet. The regular resolve and
+/// install steps pick up the responses and report them in their usual place in the output.
+pub(crate) fn prefetch_package_manager(
+    context: &BuildpackBuildContext,
+    requested_package_manager: &RequestedPackageManager,
+    node_version: &Version,
+) {
+    match requested_package_manager {
+        RequestedPackageManager::BundledNpm | RequestedPackageManager::YarnVendored(_) => {}
+        RequestedPackageManager::NpmEngine(requirement) => {
+            npm::prefetch_npm_package(context, requirement, node_version);
+        }
+        RequestedPackageManager::PnpmEngine(requirement) => {
+            pnpm::prefetch_pnpm_package(context, requirement, node_version);
+        }
+        RequestedPackageManager::YarnEngine(requirement)
+        | RequestedPackageManager::YarnDefault(requirement) => {
+            yarn::prefetch_yarn_package(context, requirement, node_version);
+        }
+        RequestedPackageManager::PackageManager(package_manager_field) => {
+            let requirement = VersionRange::parse(&package_manager_field.version.to_string())
+                .expect("Exact version string should be a valid requirement range");
+            match package_manager_field.name {
+                PackageManagerFieldPackageManager::Npm => {
+                    npm::prefetch_npm_package(context, &requirement, node_version);
+                }
+                PackageManagerFieldPackageManager::Pnpm => {
+                    pnpm::prefetch_pnpm_package(context, &requirement, node_version);
+                }
+                PackageManagerFieldPackageManager::Yarn => {
+                    yarn::prefetch_yarn_package(context, &requirement, node_version);
+                }
+            }
+        }
+    }
+}
+
 pub(crate) enum ResolvedPackageManager {
     Npm(VersionRange, PackagePackument),
     NpmBundled(Version),
@@ -204,11 +246,14 @@ pub(crate) enum ResolvedPackageManager {
 }
 
 #[instrument(skip_all)]
+#[allow(clippy::too_many_lines)]
 pub(crate) fn resolve_package_manager(
     context: &BuildpackBuildContext,
     env: &mut Env,
     requested_package_manager: &RequestedPackageManager,
 ) -> BuildpackResult<ResolvedPackageManager> {
+    // the resolved version is checked against the installed Node.js before it's installed
+    let node_version = nodejs::get_node_version(env)?;
     match requested_package_manager {
         RequestedPackageManager::BundledNpm => {
             let npm_version = npm::get_version(env)?;
@@ -221,18 +266,20 @@ pub(crate) fn resolve_package_manager(
             Ok(ResolvedPackageManager::NpmBundled(npm_version))
         }
         RequestedPackageManager::NpmEngine(requirement) => {
-            npm::resolve_npm_package_packument(context, requirement).map(|npm_package_packument| {
-                tracing::info!({
-                    { PACKAGE_MANAGER_NAME } = "npm",
-                    { PACKAGE_MANAGER_VERSION } = npm_package_packument.version.to_string(),
-                    { PACKAGE_MANAGER_VERSION_MAJOR } = npm_package_packument.version.major(),
-                    "package_manager"
-                });
-                ResolvedPackageManager::Npm(requirement.clone(), npm_package_packument)
-            })
+            npm::resolve_npm_package_packument(context, requirement, &node_version).map(
+                |npm_package_packument| {
+                    tracing::info!({
+                        { PACKAGE_MANAGER_NAME } = "npm",
+                        { PACKAGE_MANAGER_VERSION } = npm_package_packument.version.to_string(),
+                        { PACKAGE_MANAGER_VERSION_MAJOR } = npm_package_packument.version.major(),
+                        "package_manager"
+                    });
+                    ResolvedPackageManager::Npm(requirement.clone(), npm_package_packument)
+                },
+            )
         }
         RequestedPackageManager::PnpmEngine(requirement) => {
-            pnpm::resolve_pnpm_package_packument(context, requirement).map(
+            pnpm::resolve_pnpm_package_packument(context, requirement, &node_version).map(
                 |pnpm_package_packument| {
                     tracing::info!({
                         { PACKAGE_MANAGER_NAME } = "pnpm",
@@ -246,7 +293,7 @@ pub(crate) fn resolve_package_manager(
         }
         RequestedPackageManager::YarnEngine(requirement)
         | RequestedPackageManager::YarnDefault(requirement) => {
-            yarn::resolve_yarn_package_packument(context, requirement).map(
+            yarn::resolve_yarn_package_packument(context, requirement, &node_version).map(
                 |yarn_package_packument| {
                     tracing::info!({
                         { PACKAGE_MANAGER_NAME } = "yarn",
@@ -269,6 +316,7 @@ pub(crate) fn resolve_package_manager(
                 PackageManagerFieldPackageManager::Npm => npm::resolve_npm_package_packument(
                     context,
                     &requirement,
+                    &node_version,
                 )
                 .map(|npm_package_packument| {
                     tracing::info!({
@@ -282,6 +330,7 @@ pub(crate) fn resolve_package_manager(
                 PackageManagerFieldPackageManager::Pnpm => pnpm::resolve_pnpm_package_packument(
                     context,
                     &requirement,
+                    &node_version,
                 )
                 .map(|pnpm_package_packument| {
                     tracing::info!({
@@ -295,6 +344,7 @@ pub(crate) fn resolve_package_manager(
                 PackageManagerFieldPackageManager::Yarn => yarn::resolve_yarn_package_packument(
                     context,
                     &requirement,
+                    &node_version,
                 )
                 .map(|yarn_package_packument| {
                     tracing::info!({
@@ -429,18 +479,45 @@ pub(crate) enum InstalledPackageManager {
 pub(crate) fn install_dependencies(
     context: &BuildpackBuildContext,
     env: &mut Env,
-    store: &mut Store,
     installed_package_manager: &InstalledPackageManager,
+    package_json: &PackageJson,
+    install_scripts: &InstallScriptsPolicy,
+    node_modules_cache: Option<&NodeModulesCache>,
+    native_modules_cache: Option<&NativeModulesCache>,
 ) -> BuildpackResult<()> {
     match installed_package_manager {
         InstalledPackageManager::Npm(version) => {
-            npm::install_npm_dependencies(context, env, version)?;
+            npm::install_npm_dependencies(
+                context,
+                env,
+                version,
+                package_json,
+                install_scripts,
+                node_modules_cache,
+                native_modules_cache,
+            )?;
         }
         InstalledPackageManager::Yarn(version) => {
-            yarn::install_dependencies(context, env, version)?;
+            yarn::install_dependencies(
+                context,
+                env,
+                version,
+                package_json,
+                install_scripts,
+                node_modules_cache,
+                native_modules_cache,
+            )?;
         }
         InstalledPackageManager::Pnpm(version) => {
-            pnpm::install_dependencies(context, env, store, version)?;
+            pnpm::install_dependencies(
+                context,
+                env,
+                version,
+                package_json,
+                install_scripts,
+                node_modules_cache,
+                native_modules_cache,
+            )?;
         }
     }
     Ok(())
@@ -517,6 +594,9 @@ pub(crate) fn run_build_scripts(
 }
 
 fn create_run_script_error_message(script: &str, error: &fun_run::CmdError) -> ErrorMessage {
+    if is_out_of_memory_error(error) {
+        return create_run_script_out_of_memory_error_message(script, error);
+    }
     let script = style::value(script);
     let script_command = style::command(error.name());
     let package_json = style::value("package.json");
@@ -544,11 +624,54 @@ fn create_run_script_error_message(script: &str, error: &fun_run::CmdError) -> E
         .create()
 }
 
+/// Node.js reports V8 heap exhaustion with a `FATAL ERROR: ... JavaScript heap out of memory` line
+/// before aborting the process.
+fn is_out_of_memory_error(error: &fun_run::CmdError) -> bool {
+    match error {
+        fun_run::CmdError::NonZeroExitNotStreamed(output)
+        | fun_run::CmdError::NonZeroExitAlreadyStreamed(output) => {
+            [output.stderr_lossy(), output.stdout_lossy()]
+                .iter()
+                .any(|output| output.contains("JavaScript heap out of memory"))
+        }
+        fun_run::CmdError::SystemError(_, _) => false,
+    }
+}
+
+fn create_run_script_out_of_memory_error_message(
+    script: &str,
+    error: &fun_run::CmdError,
+) -> ErrorMessage {
+    let script = style::value(script);
+    let script_command = style::command(error.name());
+    let node_options = style::value("NODE_OPTIONS");
+    let max_old_space_size = style::value("NODE_OPTIONS=--max-old-space-size=<size in MB>");
+    error_message()
+        .id("package_manager/run_script/out_of_memory")
+        .error_type(ErrorType::UserFacing(SuggestRetryBuild::No, SuggestSubmitIssue::No))
+        .header(format!("Failed to execute build script - {script} (out of memory)"))
+        .body(formatdoc! { "
+            The Node.js process started by {script_command} ran out of JavaScript heap memory. Large frontend \
+            or TypeScript builds can require more memory than the build environment provides.
+
+            Unless {node_options} is already set, the buildpack limits the heap size to 75% of the memory \
+            detected for the build environment.
+
+            Suggestions:
+            - Set {max_old_space_size} to adjust the heap size for the build.
+            - Use a build environment with more memory.
+            - Reduce the memory used by the build (e.g. disable source maps or type-check in a separate step).
+        "})
+        .debug_info(error.to_string())
+        .create()
+}
+
 #[instrument(skip_all)]
 pub(crate) fn prune_dev_dependencies(
     context: &BuildpackBuildContext,
     env: &Env,
     package_manager: &InstalledPackageManager,
+    package_json: &PackageJson,
     buildpack_config: &BuildpackConfig,
 ) -> BuildpackResult<()> {
     print::bullet("Pruning dev dependencies");
@@ -569,6 +692,8 @@ pub(crate) fn prune_dev_dependencies(
         return Ok(());
     }
 
+    let dev_dependency_binaries = DevDependencyBinaries::collect(&context.app_dir, package_json);
+
     match package_manager {
         InstalledPackageManager::Npm(_) => {
             npm::prune_dev_dependencies(env, create_prune_dev_dependencies_error_message)
@@ -586,6 +711,8 @@ pub(crate) fn prune_dev_dependencies(
         ),
     }?;
 
+    verify_process_binaries(&context.app_dir, package_json, &dev_dependency_binaries);
+
     Ok(())
 }
 
@@ -661,6 +788,8 @@ pub(crate) fn configure_default_processes(
 mod tests {
     use super::*;
     use crate::utils::error_handling::test_util::{assert_error_snapshot, create_cmd_error};
+    use fun_run::{ExitStatusFromCode, OutputWithName};
+    use std::process::{ExitStatus, Output};
 
     #[test]
     fn run_script_error_message() {
@@ -670,6 +799,21 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn run_script_out_of_memory_error_message() {
+        assert_error_snapshot(&create_run_script_error_message(
+            "build",
+            &Output {
+                status: ExitStatus::from_code(134),
+                stdout: Vec::new(),
+                stderr: "FATAL ERROR: Reached heap limit Allocation failed - JavaScript heap out of memory\n".into(),
+            }
+            .named("<package_manager> run build")
+            .nonzero_captured()
+            .unwrap_err(),
+        ));
+    }
+
     #[test]
     fn prune_dev_dependencies_error_message() {
         assert_error_snapshot(&create_prune_dev_dependencies_error_message(
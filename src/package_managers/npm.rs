@@ -1,4 +1,15 @@
+use crate::cache_policy::{CacheLayerRef, CachePolicy, CacheUsage, DAY, MEBIBYTE, TrackedCache};
 use crate::cleanup::{CleanupTask, NodeGypArtifactLocation};
+use crate::install_scripts::{
+    InstallScriptsPolicy, ROOT_LIFECYCLE_SCRIPTS, are_install_scripts_deferred,
+    run_app_lifecycle_scripts, run_deferred_install_scripts,
+};
+use crate::native_modules_cache::NativeModulesCache;
+use crate::node_modules_cache::{
+    CachedDirectory, Installation, NodeModulesCache, install_with_cache,
+};
+use crate::package_json::PackageJson;
+use crate::target::TargetMetadata;
 use crate::utils::build_env::node_gyp_env;
 use crate::utils::error_handling::ErrorType::Internal;
 use crate::utils::error_handling::{
@@ -12,23 +23,37 @@ use fun_run::CommandWithName;
 use indoc::formatdoc;
 use libcnb::Env;
 use libcnb::data::layer_name;
-use libcnb::layer::{
-    CachedLayerDefinition, EmptyLayerCause, InvalidMetadataAction, LayerState, RestoredLayerAction,
-};
+use libcnb::layer::{CachedLayerDefinition, InvalidMetadataAction, LayerState};
 use nodejs_data::{Version, VersionCommandError, VersionRange};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
 use std::process::Command;
 
 pub(crate) fn resolve_npm_package_packument(
     context: &BuildpackBuildContext,
     requirement: &VersionRange,
+    node_version: &Version,
 ) -> BuildpackResult<npm_registry::PackagePackument> {
-    npm_registry::resolve_package_packument(
-        &npm_registry::packument_layer(layer_name!("npm_packument"), context, "npm")?,
+    let packument = npm_registry::packument_layer(layer_name!("npm_packument"), context, "npm")?;
+    let package_packument = npm_registry::resolve_package_packument(&packument, requirement)?;
+    npm_registry::check_node_engine(&packument, &package_packument, node_version)?;
+    Ok(package_packument)
+}
+
+pub(crate) fn prefetch_npm_package(
+    context: &BuildpackBuildContext,
+    requirement: &VersionRange,
+    node_version: &Version,
+) {
+    // the bundled npm version isn't known until Node.js is installed, so the tarball is fetched even
+    // in the rare case where the requested version turns out to already be bundled
+    npm_registry::prefetch_package(
+        context,
+        &layer_name!("npm_packument"),
+        &layer_name!("npm_engine"),
+        "npm",
         requirement,
-    )
-    .map_err(Into::into)
+        node_version,
+    );
 }
 
 pub(crate) fn get_version(env: &Env) -> BuildpackResult<Version> {
@@ -85,6 +110,10 @@ pub(crate) fn install_npm_dependencies(
     context: &BuildpackBuildContext,
     env: &Env,
     npm_version: &Version,
+    package_json: &PackageJson,
+    install_scripts: &InstallScriptsPolicy,
+    node_modules_cache: Option<&NodeModulesCache>,
+    native_modules_cache: Option<&NativeModulesCache>,
 ) -> BuildpackResult<()> {
     print::bullet("Installing node modules");
     print::sub_bullet(format!(
@@ -92,7 +121,8 @@ pub(crate) fn install_npm_dependencies(
         style::value(npm_version.to_string())
     ));
 
-    let cache_dir = create_cache_directory(context)?;
+    let (npm_cache_layer, mut npm_cache) = create_cache_directory(context)?;
+    let cache_dir = npm_cache_layer.path();
 
     print::sub_bullet("Configuring npm cache directory");
     Command::new("npm")
@@ -107,13 +137,49 @@ pub(crate) fn install_npm_dependencies(
         .named_output()
         .map_err(|e| create_set_npm_cache_directory_command_error(&e))?;
 
-    print::sub_stream_cmd(
-        Command::new("npm")
-            .args(["ci"])
-            .envs(env)
-            .envs(node_gyp_env()),
-    )
-    .map_err(|e| create_npm_install_error(&e))?;
+    let node_modules = CachedDirectory {
+        name: "node_modules",
+        path: context.app_dir.join("node_modules"),
+    };
+    let installation = install_with_cache(node_modules_cache, context, &[node_modules], || {
+        let mut npm_ci_command = Command::new("npm");
+        npm_ci_command.arg("ci");
+        if are_install_scripts_deferred(install_scripts, native_modules_cache) {
+            npm_ci_command.arg("--ignore-scripts");
+        }
+        print::sub_stream_cmd(npm_ci_command.envs(env).envs(node_gyp_env()))
+            .map_err(|e| create_npm_install_error(&e))?;
+
+        run_deferred_install_scripts(
+            install_scripts,
+            native_modules_cache,
+            &[context.app_dir.join("node_modules")],
+            |packages| rebuild_command(env, packages),
+        )?;
+        Ok(())
+    })?;
+    // the app's own lifecycle scripts don't run when scripts were disabled for the install or the
+    // install was skipped by restoring the cache
+    if installation == Installation::Restored
+        || are_install_scripts_deferred(install_scripts, native_modules_cache)
+    {
+        run_app_lifecycle_scripts(
+            &context.app_dir,
+            package_json,
+            &ROOT_LIFECYCLE_SCRIPTS,
+            |script| run_script(script, env),
+        )?;
+    }
+
+    if npm_cache.is_prune_due() {
+        print::sub_stream_cmd(Command::new("npm").args(["cache", "verify"]).envs(env))
+            .map_err(|e| create_verify_npm_cache_command_error(&e))?;
+    }
+    npm_cache.record_size();
+    npm_cache_layer.write_metadata(NpmCacheDirectoryLayerMetadata::new(
+        context,
+        npm_cache.usage(),
+    ))?;
 
     context.register_cleanup(CleanupTask::NodeGypMakefiles(
         NodeGypArtifactLocation::AppDir(context.app_dir.clone()),
@@ -122,41 +188,56 @@ pub(crate) fn install_npm_dependencies(
     Ok(())
 }
 
-fn create_cache_directory(context: &BuildpackBuildContext) -> BuildpackResult<PathBuf> {
-    let new_metadata = NpmCacheDirectoryLayerMetadata {
-        layer_version: NPM_CACHE_DIRECTORY_LAYER_VERSION.to_string(),
-    };
+fn create_cache_directory(
+    context: &BuildpackBuildContext,
+) -> BuildpackResult<(CacheLayerRef, TrackedCache)> {
+    let target = TargetMetadata::from(&context.target);
 
     let npm_cache_layer = context.cached_layer(
         layer_name!("npm_cache"),
         CachedLayerDefinition {
             build: true,
             launch: false,
-            invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
-            restored_layer_action: &|old_metadata: &NpmCacheDirectoryLayerMetadata, _| {
-                if old_metadata == &new_metadata {
-                    RestoredLayerAction::KeepLayer
-                } else {
-                    RestoredLayerAction::DeleteLayer
+            invalid_metadata_action: &|metadata| {
+                // caches from before the cache policy are kept and start tracking usage
+                match metadata.clone().and_then(|metadata| {
+                    metadata
+                        .try_into::<LegacyNpmCacheDirectoryLayerMetadata>()
+                        .ok()
+                }) {
+                    Some(legacy_metadata) => {
+                        InvalidMetadataAction::ReplaceMetadata(NpmCacheDirectoryLayerMetadata {
+                            layer_version: legacy_metadata.layer_version,
+                            target: target.clone(),
+                            usage: CacheUsage::migrated(0.0),
+                        })
+                    }
+                    None => InvalidMetadataAction::DeleteLayer,
                 }
             },
+            restored_layer_action: &|old_metadata: &NpmCacheDirectoryLayerMetadata, path| {
+                NPM_CACHE_POLICY.restored_layer_action(
+                    old_metadata.layer_version == NPM_CACHE_DIRECTORY_LAYER_VERSION
+                        && old_metadata.target == target,
+                    &old_metadata.usage,
+                    path,
+                )
+            },
         },
     )?;
 
-    match npm_cache_layer.state {
-        LayerState::Restored { .. } => {
-            print::sub_bullet("Restoring npm cache");
-        }
-        LayerState::Empty { cause } => {
-            if let EmptyLayerCause::RestoredLayerAction { .. } = cause {
-                print::sub_bullet("Restoring npm cache");
-            }
-            print::sub_bullet("Creating npm cache");
-            npm_cache_layer.write_metadata(new_metadata)?;
-        }
+    let npm_cache = NPM_CACHE_POLICY.track(&npm_cache_layer.state, &npm_cache_layer.path());
+    if let LayerState::Restored { .. } = npm_cache_layer.state {
+        print::sub_bullet("Restoring npm cache");
+    } else {
+        print::sub_bullet("Creating npm cache");
     }
+    npm_cache_layer.write_metadata(NpmCacheDirectoryLayerMetadata::new(
+        context,
+        npm_cache.usage(),
+    ))?;
 
-    Ok(npm_cache_layer.path().clone())
+    Ok((npm_cache_layer, npm_cache))
 }
 
 fn create_set_npm_cache_directory_command_error(error: &fun_run::CmdError) -> ErrorMessage {
@@ -169,6 +250,19 @@ fn create_set_npm_cache_directory_command_error(error: &fun_run::CmdError) -> Er
         .create()
 }
 
+fn create_verify_npm_cache_command_error(error: &fun_run::CmdError) -> ErrorMessage {
+    error_message()
+        .id("package_manager/npm/verify_cache")
+        .error_type(Internal)
+        .header("Failed to verify the npm cache")
+        .body(formatdoc! { "
+            The Heroku Node.js buildpack periodically verifies the npm cache to remove data that \
+            is no longer needed from the cache. An unexpected error occurred during this operation.
+        " })
+        .debug_info(error.to_string())
+        .create()
+}
+
 error_codes!(NpmError {
     StrictAllowScripts => ESTRICTALLOWSCRIPTS,
     AllowGit           => EALLOWGIT,
@@ -269,10 +363,50 @@ fn create_npm_install_error(error: &fun_run::CmdError) -> ErrorMessage {
 
 const NPM_CACHE_DIRECTORY_LAYER_VERSION: &str = "1";
 
+// npm never removes anything from its cache so it's capped by size and age, and garbage collected
+// with `npm cache verify` every few builds
+const NPM_CACHE_POLICY: CachePolicy = CachePolicy {
+    name: "npm cache",
+    max_size: Some(1024 * MEBIBYTE),
+    max_age: Some(DAY.saturating_mul(90)),
+    max_uses: None,
+    prune_interval: Some(10),
+};
+
 #[derive(Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct NpmCacheDirectoryLayerMetadata {
     layer_version: String,
+    target: TargetMetadata,
+    usage: CacheUsage,
+}
+
+impl NpmCacheDirectoryLayerMetadata {
+    fn new(context: &BuildpackBuildContext, usage: CacheUsage) -> Self {
+        Self {
+            layer_version: NPM_CACHE_DIRECTORY_LAYER_VERSION.to_string(),
+            target: TargetMetadata::from(&context.target),
+            usage,
+        }
+    }
+}
+
+/// The metadata written before the npm cache was held to a [`CachePolicy`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyNpmCacheDirectoryLayerMetadata {
+    layer_version: String,
+}
+
+/// Runs the install scripts for the given packages from an existing `node_modules` tree. This is
+/// also used for Yarn 1.x which has no equivalent command.
+pub(crate) fn rebuild_command(env: &Env, packages: &[String]) -> Command {
+    let mut command = Command::new("npm");
+    command.arg("rebuild");
+    command.args(packages);
+    command.envs(env);
+    command.envs(node_gyp_env());
+    command
 }
 
 pub(crate) fn run_script(name: impl AsRef<str>, env: &Env) -> Command {
@@ -337,6 +471,13 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn verify_npm_cache_command_error() {
+        assert_error_snapshot(&create_verify_npm_cache_command_error(&create_cmd_error(
+            "npm cache verify",
+        )));
+    }
+
     #[test]
     fn npm_install_error() {
         assert_error_snapshot(&create_npm_install_error(&create_cmd_error("npm ci")));
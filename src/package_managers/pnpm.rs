@@ -1,10 +1,22 @@
+use crate::cache_policy::{CacheLayerRef, CachePolicy, CacheUsage, MEBIBYTE, TrackedCache};
 use crate::cleanup::{CleanupTask, NodeGypArtifactLocation};
+use crate::install_scripts::{
+    InstallScriptsPolicy, ROOT_LIFECYCLE_SCRIPTS, are_install_scripts_deferred,
+    run_app_lifecycle_scripts, run_deferred_install_scripts,
+};
+use crate::native_modules_cache::NativeModulesCache;
+use crate::node_modules_cache::{
+    CachedDirectory, Installation, NodeModulesCache, install_with_cache,
+};
 use crate::package_json::PackageJson;
+use crate::target::TargetMetadata;
 use crate::utils::build_env::node_gyp_env;
 use crate::utils::error_handling::{
     ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
 };
-use crate::utils::npm_registry::{PackagePackument, packument_layer, resolve_package_packument};
+use crate::utils::npm_registry::{
+    PackagePackument, check_node_engine, packument_layer, resolve_package_packument,
+};
 use crate::{BuildpackBuildContext, BuildpackResult, utils};
 use bullet_stream::global::print;
 use bullet_stream::style;
@@ -12,10 +24,8 @@ use fun_run::CommandWithName;
 use indoc::formatdoc;
 use libcnb::Env;
 use libcnb::data::layer_name;
-use libcnb::data::store::Store;
 use libcnb::layer::{
-    CachedLayerDefinition, EmptyLayerCause, InvalidMetadataAction, LayerState, RestoredLayerAction,
-    UncachedLayerDefinition,
+    CachedLayerDefinition, InvalidMetadataAction, LayerState, UncachedLayerDefinition,
 };
 use libcnb::layer_env::{LayerEnv, ModificationBehavior, Scope};
 use nodejs_data::{Version, VersionRange};
@@ -28,12 +38,27 @@ use std::sync::LazyLock;
 pub(crate) fn resolve_pnpm_package_packument(
     context: &BuildpackBuildContext,
     requirement: &VersionRange,
+    node_version: &Version,
 ) -> BuildpackResult<PackagePackument> {
-    resolve_package_packument(
-        &packument_layer(layer_name!("pnpm_packument"), context, "pnpm")?,
+    let packument = packument_layer(layer_name!("pnpm_packument"), context, "pnpm")?;
+    let package_packument = resolve_package_packument(&packument, requirement)?;
+    check_node_engine(&packument, &package_packument, node_version)?;
+    Ok(package_packument)
+}
+
+pub(crate) fn prefetch_pnpm_package(
+    context: &BuildpackBuildContext,
+    requirement: &VersionRange,
+    node_version: &Version,
+) {
+    utils::npm_registry::prefetch_package(
+        context,
+        &layer_name!("pnpm_packument"),
+        &layer_name!("pnpm"),
+        "pnpm",
         requirement,
-    )
-    .map_err(Into::into)
+        node_version,
+    );
 }
 
 pub(crate) fn install_pnpm(
@@ -55,27 +80,74 @@ pub(crate) fn install_pnpm(
 pub(crate) fn install_dependencies(
     context: &BuildpackBuildContext,
     env: &mut Env,
-    store: &mut Store,
     version: &Version,
+    package_json: &PackageJson,
+    install_scripts: &InstallScriptsPolicy,
+    node_modules_cache: Option<&NodeModulesCache>,
+    native_modules_cache: Option<&NativeModulesCache>,
 ) -> BuildpackResult<()> {
     print::bullet("Setting up pnpm dependency store");
 
-    let store_dir = create_store_directory(context, env, version)?;
+    let (pnpm_store_layer, mut pnpm_store) = create_store_directory(context, env, version)?;
+    let store_dir = pnpm_store_layer.path();
     let virtual_store_dir = create_virtual_store_directory(context, env, version)?;
 
     verify_pnpm_config(env, "store-dir", &store_dir);
     verify_pnpm_config(env, "virtual-store-dir", &virtual_store_dir);
 
     print::bullet("Installing dependencies");
-    print::sub_stream_cmd(
-        Command::new("pnpm")
-            .args(["install", "--frozen-lockfile"])
-            .envs(&*env)
-            .envs(node_gyp_env()),
-    )
-    .map_err(|e| create_pnpm_install_command_error(&e))?;
+    // the symlinks in node_modules point into the virtual store so both are cached together
+    let cached_directories = [
+        CachedDirectory {
+            name: "node_modules",
+            path: context.app_dir.join("node_modules"),
+        },
+        CachedDirectory {
+            name: "virtual_store",
+            path: virtual_store_dir.clone(),
+        },
+    ];
+    let installation =
+        install_with_cache(node_modules_cache, context, &cached_directories, || {
+            let mut pnpm_install_command = Command::new("pnpm");
+            pnpm_install_command.args(["install", "--frozen-lockfile"]);
+            if are_install_scripts_deferred(install_scripts, native_modules_cache) {
+                pnpm_install_command.arg("--ignore-scripts");
+            }
+            print::sub_stream_cmd(pnpm_install_command.envs(&*env).envs(node_gyp_env()))
+                .map_err(|e| create_pnpm_install_command_error(&e))?;
+
+            run_deferred_install_scripts(
+                install_scripts,
+                native_modules_cache,
+                &[context.app_dir.join("node_modules"), virtual_store_dir],
+                |packages| rebuild_command(env, packages),
+            )?;
+            Ok(())
+        })?;
+    // the app's own lifecycle scripts don't run when scripts were disabled for the install or the
+    // install was skipped by restoring the cache
+    if installation == Installation::Restored
+        || are_install_scripts_deferred(install_scripts, native_modules_cache)
+    {
+        run_app_lifecycle_scripts(
+            &context.app_dir,
+            package_json,
+            &ROOT_LIFECYCLE_SCRIPTS,
+            |script| run_script(script, env),
+        )?;
+    }
 
-    maybe_prune_store_directory(&*env, store)?;
+    if pnpm_store.is_prune_due() {
+        print::bullet("Pruning unused dependencies from pnpm content-addressable store");
+        print::sub_stream_cmd(Command::new("pnpm").args(["store", "prune"]).envs(&*env))
+            .map_err(|e| create_prune_store_directory_error(&e))?;
+    }
+    pnpm_store.record_size();
+    pnpm_store_layer.write_metadata(PnpmCacheDirectoryLayerMetadata::new(
+        context,
+        pnpm_store.usage(),
+    ))?;
 
     context.register_cleanup(CleanupTask::PnpmModulesYaml(context.app_dir.clone()));
 
@@ -86,39 +158,53 @@ fn create_store_directory(
     context: &BuildpackBuildContext,
     env: &mut Env,
     version: &Version,
-) -> BuildpackResult<PathBuf> {
-    let new_metadata = PnpmCacheDirectoryLayerMetadata {
-        layer_version: PNPM_CACHE_DIRECTORY_LAYER_VERSION.to_string(),
-    };
+) -> BuildpackResult<(CacheLayerRef, TrackedCache)> {
+    let target = TargetMetadata::from(&context.target);
 
     let pnpm_cache_layer = context.cached_layer(
         layer_name!("addressable"),
         CachedLayerDefinition {
             build: true,
             launch: false,
-            invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
-            restored_layer_action: &|old_metadata: &PnpmCacheDirectoryLayerMetadata, _| {
-                if old_metadata == &new_metadata {
-                    RestoredLayerAction::KeepLayer
-                } else {
-                    RestoredLayerAction::DeleteLayer
+            invalid_metadata_action: &|metadata| {
+                // stores from before the cache policy are kept, carrying over the build count that
+                // was tracked in the buildpack store so the prune schedule doesn't restart
+                match metadata.clone().and_then(|metadata| {
+                    metadata
+                        .try_into::<LegacyPnpmCacheDirectoryLayerMetadata>()
+                        .ok()
+                }) {
+                    Some(legacy_metadata) => {
+                        InvalidMetadataAction::ReplaceMetadata(PnpmCacheDirectoryLayerMetadata {
+                            layer_version: legacy_metadata.layer_version,
+                            target: target.clone(),
+                            usage: CacheUsage::migrated(legacy_store_use_count(context)),
+                        })
+                    }
+                    None => InvalidMetadataAction::DeleteLayer,
                 }
             },
+            restored_layer_action: &|old_metadata: &PnpmCacheDirectoryLayerMetadata, path| {
+                PNPM_STORE_POLICY.restored_layer_action(
+                    old_metadata.layer_version == PNPM_CACHE_DIRECTORY_LAYER_VERSION
+                        && old_metadata.target == target,
+                    &old_metadata.usage,
+                    path,
+                )
+            },
         },
     )?;
 
-    match pnpm_cache_layer.state {
-        LayerState::Restored { .. } => {
-            print::sub_bullet("Restoring pnpm content-addressable store from cache");
-        }
-        LayerState::Empty { cause } => {
-            if let EmptyLayerCause::RestoredLayerAction { .. } = cause {
-                print::sub_bullet("Cached pnpm content-addressable store has expired");
-            }
-            print::sub_bullet("Creating new pnpm content-addressable store");
-            pnpm_cache_layer.write_metadata(new_metadata)?;
-        }
+    let pnpm_store = PNPM_STORE_POLICY.track(&pnpm_cache_layer.state, &pnpm_cache_layer.path());
+    if let LayerState::Restored { .. } = pnpm_cache_layer.state {
+        print::sub_bullet("Restoring pnpm content-addressable store from cache");
+    } else {
+        print::sub_bullet("Creating new pnpm content-addressable store");
     }
+    pnpm_cache_layer.write_metadata(PnpmCacheDirectoryLayerMetadata::new(
+        context,
+        pnpm_store.usage(),
+    ))?;
 
     let store_dir = pnpm_cache_layer.path();
 
@@ -138,15 +224,56 @@ fn create_store_directory(
     pnpm_cache_layer.write_env(layer_env)?;
     env.clone_from(&pnpm_cache_layer.read_env()?.apply(Scope::Build, env));
 
-    Ok(store_dir)
+    Ok((pnpm_cache_layer, pnpm_store))
 }
 
 const PNPM_CACHE_DIRECTORY_LAYER_VERSION: &str = "1";
 
+const PNPM_STORE_POLICY: CachePolicy = CachePolicy {
+    name: "pnpm content-addressable store",
+    max_size: Some(2048 * MEBIBYTE),
+    max_age: None,
+    max_uses: None,
+    prune_interval: Some(40),
+};
+
 #[derive(Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct PnpmCacheDirectoryLayerMetadata {
     layer_version: String,
+    target: TargetMetadata,
+    usage: CacheUsage,
+}
+
+impl PnpmCacheDirectoryLayerMetadata {
+    fn new(context: &BuildpackBuildContext, usage: CacheUsage) -> Self {
+        Self {
+            layer_version: PNPM_CACHE_DIRECTORY_LAYER_VERSION.to_string(),
+            target: TargetMetadata::from(&context.target),
+            usage,
+        }
+    }
+}
+
+/// The metadata written before the store was held to a [`CachePolicy`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyPnpmCacheDirectoryLayerMetadata {
+    layer_version: String,
+}
+
+/// Before the store was held to a [`CachePolicy`] its build count was tracked in the buildpack
+/// store under this key.
+pub(crate) const LEGACY_STORE_USE_COUNT_KEY: &str = "cache_use_count";
+
+#[allow(clippy::cast_possible_truncation)]
+fn legacy_store_use_count(context: &BuildpackBuildContext) -> f32 {
+    context
+        .store
+        .as_ref()
+        .and_then(|store| store.metadata.get(LEGACY_STORE_USE_COUNT_KEY))
+        .and_then(toml::Value::as_float)
+        .map_or(0.0, |use_count| use_count as f32)
 }
 
 fn create_virtual_store_directory(
@@ -270,30 +397,6 @@ fn create_pnpm_install_command_error(error: &fun_run::CmdError) -> ErrorMessage
         .create()
 }
 
-fn maybe_prune_store_directory(env: &Env, store: &mut Store) -> BuildpackResult<()> {
-    let cache_use_count_key = "cache_use_count";
-    let cache_prune_interval = 40;
-
-    #[allow(clippy::cast_possible_truncation)]
-    let cache_use_count = store.metadata.get(cache_use_count_key).map_or(0, |v| {
-        v.as_float().map_or(cache_prune_interval, |f| f as i64)
-    });
-
-    if cache_use_count.rem_euclid(cache_prune_interval) == 0 {
-        print::bullet("Pruning unused dependencies from pnpm content-addressable store");
-        print::sub_stream_cmd(Command::new("pnpm").args(["store", "prune"]).envs(env))
-            .map_err(|e| create_prune_store_directory_error(&e))?;
-    }
-
-    #[allow(clippy::cast_precision_loss)]
-    store.metadata.insert(
-        cache_use_count_key.into(),
-        toml::Value::from((cache_use_count + 1) as f64),
-    );
-
-    Ok(())
-}
-
 fn create_prune_store_directory_error(error: &fun_run::CmdError) -> ErrorMessage {
     error_message()
         .id("package_manager/pnpm/prune_store_directory")
@@ -409,6 +512,17 @@ fn create_parse_pnpm_list_output_error(error: &serde_json::Error) -> ErrorMessag
         .create()
 }
 
+/// Runs the install scripts for the given packages, or every package when none are given, from an
+/// existing `node_modules` tree.
+pub(crate) fn rebuild_command(env: &Env, packages: &[String]) -> Command {
+    let mut command = Command::new("pnpm");
+    command.arg("rebuild");
+    command.args(packages);
+    command.envs(env);
+    command.envs(node_gyp_env());
+    command
+}
+
 pub(crate) fn run_script(name: impl AsRef<str>, env: &Env) -> Command {
     let mut command = Command::new("pnpm");
     command.args(["run", name.as_ref()]);
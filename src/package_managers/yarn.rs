@@ -1,24 +1,41 @@
+use crate::cache_policy::{CacheLayerRef, CachePolicy, CacheUsage, MEBIBYTE, TrackedCache};
 use crate::cleanup::{CleanupTask, NodeGypArtifactLocation};
+use crate::install_scripts::{
+    InstallScriptsPolicy, ROOT_LIFECYCLE_SCRIPTS, YARN_BERRY_ROOT_LIFECYCLE_SCRIPTS,
+    are_install_scripts_deferred, run_app_lifecycle_scripts, run_deferred_install_scripts,
+};
+use crate::native_modules_cache::NativeModulesCache;
+use crate::node_modules_cache::{
+    CachedDirectory, Installation, NodeModulesCache, install_with_cache,
+};
 use crate::o11y::*;
+use crate::package_json::PackageJson;
+use crate::package_managers::npm;
+use crate::target::TargetMetadata;
 use crate::utils::build_env::node_gyp_env;
 use crate::utils::error_handling::{
     ErrorMessage, ErrorType, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
 };
-use crate::utils::npm_registry::{PackagePackument, packument_layer, resolve_package_packument};
+use crate::utils::npm_registry::{
+    PackagePackument, check_node_engine, packument_layer, resolve_package_packument,
+};
 use crate::{BuildpackBuildContext, BuildpackResult, utils};
 use bullet_stream::global::print;
 use bullet_stream::style;
 use fun_run::CommandWithName;
 use indoc::formatdoc;
 use libcnb::Env;
+use libcnb::data::layer::LayerName;
 use libcnb::data::layer_name;
 use libcnb::layer::{
-    CachedLayerDefinition, EmptyLayerCause, InvalidMetadataAction, LayerState, RestoredLayerAction,
-    UncachedLayerDefinition,
+    CachedLayerDefinition, InvalidMetadataAction, LayerState, UncachedLayerDefinition,
 };
 use libcnb::layer_env::Scope;
 use nodejs_data::{Version, VersionCommandError, VersionRange};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -37,20 +54,41 @@ pub(crate) static DEFAULT_YARN_REQUIREMENT: LazyLock<VersionRange> = LazyLock::n
 pub(crate) fn resolve_yarn_package_packument(
     context: &BuildpackBuildContext,
     requirement: &VersionRange,
+    node_version: &Version,
 ) -> BuildpackResult<PackagePackument> {
-    let (yarn_layer_name, yarn_package_name) = if requirement.allows_any(&YARN_BERRY_RANGE) {
+    let (yarn_layer_name, yarn_package_name) = yarn_packument_source(requirement);
+    let packument = packument_layer(yarn_layer_name, context, yarn_package_name)?;
+    let package_packument = resolve_package_packument(&packument, requirement)?;
+    check_node_engine(&packument, &package_packument, node_version)?;
+    Ok(package_packument)
+}
+
+pub(crate) fn prefetch_yarn_package(
+    context: &BuildpackBuildContext,
+    requirement: &VersionRange,
+    node_version: &Version,
+) {
+    let (yarn_layer_name, yarn_package_name) = yarn_packument_source(requirement);
+    utils::npm_registry::prefetch_package(
+        context,
+        &yarn_layer_name,
+        &layer_name!("yarn"),
+        yarn_package_name,
+        requirement,
+        node_version,
+    );
+}
+
+/// Yarn 2+ is published as `@yarnpkg/cli-dist` instead of `yarn`.
+fn yarn_packument_source(requirement: &VersionRange) -> (LayerName, &'static str) {
+    if requirement.allows_any(&YARN_BERRY_RANGE) {
         (
             layer_name!("yarnpkg_cli-dist_packument"),
             "@yarnpkg/cli-dist",
         )
     } else {
         (layer_name!("yarn_packument"), "yarn")
-    };
-    resolve_package_packument(
-        &packument_layer(yarn_layer_name, context, yarn_package_name)?,
-        requirement,
-    )
-    .map_err(Into::into)
+    }
 }
 
 pub(crate) fn install_yarn(
@@ -269,43 +307,138 @@ pub(crate) fn install_dependencies(
     context: &BuildpackBuildContext,
     env: &Env,
     version: &Version,
+    package_json: &PackageJson,
+    install_scripts: &InstallScriptsPolicy,
+    node_modules_cache: Option<&NodeModulesCache>,
+    native_modules_cache: Option<&NativeModulesCache>,
 ) -> BuildpackResult<()> {
     print::bullet("Setting up yarn dependency cache");
     ensure_global_cache_is_disabled(env, version)?;
 
     let yarn_cache = get_cache_folder_config(env, version)?;
     let zero_install_mode = is_yarn_zero_install_mode(&yarn_cache);
-    if zero_install_mode {
+    // only installs into node_modules can be cached, Plug'n'Play and zero-installs are left alone
+    let mut node_modules_cache = node_modules_cache.filter(|_| !zero_install_mode);
+    let dependency_cache = if zero_install_mode {
         print::sub_bullet("Yarn zero-install detected. Skipping dependency cache.");
+        None
     } else {
         let node_linker = get_node_linker_config(env, version)?;
-        let cache_dir = create_cache_directory(context, version, node_linker.as_ref())?;
-        set_cache_folder_config(env, version, &cache_dir)?;
-    }
+        if matches!(node_linker, Some(NodeLinker::Pnp | NodeLinker::Pnpm)) {
+            node_modules_cache = None;
+        }
+        let (yarn_cache_layer, yarn_cache) =
+            create_cache_directory(context, version, node_linker.as_ref())?;
+        set_cache_folder_config(env, version, &yarn_cache_layer.path())?;
+        Some((yarn_cache_layer, yarn_cache))
+    };
 
     print::bullet("Installing dependencies");
+    let node_modules = CachedDirectory {
+        name: "node_modules",
+        path: context.app_dir.join("node_modules"),
+    };
+    let installation = install_with_cache(node_modules_cache, context, &[node_modules], || {
+        install_dependencies_with_yarn(
+            context,
+            env,
+            version,
+            install_scripts,
+            native_modules_cache,
+            zero_install_mode,
+        )
+    })?;
+    // the app's own lifecycle scripts don't run when scripts were disabled for the install or the
+    // install was skipped by restoring the cache
+    if installation == Installation::Restored
+        || are_install_scripts_deferred(install_scripts, native_modules_cache)
+    {
+        run_app_lifecycle_scripts(
+            &context.app_dir,
+            package_json,
+            if version.major() == 1 {
+                &ROOT_LIFECYCLE_SCRIPTS
+            } else {
+                &YARN_BERRY_ROOT_LIFECYCLE_SCRIPTS
+            },
+            |script| run_script(script, env),
+        )?;
+    }
+
+    if let Some((yarn_cache_layer, mut yarn_cache)) = dependency_cache {
+        // Yarn 1.x doesn't cache zip archives so it's only held to the cache policy limits
+        if version.major() >= 2 && yarn_cache.is_prune_due() {
+            match prune_unreferenced_cache_archives(
+                &yarn_cache_layer.path(),
+                &context.app_dir.join("yarn.lock"),
+            ) {
+                Ok(0) => {}
+                Ok(removed) => print::sub_bullet(format!(
+                    "Removed {removed} unreferenced packages from the yarn dependency cache"
+                )),
+                Err(error) => print::warning(formatdoc! { "
+                    Unreferenced packages could not be removed from the yarn dependency cache: {error}
+                " }),
+            }
+        }
+        yarn_cache.record_size();
+        yarn_cache_layer.write_metadata(YarnCacheDirectoryLayerMetadata::new(
+            context,
+            version,
+            yarn_cache.usage(),
+        ))?;
+    }
+
+    // Register app directory for cleanup of non-deterministic Makefiles generated
+    // by node-gyp. Native modules may be compiled into node_modules/ (yarn v1 and
+    // node-modules linker) or .yarn/unplugged/ (PnP linker).
+    context.register_cleanup(CleanupTask::NodeGypMakefiles(
+        NodeGypArtifactLocation::AppDir(context.app_dir.clone()),
+    ));
+
+    Ok(())
+}
+
+fn install_dependencies_with_yarn(
+    context: &BuildpackBuildContext,
+    env: &Env,
+    version: &Version,
+    install_scripts: &InstallScriptsPolicy,
+    native_modules_cache: Option<&NativeModulesCache>,
+    zero_install_mode: bool,
+) -> BuildpackResult<()> {
+    let defer_install_scripts = are_install_scripts_deferred(install_scripts, native_modules_cache);
     let mut yarn_install_command = Command::new("yarn");
     yarn_install_command.envs(env);
     yarn_install_command.envs(node_gyp_env());
     yarn_install_command.arg("install");
     if version.major() == 1 {
         yarn_install_command.args(["--production=false", "--frozen-lockfile"]);
+        if defer_install_scripts {
+            yarn_install_command.arg("--ignore-scripts");
+        }
     } else {
         yarn_install_command.args(["--immutable", "--inline-builds"]);
         if zero_install_mode {
             yarn_install_command.arg("--immutable-cache");
         }
+        if defer_install_scripts {
+            yarn_install_command.arg("--mode=skip-build");
+        }
     }
 
     print::sub_stream_cmd(yarn_install_command)
         .map_err(|e| create_yarn_install_command_error(&e))?;
 
-    // Register app directory for cleanup of non-deterministic Makefiles generated
-    // by node-gyp. Native modules may be compiled into node_modules/ (yarn v1 and
-    // node-modules linker) or .yarn/unplugged/ (PnP linker).
-    context.register_cleanup(CleanupTask::NodeGypMakefiles(
-        NodeGypArtifactLocation::AppDir(context.app_dir.clone()),
-    ));
+    run_deferred_install_scripts(
+        install_scripts,
+        native_modules_cache,
+        &[
+            context.app_dir.join("node_modules"),
+            context.app_dir.join(".yarn/unplugged"),
+        ],
+        |packages| rebuild_command(env, version, packages),
+    )?;
 
     Ok(())
 }
@@ -484,12 +617,9 @@ fn create_cache_directory(
     context: &BuildpackBuildContext,
     version: &Version,
     node_linker: Option<&NodeLinker>,
-) -> BuildpackResult<PathBuf> {
-    let new_metadata = YarnCacheDirectoryLayerMetadata {
-        layer_version: YARN_CACHE_DIRECTORY_LAYER_VERSION.to_string(),
-        cache_usage_count: 0.0,
-        yarn_major_version: version.major().to_string(),
-    };
+) -> BuildpackResult<(CacheLayerRef, TrackedCache)> {
+    let yarn_major_version = version.major().to_string();
+    let target = TargetMetadata::from(&context.target);
 
     let yarn_cache_layer = context.cached_layer(
         layer_name!("yarn_cache"),
@@ -497,51 +627,144 @@ fn create_cache_directory(
             build: true,
             // In Plug'n'Play mode, Yarn resolves packages directly from the cache so it must be present at launch
             launch: matches!(node_linker, Some(NodeLinker::Pnp)),
-            invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
-            restored_layer_action: &|old_metadata: &YarnCacheDirectoryLayerMetadata, _| {
-                let is_reusable = old_metadata.yarn_major_version
-                    == new_metadata.yarn_major_version
-                    && old_metadata.layer_version == new_metadata.layer_version
-                    && old_metadata.cache_usage_count < YARN_CACHE_DIRECTORY_MAX_CACHE_USAGE_COUNT;
-                if is_reusable {
-                    RestoredLayerAction::KeepLayer
-                } else {
-                    RestoredLayerAction::DeleteLayer
+            invalid_metadata_action: &|metadata| {
+                // caches from before the cache policy are kept, carrying over their build count
+                match metadata.clone().and_then(|metadata| {
+                    metadata
+                        .try_into::<LegacyYarnCacheDirectoryLayerMetadata>()
+                        .ok()
+                }) {
+                    Some(legacy_metadata) => {
+                        InvalidMetadataAction::ReplaceMetadata(YarnCacheDirectoryLayerMetadata {
+                            yarn_major_version: legacy_metadata.yarn_major_version,
+                            layer_version: legacy_metadata.layer_version,
+                            target: target.clone(),
+                            usage: CacheUsage::migrated(legacy_metadata.cache_usage_count),
+                        })
+                    }
+                    None => InvalidMetadataAction::DeleteLayer,
                 }
             },
+            restored_layer_action: &|old_metadata: &YarnCacheDirectoryLayerMetadata, path| {
+                YARN_CACHE_POLICY.restored_layer_action(
+                    old_metadata.yarn_major_version == yarn_major_version
+                        && old_metadata.layer_version == YARN_CACHE_DIRECTORY_LAYER_VERSION
+                        && old_metadata.target == target,
+                    &old_metadata.usage,
+                    path,
+                )
+            },
         },
     )?;
 
-    match yarn_cache_layer.state {
-        LayerState::Restored { .. } => {
-            print::sub_bullet("Restoring yarn dependency cache");
-        }
-        LayerState::Empty { cause } => {
-            if let EmptyLayerCause::RestoredLayerAction { .. } = cause {
-                print::sub_bullet("Clearing yarn dependency cache");
-            }
-            yarn_cache_layer.write_metadata(YarnCacheDirectoryLayerMetadata {
-                cache_usage_count: new_metadata.cache_usage_count + 1.0,
-                ..new_metadata
-            })?;
-        }
+    let yarn_cache = YARN_CACHE_POLICY.track(&yarn_cache_layer.state, &yarn_cache_layer.path());
+    if let LayerState::Restored { .. } = yarn_cache_layer.state {
+        print::sub_bullet("Restoring yarn dependency cache");
     }
+    yarn_cache_layer.write_metadata(YarnCacheDirectoryLayerMetadata::new(
+        context,
+        version,
+        yarn_cache.usage(),
+    ))?;
 
-    Ok(yarn_cache_layer.path())
+    Ok((yarn_cache_layer, yarn_cache))
 }
 
-const YARN_CACHE_DIRECTORY_MAX_CACHE_USAGE_COUNT: f32 = 150.0;
 const YARN_CACHE_DIRECTORY_LAYER_VERSION: &str = "1";
 
+// Yarn 2+ already removes unused archives from a project cache, the targeted prune on every reuse
+// catches what it leaves behind
+const YARN_CACHE_POLICY: CachePolicy = CachePolicy {
+    name: "yarn dependency cache",
+    max_size: Some(1024 * MEBIBYTE),
+    max_age: None,
+    max_uses: Some(150),
+    prune_interval: Some(1),
+};
+
 #[derive(Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 struct YarnCacheDirectoryLayerMetadata {
-    // Usings float here due to [an issue with lifecycle's handling of integers](https://github.com/buildpacks/lifecycle/issues/884)
+    yarn_major_version: String,
+    layer_version: String,
+    target: TargetMetadata,
+    usage: CacheUsage,
+}
+
+impl YarnCacheDirectoryLayerMetadata {
+    fn new(context: &BuildpackBuildContext, version: &Version, usage: CacheUsage) -> Self {
+        Self {
+            yarn_major_version: version.major().to_string(),
+            layer_version: YARN_CACHE_DIRECTORY_LAYER_VERSION.to_string(),
+            target: TargetMetadata::from(&context.target),
+            usage,
+        }
+    }
+}
+
+/// The metadata written before the cache was held to a [`CachePolicy`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyYarnCacheDirectoryLayerMetadata {
+    // Using float here due to [an issue with lifecycle's handling of integers](https://github.com/buildpacks/lifecycle/issues/884)
     cache_usage_count: f32,
     yarn_major_version: String,
     layer_version: String,
 }
 
+/// Removes the archives from a Yarn 2+ cache that aren't referenced by `yarn.lock`. Archives are
+/// named after the package locator and end with a 10 character locator hash and the first 10
+/// characters of the checksum recorded in the lockfile (e.g.;
+/// `accepts-npm-1.3.8-9a812371c9-50c43d32e7.zip`). Archives named any other way, like the ones
+/// ending in a cache key (e.g.; `-10c0.zip`) or packages without a checksum, are always kept since
+/// they can't be matched against the lockfile. Nothing is removed if the lockfile doesn't record
+/// any checksums. Returns the number of archives removed.
+fn prune_unreferenced_cache_archives(cache_dir: &Path, lockfile: &Path) -> io::Result<usize> {
+    let lockfile_contents = fs::read_to_string(lockfile)?;
+    let referenced = lockfile_contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("checksum: "))
+        // Yarn 4 prefixes checksums with the cache key (e.g.; `10c0/`)
+        .map(|checksum| checksum.rsplit('/').next().unwrap_or(checksum))
+        .filter_map(|checksum| checksum.get(..10))
+        .collect::<HashSet<_>>();
+    if referenced.is_empty() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let is_unreferenced = path.extension().is_some_and(|extension| extension == "zip")
+            && path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(archive_checksum)
+                .is_some_and(|checksum| !referenced.contains(checksum));
+        if is_unreferenced {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// The checksum prefix of an archive named `<locator>-<locator hash>-<checksum>`, where both hashes
+/// are 10 hex characters.
+fn archive_checksum(archive_stem: &str) -> Option<&str> {
+    let is_hash = |segment: &str| {
+        segment.len() == 10
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    };
+    let mut segments = archive_stem.rsplitn(3, '-');
+    let checksum = segments.next()?;
+    let locator_hash = segments.next()?;
+    segments.next()?;
+    (is_hash(checksum) && is_hash(locator_hash)).then_some(checksum)
+}
+
 fn create_yarn_install_command_error(error: &fun_run::CmdError) -> ErrorMessage {
     let yarn_install = style::value(error.name());
     error_message()
@@ -563,6 +786,21 @@ fn create_yarn_install_command_error(error: &fun_run::CmdError) -> ErrorMessage
         .create()
 }
 
+/// Runs the install scripts for the given packages, or every package when none are given, from an
+/// existing `node_modules` tree.
+pub(crate) fn rebuild_command(env: &Env, version: &Version, packages: &[String]) -> Command {
+    if version.major() == 1 {
+        // Yarn 1.x has no command to build individual packages
+        return npm::rebuild_command(env, packages);
+    }
+    let mut command = Command::new("yarn");
+    command.arg("rebuild");
+    command.args(packages);
+    command.envs(env);
+    command.envs(node_gyp_env());
+    command
+}
+
 pub(crate) fn run_script(name: impl AsRef<str>, env: &Env) -> Command {
     let mut command = Command::new("yarn");
     command.args(["run", name.as_ref()]);
@@ -628,6 +866,7 @@ fn create_yarn_install_prune_plugin_error(error: &std::io::Error) -> ErrorMessag
 mod tests {
     use super::*;
     use crate::utils::error_handling::test_util::{assert_error_snapshot, create_cmd_error};
+    use indoc::indoc;
 
     #[test]
     fn version_parse_error() {
@@ -780,6 +1019,89 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_prune_unreferenced_cache_archives() {
+        let fixture =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/yarn-4-modules-zero");
+        let cache_dir = tempfile::tempdir().unwrap();
+        for entry in fs::read_dir(fixture.join(".yarn/cache")).unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(entry.path(), cache_dir.path().join(entry.file_name())).unwrap();
+        }
+        let archive_count = fs::read_dir(cache_dir.path()).unwrap().count();
+        let stale_archive = cache_dir
+            .path()
+            .join("accepts-npm-1.3.7-9a812371c9-27fc8b3a3e.zip");
+        fs::write(&stale_archive, "").unwrap();
+        // archives that can't be matched against the lockfile are kept
+        for unmatched_archive in [
+            "accepts-npm-1.3.8-9a812371c9-10c0.zip",
+            "accepts-npm-1.3.8-9a812371c9-8.zip",
+            "local-file-0cae9a5b41.zip",
+            "accepts-npm-1.3.8-9a812371c9-27FC8B3A3E.zip",
+        ] {
+            fs::write(cache_dir.path().join(unmatched_archive), "").unwrap();
+        }
+
+        assert_eq!(
+            prune_unreferenced_cache_archives(cache_dir.path(), &fixture.join("yarn.lock"))
+                .unwrap(),
+            1
+        );
+        assert!(!stale_archive.exists());
+        assert_eq!(
+            fs::read_dir(cache_dir.path()).unwrap().count(),
+            archive_count + 4
+        );
+    }
+
+    #[test]
+    fn test_archive_checksum() {
+        assert_eq!(
+            archive_checksum("accepts-npm-1.3.8-9a812371c9-50c43d32e7"),
+            Some("50c43d32e7")
+        );
+        assert_eq!(
+            archive_checksum("@babel-core-npm-7.23.0-5ec0d1d8f0-10c0"),
+            None
+        );
+        assert_eq!(archive_checksum("accepts-npm-1.3.8-9a812371c9-8"), None);
+        assert_eq!(archive_checksum("local-file-0cae9a5b41"), None);
+        assert_eq!(archive_checksum("9a812371c9-50c43d32e7"), None);
+    }
+
+    #[test]
+    fn test_legacy_cache_metadata() {
+        let legacy_metadata = toml::from_str::<LegacyYarnCacheDirectoryLayerMetadata>(indoc! {r#"
+            cache_usage_count = 12.0
+            yarn_major_version = "4"
+            layer_version = "1"
+        "#})
+        .unwrap();
+        assert!((legacy_metadata.cache_usage_count - 12.0).abs() < f32::EPSILON);
+        assert_eq!(legacy_metadata.yarn_major_version, "4");
+    }
+
+    #[test]
+    fn test_prune_unreferenced_cache_archives_without_checksums() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let lockfile = app_dir.path().join("yarn.lock");
+        fs::write(&lockfile, "# yarn lockfile v1\n").unwrap();
+        fs::write(
+            cache_dir
+                .path()
+                .join("accepts-npm-1.3.8-9a812371c9-50c43d32e7.zip"),
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(
+            prune_unreferenced_cache_archives(cache_dir.path(), &lockfile).unwrap(),
+            0
+        );
+    }
+
     #[test]
     fn yarn_install_prune_plugin_error() {
         assert_error_snapshot(&create_yarn_install_prune_plugin_error(
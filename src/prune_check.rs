@@ -0,0 +1,481 @@
+use crate::o11y::*;
+use crate::package_json::PackageJson;
+use bullet_stream::global::print;
+use bullet_stream::style;
+use indoc::formatdoc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Maps each binary name provided by a dev-only dependency to the package that provides it.
+///
+/// This must be collected **before** dev dependencies are pruned since the `bin` declarations are
+/// read from the installed package manifests in `node_modules`.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DevDependencyBinaries(BTreeMap<String, String>);
+
+impl DevDependencyBinaries {
+    pub(crate) fn collect(app_dir: &Path, package_json: &PackageJson) -> Self {
+        let dependencies = package_json
+            .dependencies()
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        let mut binaries = BTreeMap::new();
+        for package_name in package_json.dev_dependencies() {
+            // packages that are also production dependencies survive pruning
+            if dependencies.contains(&package_name) {
+                continue;
+            }
+            for binary in read_package_binaries(&app_dir.join("node_modules"), &package_name) {
+                binaries.insert(binary, package_name.clone());
+            }
+        }
+        Self(binaries)
+    }
+}
+
+fn read_package_binaries(node_modules: &Path, package_name: &str) -> Vec<String> {
+    // installed package manifests are read directly instead of through `PackageJson` to avoid
+    // emitting telemetry for every dependency
+    let Some(package_json) =
+        std::fs::read_to_string(node_modules.join(package_name).join("package.json"))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+    else {
+        return vec![];
+    };
+    match &package_json["bin"] {
+        // a string `bin` is exposed under the unscoped package name
+        serde_json::Value::String(_) => vec![
+            package_name
+                .rsplit_once('/')
+                .map_or(package_name, |(_, name)| name)
+                .to_string(),
+        ],
+        serde_json::Value::Object(bins) => bins.keys().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct MissingBinary {
+    binary: String,
+    package_name: String,
+    command: String,
+}
+
+/// Checks that the binaries referenced by the `start` script and any `Procfile` process commands
+/// still resolve from `node_modules/.bin` after dev dependencies were pruned. Scripts run through
+/// the package manager (e.g. `npm run migrate`) are followed into their own commands. A warning
+/// naming the dev dependency to move is printed for each binary that was removed.
+pub(crate) fn verify_process_binaries(
+    app_dir: &Path,
+    package_json: &PackageJson,
+    dev_dependency_binaries: &DevDependencyBinaries,
+) {
+    let mut commands = vec![];
+    if let Some((_, start_script)) = package_json.script("start") {
+        commands.push(start_script);
+    }
+    if let Ok(procfile) = std::fs::read_to_string(app_dir.join("Procfile")) {
+        commands.extend(procfile_commands(&procfile));
+    }
+
+    let missing_binaries = find_missing_binaries(
+        &app_dir.join("node_modules/.bin"),
+        &commands,
+        package_json,
+        dev_dependency_binaries,
+    );
+
+    tracing::info!(
+        { PRUNE_CHECK_MISSING_BINARIES } = missing_binaries
+            .iter()
+            .map(|missing| missing.binary.clone())
+            .collect::<Vec<_>>()
+            .join(","),
+        "prune_check"
+    );
+
+    for missing_binary in missing_binaries {
+        print::warning(create_missing_binary_warning(&missing_binary));
+    }
+}
+
+fn find_missing_binaries(
+    bin_dir: &Path,
+    commands: &[String],
+    package_json: &PackageJson,
+    dev_dependency_binaries: &DevDependencyBinaries,
+) -> Vec<MissingBinary> {
+    let mut seen = BTreeSet::new();
+    commands
+        .iter()
+        .flat_map(|command| command_binaries(command, package_json, 0))
+        .filter_map(|(binary, command)| {
+            let package_name = dev_dependency_binaries.0.get(&binary)?;
+            if bin_dir.join(&binary).exists() || !seen.insert(binary.clone()) {
+                return None;
+            }
+            Some(MissingBinary {
+                binary,
+                package_name: package_name.clone(),
+                command,
+            })
+        })
+        .collect()
+}
+
+/// How many levels of scripts calling other scripts are followed, which also stops scripts that
+/// call each other in a loop.
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// Extracts the program invoked by each simple command in a shell command, paired with the
+/// command that invokes it. Scripts run through `npm run`, `yarn [run]`, or `pnpm [run]` are
+/// followed into the package.json script they reference.
+fn command_binaries(
+    command: &str,
+    package_json: &PackageJson,
+    depth: usize,
+) -> Vec<(String, String)> {
+    let mut binaries = vec![];
+    for words in shell_commands(command) {
+        let args = program_args(&words);
+        let Some(program) = args.first() else {
+            continue;
+        };
+        binaries.push((program.clone(), command.to_string()));
+        if depth < MAX_SCRIPT_DEPTH
+            && let Some((_, script)) =
+                referenced_script(args).and_then(|name| package_json.script(name))
+        {
+            binaries.extend(command_binaries(&script, package_json, depth + 1));
+        }
+    }
+    binaries
+}
+
+/// Splits a shell command into the words of each simple command it runs. Quotes and backslash
+/// escapes are honored so operators inside them don't split the command. Commands are separated
+/// by `&&`, `||`, `|`, `;`, `&`, newlines, and subshell parentheses, and comments are dropped.
+fn shell_commands(command: &str) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                word.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            }
+                            None => word.push('\\'),
+                        },
+                        _ => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                // a backslash before a newline continues the command on the next line
+                if let Some(escaped) = chars.next().filter(|&escaped| escaped != '\n') {
+                    word.push(escaped);
+                }
+            }
+            '#' if !in_word => {
+                chars.by_ref().find(|&c| c == '\n');
+                end_command(&mut commands, &mut words);
+            }
+            '&' | '|' | ';' | '\n' | '(' | ')' => {
+                end_word(&mut words, &mut word, &mut in_word);
+                end_command(&mut commands, &mut words);
+            }
+            c if c.is_whitespace() => end_word(&mut words, &mut word, &mut in_word),
+            _ => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    end_word(&mut words, &mut word, &mut in_word);
+    end_command(&mut commands, &mut words);
+    commands
+}
+
+fn end_word(words: &mut Vec<String>, word: &mut String, in_word: &mut bool) {
+    if *in_word {
+        words.push(std::mem::take(word));
+        *in_word = false;
+    }
+}
+
+fn end_command(commands: &mut Vec<Vec<String>>, words: &mut Vec<String>) {
+    if !words.is_empty() {
+        commands.push(std::mem::take(words));
+    }
+}
+
+/// The program and arguments of a simple command. Leading environment variable assignments,
+/// options, and the `env`, `exec`, and `npx` wrappers are skipped.
+fn program_args(words: &[String]) -> &[String] {
+    let start = words
+        .iter()
+        .position(|word| {
+            !word.is_empty()
+                && !word.starts_with('-')
+                && !is_variable_assignment(word)
+                && !["env", "exec", "npx"].contains(&word.as_str())
+        })
+        .unwrap_or(words.len());
+    &words[start..]
+}
+
+fn is_variable_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// The package.json script run by `npm run <script>`, `yarn [run] <script>`, or
+/// `pnpm [run] <script>`. Options passed to the package manager are ignored.
+fn referenced_script(args: &[String]) -> Option<&str> {
+    let mut args = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| !arg.starts_with('-'));
+    match (args.next()?, args.next()?) {
+        ("npm", "run" | "run-script") | ("yarn" | "pnpm", "run") => args.next(),
+        ("yarn" | "pnpm", script) => Some(script),
+        _ => None,
+    }
+}
+
+fn procfile_commands(procfile: &str) -> Vec<String> {
+    procfile
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(_, command)| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+fn create_missing_binary_warning(missing_binary: &MissingBinary) -> String {
+    let binary = style::value(&missing_binary.binary);
+    let package_name = style::value(&missing_binary.package_name);
+    let command = style::command(&missing_binary.command);
+    formatdoc! { "
+        The command {command} runs {binary} which is provided by {package_name}. This package is \
+        listed in devDependencies and was removed when dev dependencies were pruned, so this \
+        command will fail when the application starts.
+
+        Move {package_name} from devDependencies to dependencies in package.json.
+    " }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_package_json(app_dir: &Path, package_json: &serde_json::Value) -> PackageJson {
+        let package_json_path = app_dir.join("package.json");
+        fs::write(&package_json_path, package_json.to_string()).unwrap();
+        PackageJson::try_from(package_json_path).unwrap()
+    }
+
+    fn programs(command: &str, package_json: &PackageJson) -> Vec<String> {
+        command_binaries(command, package_json, 0)
+            .into_iter()
+            .map(|(binary, _)| binary)
+            .collect()
+    }
+
+    #[test]
+    fn extract_command_binaries() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let package_json = write_package_json(app_dir.path(), &serde_json::json!({}));
+        assert_eq!(programs("next start", &package_json), vec!["next"]);
+        assert_eq!(
+            programs("NODE_ENV=production ts-node src/index.ts", &package_json),
+            vec!["ts-node"]
+        );
+        assert_eq!(
+            programs("npm run migrate && exec nodemon server.js", &package_json),
+            vec!["npm", "nodemon"]
+        );
+        assert_eq!(
+            programs(
+                "npx prisma migrate deploy; node dist/index.js",
+                &package_json
+            ),
+            vec!["prisma", "node"]
+        );
+        assert_eq!(
+            programs("npx -y tsx src/index.ts | pino-pretty", &package_json),
+            vec!["tsx", "pino-pretty"]
+        );
+    }
+
+    #[test]
+    fn extract_command_binaries_respects_quoting() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let package_json = write_package_json(app_dir.path(), &serde_json::json!({}));
+        assert_eq!(
+            programs(
+                "concurrently 'tsc -w && echo done' \"node a.js; node b.js\" # && nodemon",
+                &package_json
+            ),
+            vec!["concurrently"]
+        );
+        assert_eq!(
+            programs("(cd dist && node index.js) & \\\n  wait", &package_json),
+            vec!["cd", "node", "wait"]
+        );
+        assert_eq!(
+            programs("DEBUG='app:*' 'ts-node' src/index.ts", &package_json),
+            vec!["ts-node"]
+        );
+    }
+
+    #[test]
+    fn extract_command_binaries_follows_scripts() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let package_json = write_package_json(
+            app_dir.path(),
+            &serde_json::json!({
+                "scripts": {
+                    "start": "npm run --silent migrate && yarn serve",
+                    "migrate": "prisma migrate deploy",
+                    "serve": "pnpm run server",
+                    "server": "ts-node src/index.ts",
+                    "loop": "yarn run loop"
+                }
+            }),
+        );
+        assert_eq!(
+            command_binaries("npm run start", &package_json, 0),
+            vec![
+                ("npm".to_string(), "npm run start".to_string()),
+                (
+                    "npm".to_string(),
+                    "npm run --silent migrate && yarn serve".to_string()
+                ),
+                ("prisma".to_string(), "prisma migrate deploy".to_string()),
+                (
+                    "yarn".to_string(),
+                    "npm run --silent migrate && yarn serve".to_string()
+                ),
+                ("pnpm".to_string(), "pnpm run server".to_string()),
+                ("ts-node".to_string(), "ts-node src/index.ts".to_string()),
+            ]
+        );
+        assert_eq!(
+            programs("yarn loop", &package_json).len(),
+            MAX_SCRIPT_DEPTH + 1
+        );
+        assert_eq!(programs("yarn install", &package_json), vec!["yarn"]);
+    }
+
+    #[test]
+    fn extract_procfile_commands() {
+        assert_eq!(
+            procfile_commands("web: next start -p $PORT\nworker:   node worker.js\n\n"),
+            vec!["next start -p $PORT", "node worker.js"]
+        );
+    }
+
+    #[test]
+    fn collect_and_verify_dev_dependency_binaries() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let node_modules = app_dir.path().join("node_modules");
+        for (name, bin) in [
+            ("next", serde_json::json!({ "next": "dist/bin/next" })),
+            (
+                "ts-node",
+                serde_json::json!({ "ts-node": "dist/bin.js", "ts-node-esm": "dist/bin-esm.js" }),
+            ),
+            ("@nestjs/cli", serde_json::json!({ "nest": "bin/nest.js" })),
+            ("nodemon", serde_json::json!("bin/nodemon.js")),
+        ] {
+            fs::create_dir_all(node_modules.join(name)).unwrap();
+            fs::write(
+                node_modules.join(name).join("package.json"),
+                serde_json::json!({ "name": name, "bin": bin }).to_string(),
+            )
+            .unwrap();
+        }
+        let package_json_path = app_dir.path().join("package.json");
+        fs::write(
+            &package_json_path,
+            serde_json::json!({
+                "scripts": { "dev": "ts-node src/index.ts" },
+                "dependencies": { "nodemon": "^3.0.0" },
+                "devDependencies": {
+                    "next": "^15.0.0",
+                    "ts-node": "^10.0.0",
+                    "@nestjs/cli": "^11.0.0",
+                    "nodemon": "^3.0.0"
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let package_json = PackageJson::try_from(package_json_path).unwrap();
+
+        let dev_dependency_binaries = DevDependencyBinaries::collect(app_dir.path(), &package_json);
+        assert_eq!(
+            dev_dependency_binaries,
+            DevDependencyBinaries(BTreeMap::from([
+                ("nest".to_string(), "@nestjs/cli".to_string()),
+                ("next".to_string(), "next".to_string()),
+                ("ts-node".to_string(), "ts-node".to_string()),
+                ("ts-node-esm".to_string(), "ts-node".to_string()),
+            ]))
+        );
+
+        // simulate pruning, `nest` survives because it is still linked in `node_modules/.bin`
+        let bin_dir = node_modules.join(".bin");
+        fs::create_dir_all(&bin_dir).unwrap();
+        fs::write(bin_dir.join("nest"), "").unwrap();
+
+        assert_eq!(
+            find_missing_binaries(
+                &bin_dir,
+                &[
+                    "next start".to_string(),
+                    "nest start && nodemon server.js".to_string(),
+                    "next build".to_string(),
+                    "npm run dev".to_string(),
+                ],
+                &package_json,
+                &dev_dependency_binaries
+            ),
+            vec![
+                MissingBinary {
+                    binary: "next".to_string(),
+                    package_name: "next".to_string(),
+                    command: "next start".to_string(),
+                },
+                MissingBinary {
+                    binary: "ts-node".to_string(),
+                    package_name: "ts-node".to_string(),
+                    command: "ts-node src/index.ts".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -1,33 +1,66 @@
+use crate::buildpack_config::{BuildpackConfig, ConfigValue};
 use crate::o11y::*;
 use crate::package_json::PackageJson;
+use crate::package_manager::RequestedPackageManager;
 use crate::runtimes::nodejs::NODEJS_INVENTORY;
+use crate::target::TargetMetadata;
 use crate::utils::error_handling::ErrorType::UserFacing;
 use crate::utils::error_handling::{
-    ErrorMessage, SuggestRetryBuild, SuggestSubmitIssue, error_message,
+    ErrorMessage, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
 };
+use crate::workspace_packages::find_workspace_package_dirs;
 use crate::{BuildpackBuildContext, BuildpackResult, runtimes};
 use bullet_stream::global::print;
 use bullet_stream::style;
 use indoc::formatdoc;
 use libcnb::Env;
+use libcnb::data::store::Store;
 use libherokubuildpack::inventory::artifact::{Arch, Os};
-use nodejs_data::{NodejsArtifact, RECOMMENDED_LTS_VERSION, VersionRange};
-use std::env::consts;
-use std::sync::LazyLock;
+use nodejs_data::{NodejsArtifact, RECOMMENDED_LTS_VERSION, Version, VersionRange};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use tracing::instrument;
 
-static OS: LazyLock<Os> = LazyLock::new(|| consts::OS.parse::<Os>().expect("OS should be valid"));
-
-static ARCH: LazyLock<Arch> =
-    LazyLock::new(|| consts::ARCH.parse::<Arch>().expect("ARCH should be valid"));
-
 pub(crate) enum RequestedRuntime {
     NodeJsEngine(VersionRange),
+    /// The `engines.node` ranges declared across the root and workspace packages, keyed on the
+    /// relative path of each `package.json`. The resolved version must satisfy all of them.
+    NodeJsWorkspaceEngines(Vec<(PathBuf, VersionRange)>),
     NodeJsDefault,
 }
 
 #[instrument(skip_all)]
-pub(crate) fn determine_runtime(package_json: &PackageJson) -> RequestedRuntime {
+pub(crate) fn determine_runtime(app_dir: &Path, package_json: &PackageJson) -> RequestedRuntime {
+    let workspace_engines = find_workspace_package_dirs(app_dir, package_json)
+        .into_iter()
+        .map(|package_dir| package_dir.join("package.json"))
+        .filter_map(|path| {
+            read_node_engine(&app_dir.join(&path)).map(|requirement| (path, requirement))
+        })
+        .collect::<Vec<_>>();
+    if !workspace_engines.is_empty() {
+        let mut engines = vec![];
+        if let Some(Ok(requirement)) = package_json.node_engine() {
+            engines.push((PathBuf::from("package.json"), requirement));
+        }
+        engines.extend(workspace_engines);
+        tracing::info!(
+            { RUNTIME_REQUESTED_NAME } = "nodejs",
+            { RUNTIME_REQUESTED_VERSION } = engines
+                .iter()
+                .map(|(_, requirement)| requirement.to_string())
+                .collect::<Vec<_>>()
+                .join(" && "),
+            { RUNTIME_REQUESTED_SOURCES } = engines
+                .iter()
+                .map(|(path, _)| path.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            "runtime"
+        );
+        return RequestedRuntime::NodeJsWorkspaceEngines(engines);
+    }
+
     if let Some(Ok(version)) = package_json.node_engine() {
         tracing::info!(
             { RUNTIME_REQUESTED_NAME } = "nodejs",
@@ -45,8 +78,30 @@ pub(crate) fn determine_runtime(package_json: &PackageJson) -> RequestedRuntime
     }
 }
 
+/// Workspace package manifests are read directly instead of through `PackageJson` to avoid emitting
+/// telemetry for each of them. Missing or invalid ranges are ignored, as they are for the root.
+fn read_node_engine(package_json_path: &Path) -> Option<VersionRange> {
+    std::fs::read_to_string(package_json_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .and_then(|package_json| {
+            package_json["engines"]["node"]
+                .as_str()
+                .and_then(|requirement| VersionRange::parse(requirement).ok())
+        })
+}
+
 pub(crate) fn log_requested_runtime(requested_runtime: &RequestedRuntime) {
     match requested_runtime {
+        RequestedRuntime::NodeJsWorkspaceEngines(engines) => {
+            for (path, requirement) in engines {
+                print::sub_bullet(format!(
+                    "Detected Node.js version range: {} in {}",
+                    style::value(requirement.to_string()),
+                    file_value(path)
+                ));
+            }
+        }
         RequestedRuntime::NodeJsEngine(version) => {
             print::sub_bullet(format!(
                 "Detected Node.js version range: {}",
@@ -77,20 +132,79 @@ pub(crate) fn check_runtime_support_status(
     Ok(resolved_runtime)
 }
 
+/// The `Store` metadata key holding the Node.js version resolved by the previous build.
+const PREVIOUS_NODEJS_VERSION_KEY: &str = "nodejs_version";
+
+pub(crate) fn read_previous_runtime_version(store: &Store) -> Option<Version> {
+    store
+        .metadata
+        .get(PREVIOUS_NODEJS_VERSION_KEY)
+        .and_then(toml::Value::as_str)
+        .and_then(|version| Version::parse(version).ok())
+}
+
+pub(crate) fn record_resolved_runtime(store: &mut Store, resolved_runtime: &ResolvedRuntime) {
+    match resolved_runtime {
+        ResolvedRuntime::Nodejs(artifact) => {
+            store.metadata.insert(
+                PREVIOUS_NODEJS_VERSION_KEY.to_string(),
+                toml::Value::String(artifact.version.to_string()),
+            );
+        }
+    }
+}
+
 #[instrument(skip_all)]
 pub(crate) fn resolve_runtime(
     requested_runtime: RequestedRuntime,
+    target: &TargetMetadata,
+    previous_version: Option<&Version>,
+    buildpack_config: &BuildpackConfig,
 ) -> BuildpackResult<ResolvedRuntime> {
-    match requested_runtime {
-        RequestedRuntime::NodeJsEngine(requirement) => resolve_nodejs_runtime(&requirement),
-        RequestedRuntime::NodeJsDefault => resolve_nodejs_runtime(&RECOMMENDED_LTS_VERSION),
-    }
+    let allow_major_upgrades = matches!(
+        buildpack_config.allow_major_upgrades,
+        Some(ConfigValue { value: true, .. })
+    );
+    let requirement = match requested_runtime {
+        RequestedRuntime::NodeJsEngine(requirement) => requirement,
+        RequestedRuntime::NodeJsWorkspaceEngines(engines) => intersect_workspace_engines(&engines)
+            .ok_or_else(|| create_conflicting_workspace_engines_error(&engines))?,
+        RequestedRuntime::NodeJsDefault => RECOMMENDED_LTS_VERSION.clone(),
+    };
+    resolve_nodejs_runtime(&requirement, target, previous_version, allow_major_upgrades)
 }
 
-fn resolve_nodejs_runtime(requirement: &VersionRange) -> BuildpackResult<ResolvedRuntime> {
-    let artifact = NODEJS_INVENTORY
-        .resolve(*OS, *ARCH, requirement)
-        .ok_or(create_unknown_nodejs_version_error(requirement))?;
+fn intersect_workspace_engines(engines: &[(PathBuf, VersionRange)]) -> Option<VersionRange> {
+    let ((_, first), rest) = engines.split_first()?;
+    rest.iter()
+        .try_fold(first.clone(), |intersection, (_, requirement)| {
+            intersection.intersect(requirement)
+        })
+}
+
+fn resolve_nodejs_runtime(
+    requirement: &VersionRange,
+    target: &TargetMetadata,
+    previous_version: Option<&Version>,
+    allow_major_upgrades: bool,
+) -> BuildpackResult<ResolvedRuntime> {
+    let (os, arch) = nodejs_inventory_target(target)?;
+    let (artifact, major_resolution) = resolve_nodejs_artifact(
+        requirement,
+        os,
+        arch,
+        previous_version,
+        allow_major_upgrades,
+    )
+    .ok_or(create_unknown_nodejs_version_error(requirement))?;
+    if let Some((previous_version, major_resolution)) = previous_version.zip(major_resolution) {
+        tracing::info!(
+            { RUNTIME_PREVIOUS_VERSION } = previous_version.to_string(),
+            { RUNTIME_MAJOR_RESOLUTION } = major_resolution.to_string(),
+            "runtime"
+        );
+        log_major_resolution(previous_version, &major_resolution, allow_major_upgrades);
+    }
     tracing::info!(
         { RUNTIME_NAME } = "nodejs",
         { RUNTIME_VERSION } = artifact.version.to_string(),
@@ -101,6 +215,150 @@ fn resolve_nodejs_runtime(requirement: &VersionRange) -> BuildpackResult<Resolve
     Ok(ResolvedRuntime::Nodejs(artifact.clone()))
 }
 
+/// How the major version of the previous build was handled when an open-ended range allows a
+/// newer major release from the inventory.
+#[derive(Debug, PartialEq)]
+enum MajorResolution {
+    /// The previous major line was kept and a newer major is available.
+    Pinned { latest_version: Version },
+    /// The newer major was resolved, either because major upgrades are allowed or because the
+    /// requested range no longer includes the previous major.
+    Upgraded,
+}
+
+impl Display for MajorResolution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MajorResolution::Pinned { .. } => write!(f, "pinned"),
+            MajorResolution::Upgraded => write!(f, "upgraded"),
+        }
+    }
+}
+
+/// Resolves the highest release satisfying the requirement. When a previous build resolved an
+/// older major version and the requirement has no upper bound (e.g. `>=20`), the previous major
+/// line is kept unless major upgrades are allowed so a routine rebuild doesn't silently move the
+/// app onto a new major release as soon as the inventory gains one.
+fn resolve_nodejs_artifact(
+    requirement: &VersionRange,
+    os: Os,
+    arch: Arch,
+    previous_version: Option<&Version>,
+    allow_major_upgrades: bool,
+) -> Option<(&'static NodejsArtifact, Option<MajorResolution>)> {
+    let latest = NODEJS_INVENTORY.resolve(os, arch, requirement)?;
+    let Some(previous_version) = previous_version.filter(|previous_version| {
+        latest.version.major() > previous_version.major() && is_open_ended(requirement)
+    }) else {
+        return Some((latest, None));
+    };
+    if !allow_major_upgrades
+        && let Some(pinned) = VersionRange::parse(&format!("{}.x", previous_version.major()))
+            .ok()
+            .and_then(|previous_major| requirement.intersect(&previous_major))
+            .and_then(|requirement| NODEJS_INVENTORY.resolve(os, arch, &requirement))
+    {
+        return Some((
+            pinned,
+            Some(MajorResolution::Pinned {
+                latest_version: latest.version.clone(),
+            }),
+        ));
+    }
+    Some((latest, Some(MajorResolution::Upgraded)))
+}
+
+/// A range is open-ended when it would accept any future major release.
+fn is_open_ended(requirement: &VersionRange) -> bool {
+    requirement.satisfies(&Version::new(u64::from(u32::MAX), 0, 0))
+}
+
+fn log_major_resolution(
+    previous_version: &Version,
+    major_resolution: &MajorResolution,
+    allow_major_upgrades: bool,
+) {
+    let previous_major = style::value(format!("{}.x", previous_version.major()));
+    match major_resolution {
+        MajorResolution::Pinned { latest_version } => {
+            tracing::info!(
+                { RUNTIME_LATEST_VERSION } = latest_version.to_string(),
+                "runtime"
+            );
+            print::sub_bullet(format!(
+                "Keeping Node.js {previous_major} from the previous build. Node.js {} is \
+                available, set {} to {} in {} to upgrade",
+                style::value(latest_version.to_string()),
+                style::value("runtime.allow_major_upgrades"),
+                style::value("true"),
+                file_value("project.toml"),
+            ));
+        }
+        MajorResolution::Upgraded if allow_major_upgrades => {
+            print::sub_bullet(format!(
+                "Upgrading from Node.js {previous_major} used by the previous build as {} is enabled",
+                style::value("runtime.allow_major_upgrades"),
+            ));
+        }
+        MajorResolution::Upgraded => {
+            print::sub_bullet(format!(
+                "Upgrading from Node.js {previous_major} used by the previous build as it no \
+                longer satisfies the requested range"
+            ));
+        }
+    }
+}
+
+/// Distros that use musl instead of glibc. The target metadata doesn't declare which libc a distro
+/// uses so this list has to be kept up to date by hand.
+const MUSL_DISTROS: [&str; 1] = ["alpine"];
+
+/// Maps the build target onto the platforms in the inventory. The Node.js distributions in the
+/// inventory are linked against glibc so the distros in [`MUSL_DISTROS`] are also rejected.
+fn nodejs_inventory_target(target: &TargetMetadata) -> Result<(Os, Arch), ErrorMessage> {
+    target
+        .os
+        .parse::<Os>()
+        .ok()
+        .zip(target.arch.parse::<Arch>().ok())
+        .filter(|(os, arch)| {
+            !MUSL_DISTROS.contains(&target.distro_name.as_str())
+                && NODEJS_INVENTORY
+                    .artifacts
+                    .iter()
+                    .any(|artifact| artifact.os == *os && artifact.arch == *arch)
+        })
+        .ok_or_else(|| create_unsupported_target_error(target))
+}
+
+fn create_unsupported_target_error(target: &TargetMetadata) -> ErrorMessage {
+    let musl_distros = MUSL_DISTROS
+        .iter()
+        .map(style::value)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let target = style::value(target.to_string());
+    let amd64 = style::value("amd64");
+    let arm64 = style::value("arm64");
+    let platform = style::value("--platform linux/amd64");
+    error_message()
+        .id("runtime/unsupported_target")
+        .error_type(UserFacing(SuggestRetryBuild::No, SuggestSubmitIssue::No))
+        .header(format!("Unsupported build target: {target}"))
+        .body(formatdoc! {"
+            This buildpack installs the official Node.js Linux distributions for the {amd64} and \
+            {arm64} architectures on glibc-based distros, but the build is targeting {target}.
+
+            Distros that use musl instead of glibc ({musl_distros}) are not supported. Other \
+            distros are assumed to use glibc.
+
+            Suggestions:
+            - Build the application with a supported builder and run image.
+            - Request a supported platform (e.g. {platform}) when building.
+        "})
+        .create()
+}
+
 pub(crate) fn log_resolved_runtime(resolved_runtime: &ResolvedRuntime) {
     match resolved_runtime {
         ResolvedRuntime::Nodejs(artifact) => print::sub_bullet(format!(
@@ -110,6 +368,36 @@ pub(crate) fn log_resolved_runtime(resolved_runtime: &ResolvedRuntime) {
     }
 }
 
+fn create_conflicting_workspace_engines_error(engines: &[(PathBuf, VersionRange)]) -> ErrorMessage {
+    let engines_node = style::value("engines.node");
+    let declared_ranges = engines
+        .iter()
+        .map(|(path, requirement)| {
+            format!(
+                "- {} in {}",
+                style::value(requirement.to_string()),
+                file_value(path)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    error_message()
+        .id("runtime/conflicting_workspace_engines")
+        .error_type(UserFacing(SuggestRetryBuild::No, SuggestSubmitIssue::No))
+        .header("Conflicting Node.js version ranges")
+        .body(formatdoc! {"
+            The workspace packages declare {engines_node} ranges that no single Node.js version can \
+            satisfy. Every package in the workspace runs on the same Node.js installation so the \
+            resolved version must satisfy all of these ranges:
+
+            {declared_ranges}
+
+            Suggestions:
+            - Update the {engines_node} ranges so they share at least one Node.js version.
+        "})
+        .create()
+}
+
 fn create_unknown_nodejs_version_error(requirement: &VersionRange) -> ErrorMessage {
     let node_releases_url = style::url(format!(
         "https://github.com/nodejs/node/releases?q=\"v{requirement}\"&expanded=true"
@@ -137,11 +425,20 @@ pub(crate) fn install_runtime(
     context: &BuildpackBuildContext,
     env: &mut Env,
     resolved_runtime: ResolvedRuntime,
+    buildpack_config: &BuildpackConfig,
+    requested_package_manager: &RequestedPackageManager,
 ) -> BuildpackResult<()> {
     match resolved_runtime {
         ResolvedRuntime::Nodejs(artifact) => {
+            let slim_runtime = matches!(
+                buildpack_config.slim_runtime,
+                Some(ConfigValue { value: true, .. })
+            )
+            .then(|| runtimes::nodejs::SlimRuntime {
+                keep_npm: requested_package_manager.is_npm(),
+            });
             // TODO: confirm installation and version by calling `node --version`
-            runtimes::nodejs::install(context, env, &artifact)?;
+            runtimes::nodejs::install(context, env, &artifact, slim_runtime)?;
             Ok(())
         }
     }
@@ -152,6 +449,220 @@ mod tests {
     use super::*;
     use crate::utils::error_handling::test_util::assert_error_snapshot;
 
+    fn target(os: &str, arch: &str, distro_name: &str) -> TargetMetadata {
+        TargetMetadata {
+            os: os.to_string(),
+            arch: arch.to_string(),
+            arch_variant: None,
+            distro_name: distro_name.to_string(),
+            distro_version: "24.04".to_string(),
+        }
+    }
+
+    #[test]
+    fn inventory_target_from_build_target() {
+        assert_eq!(
+            nodejs_inventory_target(&target("linux", "amd64", "ubuntu")).unwrap(),
+            (Os::Linux, Arch::Amd64)
+        );
+        assert_eq!(
+            nodejs_inventory_target(&target("linux", "arm64", "ubuntu")).unwrap(),
+            (Os::Linux, Arch::Arm64)
+        );
+        assert!(nodejs_inventory_target(&target("linux", "arm", "ubuntu")).is_err());
+        assert!(nodejs_inventory_target(&target("darwin", "arm64", "macos")).is_err());
+        assert!(nodejs_inventory_target(&target("linux", "amd64", "alpine")).is_err());
+    }
+
+    #[test]
+    fn unsupported_target_error() {
+        let mut target = target("linux", "arm", "ubuntu");
+        target.arch_variant = Some("v7".to_string());
+        assert_error_snapshot(&create_unsupported_target_error(&target));
+    }
+
+    #[test]
+    fn unsupported_musl_target_error() {
+        assert_error_snapshot(&create_unsupported_target_error(&target(
+            "linux", "amd64", "alpine",
+        )));
+    }
+
+    fn write_package_json(dir: &Path, package_json: &serde_json::Value) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("package.json"), package_json.to_string()).unwrap();
+    }
+
+    #[test]
+    fn determine_runtime_from_workspace_engines() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let app_dir = app_dir.path();
+        write_package_json(
+            app_dir,
+            &serde_json::json!({
+                "engines": { "node": ">=20" },
+                "workspaces": ["packages/*"],
+            }),
+        );
+        write_package_json(
+            &app_dir.join("packages/api"),
+            &serde_json::json!({ "engines": { "node": "^22 || ^24" } }),
+        );
+        write_package_json(&app_dir.join("packages/web"), &serde_json::json!({}));
+        let package_json = PackageJson::try_from(app_dir.join("package.json")).unwrap();
+
+        let RequestedRuntime::NodeJsWorkspaceEngines(engines) =
+            determine_runtime(app_dir, &package_json)
+        else {
+            panic!("Workspace engines should be requested");
+        };
+        assert_eq!(
+            engines
+                .iter()
+                .map(|(path, requirement)| (path.to_string_lossy(), requirement.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("package.json".into(), ">=20".to_string()),
+                ("packages/api/package.json".into(), "^22 || ^24".to_string()),
+            ]
+        );
+
+        let requirement = intersect_workspace_engines(&engines).unwrap();
+        assert!(requirement.satisfies(&nodejs_data::Version::new(24, 0, 0)));
+        assert!(!requirement.satisfies(&nodejs_data::Version::new(20, 0, 0)));
+    }
+
+    #[test]
+    fn determine_runtime_without_workspace_engines() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let app_dir = app_dir.path();
+        write_package_json(
+            app_dir,
+            &serde_json::json!({
+                "engines": { "node": "22.x" },
+                "workspaces": ["packages/*"],
+            }),
+        );
+        write_package_json(&app_dir.join("packages/web"), &serde_json::json!({}));
+        let package_json = PackageJson::try_from(app_dir.join("package.json")).unwrap();
+
+        assert!(matches!(
+            determine_runtime(app_dir, &package_json),
+            RequestedRuntime::NodeJsEngine(_)
+        ));
+    }
+
+    #[test]
+    fn conflicting_workspace_engines_error() {
+        let engines = vec![
+            (
+                PathBuf::from("package.json"),
+                VersionRange::parse(">=24").unwrap(),
+            ),
+            (
+                PathBuf::from("packages/legacy/package.json"),
+                VersionRange::parse("20.x").unwrap(),
+            ),
+        ];
+        assert!(intersect_workspace_engines(&engines).is_none());
+        assert_error_snapshot(&create_conflicting_workspace_engines_error(&engines));
+    }
+
+    #[test]
+    fn resolve_nodejs_artifact_keeps_previous_major_for_open_ended_range() {
+        let requirement = VersionRange::parse(">=22").unwrap();
+        let latest = NODEJS_INVENTORY
+            .resolve(Os::Linux, Arch::Amd64, &requirement)
+            .unwrap();
+        let previous_version = Version::new(22, 0, 0);
+
+        let (artifact, major_resolution) = resolve_nodejs_artifact(
+            &requirement,
+            Os::Linux,
+            Arch::Amd64,
+            Some(&previous_version),
+            false,
+        )
+        .unwrap();
+        assert_eq!(artifact.version.major(), 22);
+        assert!(artifact.version > previous_version);
+        assert_eq!(
+            major_resolution,
+            Some(MajorResolution::Pinned {
+                latest_version: latest.version.clone()
+            })
+        );
+
+        let (artifact, major_resolution) = resolve_nodejs_artifact(
+            &requirement,
+            Os::Linux,
+            Arch::Amd64,
+            Some(&previous_version),
+            true,
+        )
+        .unwrap();
+        assert_eq!(artifact.version, latest.version);
+        assert_eq!(major_resolution, Some(MajorResolution::Upgraded));
+    }
+
+    #[test]
+    fn resolve_nodejs_artifact_ignores_previous_major_outside_open_ended_range() {
+        let previous_version = Version::new(22, 0, 0);
+        for requirement in ["22.x || 24.x", ">=22 <=24.0.0"] {
+            let (_, major_resolution) = resolve_nodejs_artifact(
+                &VersionRange::parse(requirement).unwrap(),
+                Os::Linux,
+                Arch::Amd64,
+                Some(&previous_version),
+                false,
+            )
+            .unwrap();
+            assert_eq!(major_resolution, None, "{requirement}");
+        }
+
+        let (artifact, major_resolution) = resolve_nodejs_artifact(
+            &VersionRange::parse(">=24").unwrap(),
+            Os::Linux,
+            Arch::Amd64,
+            Some(&previous_version),
+            false,
+        )
+        .unwrap();
+        assert!(artifact.version.major() >= 24);
+        assert_eq!(major_resolution, Some(MajorResolution::Upgraded));
+
+        let (_, major_resolution) = resolve_nodejs_artifact(
+            &VersionRange::parse(">=22").unwrap(),
+            Os::Linux,
+            Arch::Amd64,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(major_resolution, None);
+    }
+
+    #[test]
+    fn previous_runtime_version_round_trips_through_store() {
+        let mut store = Store {
+            metadata: toml::Table::new(),
+        };
+        assert_eq!(read_previous_runtime_version(&store), None);
+
+        let artifact = NODEJS_INVENTORY
+            .resolve(
+                Os::Linux,
+                Arch::Amd64,
+                &VersionRange::parse("22.x").unwrap(),
+            )
+            .unwrap();
+        record_resolved_runtime(&mut store, &ResolvedRuntime::Nodejs(artifact.clone()));
+        assert_eq!(
+            read_previous_runtime_version(&store),
+            Some(artifact.version.clone())
+        );
+    }
+
     #[test]
     fn unknown_nodejs_version_error() {
         assert_error_snapshot(&create_unknown_nodejs_version_error(
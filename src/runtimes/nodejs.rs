@@ -1,9 +1,11 @@
+use crate::target::TargetMetadata;
 use crate::utils::error_handling::ErrorType::{Internal, UserFacing};
 use crate::utils::error_handling::{
     ErrorMessage, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
 };
+use crate::utils::fs::{directory_size, format_bytes};
 use crate::utils::http::{
-    ChecksumValidator, DownloadError, DownloadTask, Extractor, GzipOptions, download,
+    ChecksumValidator, DownloadError, DownloadTask, Extractor, TarOptions, download,
 };
 use crate::{BuildpackBuildContext, BuildpackResult};
 use bullet_stream::global::print;
@@ -14,10 +16,13 @@ use libcnb::Env;
 use libcnb::data::layer_name;
 use libcnb::layer::{
     CachedLayerDefinition, InvalidMetadataAction, LayerState, RestoredLayerAction,
+    UncachedLayerDefinition,
 };
-use libcnb::layer_env::Scope;
+use libcnb::layer_env::{LayerEnv, ModificationBehavior, Scope};
 use nodejs_data::{NodejsArtifact, NodejsInventory, Version, VersionCommandError};
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
 use std::process::Command;
 use std::sync::LazyLock;
 
@@ -26,14 +31,53 @@ pub(crate) static NODEJS_INVENTORY: LazyLock<NodejsInventory> = LazyLock::new(||
         .expect("Inventory file should be valid")
 });
 
+#[allow(clippy::too_many_lines)]
 pub(crate) fn install(
     context: &BuildpackBuildContext,
     env: &mut Env,
     distribution_artifact: &NodejsArtifact,
+    slim_runtime: Option<SlimRuntime>,
 ) -> BuildpackResult<()> {
     print::bullet("Installing Node.js distribution");
 
-    let new_metadata = NodejsLayerMetadata::from(distribution_artifact.clone());
+    let new_metadata = NodejsLayerMetadata {
+        slim: slim_runtime,
+        ..NodejsLayerMetadata::new(
+            distribution_artifact.clone(),
+            TargetMetadata::from(&context.target),
+        )
+    };
+
+    let build_layer = if slim_runtime.is_some() {
+        Some(context.cached_layer(
+            layer_name!("dist_build"),
+            CachedLayerDefinition {
+                build: true,
+                launch: false,
+                invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
+                restored_layer_action: &|old_metadata: &NodejsLayerMetadata, _| {
+                    if old_metadata == &new_metadata {
+                        RestoredLayerAction::KeepLayer
+                    } else {
+                        RestoredLayerAction::DeleteLayer
+                    }
+                },
+            },
+        )?)
+    } else {
+        // clears the build-only files left behind by a previous slim build
+        context.uncached_layer(
+            layer_name!("dist_build"),
+            UncachedLayerDefinition {
+                build: false,
+                launch: false,
+            },
+        )?;
+        None
+    };
+    let build_layer_restored = build_layer
+        .as_ref()
+        .is_none_or(|layer| matches!(layer.state, LayerState::Restored { .. }));
 
     let distribution_layer = context.cached_layer(
         // TODO: change this layer name to nodejs_runtime after the package managers are cleaned up
@@ -43,7 +87,9 @@ pub(crate) fn install(
             launch: true,
             invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
             restored_layer_action: &|old_metadata: &NodejsLayerMetadata, _| {
-                if old_metadata == &new_metadata {
+                // the build-only files of a slim runtime are in a separate layer and both must be
+                // reused
+                if old_metadata == &new_metadata && build_layer_restored {
                     RestoredLayerAction::KeepLayer
                 } else {
                     RestoredLayerAction::DeleteLayer
@@ -62,15 +108,12 @@ pub(crate) fn install(
             print::sub_bullet(format!("Reusing Node.js {version_tag}"));
         }
         LayerState::Empty { .. } => {
-            download(
+            let download_summary = download(
                 &DownloadTask::builder(&distribution_artifact.url, distribution_layer.path())
                     .checksum_validator(ChecksumValidator::Sha256(
                         &distribution_artifact.checksum.value,
                     ))
-                    .extractor(Extractor::Gzip(GzipOptions {
-                        strip_components: 1,
-                        ..GzipOptions::default()
-                    }))
+                    .extractor(distribution_extractor(distribution_artifact, slim_runtime))
                     .build(),
             )
             .map_err(create_downloader_error)?;
@@ -80,15 +123,170 @@ pub(crate) fn install(
             print::sub_bullet("Verifying checksum");
             print::sub_bullet(format!("Extracting Node.js {version_info}"));
             print::sub_start_timer(format!("Installing Node.js {version_info}")).done();
+
+            if let (Some(build_layer), Some(slim_runtime)) = (&build_layer, slim_runtime) {
+                let moved_bytes = move_build_only_paths(
+                    &distribution_layer.path(),
+                    &build_layer.path(),
+                    slim_runtime,
+                )
+                .map_err(|e| create_move_build_only_paths_error(&e))?;
+                build_layer.write_env(build_layer_env(&build_layer.path(), slim_runtime))?;
+                build_layer.write_metadata(new_metadata.clone())?;
+                print::sub_bullet(format!(
+                    "Removed {} from the launch image ({} kept for the build)",
+                    style::value(format_bytes(download_summary.excluded_bytes + moved_bytes)),
+                    if slim_runtime.keep_npm {
+                        "headers are"
+                    } else {
+                        "headers and npm are"
+                    }
+                ));
+            }
+
             distribution_layer.write_metadata(new_metadata)?;
         }
     }
 
+    if let Some(build_layer) = &build_layer {
+        env.clone_from(&build_layer.read_env()?.apply(Scope::Build, env));
+    } else {
+        distribution_layer.write_env(node_gyp_headers_env(&distribution_layer.path()))?;
+    }
     env.clone_from(&distribution_layer.read_env()?.apply(Scope::Build, env));
 
     Ok(())
 }
 
+/// Points node-gyp at the headers in `<dir>/include` so native modules are built against them
+/// instead of headers downloaded from nodejs.org. A user-provided value still takes precedence.
+fn node_gyp_headers_env(dir: &Path) -> LayerEnv {
+    LayerEnv::new().chainable_insert(
+        Scope::Build,
+        ModificationBehavior::Default,
+        "npm_config_nodedir",
+        dir,
+    )
+}
+
+/// The `bin` directory of the build-only layer is added to `PATH` implicitly. Yarn 1 looks for the
+/// node-gyp bundled with npm next to the `node` binary, so when npm was moved out of the
+/// distribution its node-gyp wrapper is also added to `PATH` for the build.
+fn build_layer_env(build_dir: &Path, slim_runtime: SlimRuntime) -> LayerEnv {
+    let layer_env = node_gyp_headers_env(build_dir);
+    if slim_runtime.keep_npm {
+        layer_env
+    } else {
+        layer_env
+            .chainable_insert(
+                Scope::Build,
+                ModificationBehavior::Prepend,
+                "PATH",
+                build_dir.join("lib/node_modules/npm/bin/node-gyp-bin"),
+            )
+            .chainable_insert(Scope::Build, ModificationBehavior::Delimiter, "PATH", ":")
+    }
+}
+
+/// Opt-in removal of the parts of the distribution that an app doesn't need at runtime, enabled
+/// with `runtime.slim = true`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SlimRuntime {
+    /// The bundled npm is only kept in the launch image when npm is the package manager for the
+    /// app. Otherwise it is moved to the build-only layer since it is still used during the build
+    /// (e.g.; to rebuild packages for Yarn 1).
+    pub(crate) keep_npm: bool,
+}
+
+/// Paths (relative to the distribution root) that a slim runtime never needs.
+const SLIM_EXCLUDED_PATHS: [&str; 7] = [
+    "CHANGELOG.md",
+    "README.md",
+    "bin/corepack",
+    "lib/node_modules/corepack",
+    "share/doc",
+    "share/man",
+    "share/systemtap",
+];
+
+/// Paths (relative to the distribution root) of the bundled npm. The `bin` entries are relative
+/// symlinks into `lib/node_modules/npm` so they still resolve after being moved together.
+const SLIM_NPM_PATHS: [&str; 3] = ["bin/npm", "bin/npx", "lib/node_modules/npm"];
+
+fn slim_exclude(path: &Path) -> bool {
+    SLIM_EXCLUDED_PATHS
+        .iter()
+        .any(|excluded| path.starts_with(excluded))
+}
+
+/// Moves `include/` and, unless npm is kept in the launch image, the bundled npm into the
+/// build-only layer so native modules can still be compiled against the headers with
+/// `npm_config_nodedir` and npm remains available to the build. Returns the size of the moved
+/// files.
+fn move_build_only_paths(
+    distribution_dir: &Path,
+    build_dir: &Path,
+    slim_runtime: SlimRuntime,
+) -> std::io::Result<u64> {
+    let mut size = 0;
+    for path in std::iter::once("include").chain(if slim_runtime.keep_npm {
+        [].iter().copied()
+    } else {
+        SLIM_NPM_PATHS.iter().copied()
+    }) {
+        let source = distribution_dir.join(path);
+        let destination = build_dir.join(path);
+        let Ok(metadata) = fs::symlink_metadata(&source) else {
+            continue;
+        };
+        if !metadata.is_symlink() {
+            size += directory_size(&source)?;
+        }
+        match fs::symlink_metadata(&destination) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&destination)?,
+            Ok(_) => fs::remove_file(&destination)?,
+            Err(_) => {}
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&source, &destination)?;
+    }
+    Ok(size)
+}
+
+fn create_move_build_only_paths_error(error: &std::io::Error) -> ErrorMessage {
+    error_message()
+        .id("runtime/nodejs/slim/move_build_only_paths")
+        .error_type(Internal)
+        .header("Failed to separate build-only Node.js files")
+        .body(formatdoc! {"
+            An unexpected I/O error occurred while moving the Node.js headers and npm out of the \
+            runtime layer for {slim}.
+        ", slim = style::value("runtime.slim = true") })
+        .debug_info(error.to_string())
+        .create()
+}
+
+/// Distributions are published as both `.tar.gz` and `.tar.xz` so the archive format is chosen by
+/// the url of the artifact selected from the inventory.
+fn distribution_extractor(
+    distribution_artifact: &NodejsArtifact,
+    slim_runtime: Option<SlimRuntime>,
+) -> Extractor {
+    let tar_options = TarOptions {
+        strip_components: 1,
+        exclude: slim_runtime
+            .map_or_else(|| TarOptions::default().exclude, |_| Box::new(slim_exclude)),
+    };
+    if distribution_artifact.url.ends_with(".tar.xz") {
+        Extractor::Xz(tar_options)
+    } else {
+        Extractor::Gzip(tar_options)
+    }
+}
+
 fn create_downloader_error(error: DownloadError) -> ErrorMessage {
     match error {
         DownloadError::Download { url, source } => {
@@ -111,6 +309,22 @@ fn create_downloader_error(error: DownloadError) -> ErrorMessage {
                 .create()
         }
 
+        DownloadError::ReadFile { url, source } => {
+            let url = style::url(url);
+            error_message()
+                .id("runtime/nodejs/download/read_file")
+                .error_type(UserFacing(SuggestRetryBuild::Yes, SuggestSubmitIssue::No))
+                .header("Failed to read Node.js distribution")
+                .body(formatdoc! {"
+                    An unexpected I/O error occurred while reading the Node.js distribution from {url}.
+
+                    Suggestions:
+                    - Confirm the file exists and has read permissions.
+                "})
+                .debug_info(source.to_string())
+                .create()
+        }
+
         DownloadError::ChecksumMismatch {
             url,
             expected_checksum,
@@ -155,20 +369,25 @@ fn create_downloader_error(error: DownloadError) -> ErrorMessage {
     }
 }
 
-const LAYER_VERSION: &str = "1";
+const LAYER_VERSION: &str = "2";
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct NodejsLayerMetadata {
     artifact: NodejsArtifact,
     layer_version: String,
+    target: TargetMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slim: Option<SlimRuntime>,
 }
 
-impl From<NodejsArtifact> for NodejsLayerMetadata {
-    fn from(value: NodejsArtifact) -> Self {
+impl NodejsLayerMetadata {
+    fn new(artifact: NodejsArtifact, target: TargetMetadata) -> Self {
         Self {
-            artifact: value,
+            artifact,
             layer_version: LAYER_VERSION.to_string(),
+            target,
+            slim: None,
         }
     }
 }
@@ -236,27 +455,151 @@ mod tests {
         }
     }
 
+    fn create_target(arch: &str) -> TargetMetadata {
+        TargetMetadata {
+            os: "linux".to_string(),
+            arch: arch.to_string(),
+            arch_variant: None,
+            distro_name: "ubuntu".to_string(),
+            distro_version: "24.04".to_string(),
+        }
+    }
+
     #[test]
     fn metadata_sanity_check() {
         // this is a check to ensure that the same node.js artifact doesn't invalidate the cache
         assert_eq!(
-            NodejsLayerMetadata::from(create_nodejs_artifact("22.0.0")),
-            NodejsLayerMetadata::from(create_nodejs_artifact("22.0.0"))
+            NodejsLayerMetadata::new(create_nodejs_artifact("22.0.0"), create_target("arm64")),
+            NodejsLayerMetadata::new(create_nodejs_artifact("22.0.0"), create_target("arm64"))
         );
 
         // this is a check to ensure that a different node.js artifact does invalidate the cache
         assert_ne!(
-            NodejsLayerMetadata::from(create_nodejs_artifact("22.0.0")),
-            NodejsLayerMetadata::from(create_nodejs_artifact("24.0.0")),
+            NodejsLayerMetadata::new(create_nodejs_artifact("22.0.0"), create_target("arm64")),
+            NodejsLayerMetadata::new(create_nodejs_artifact("24.0.0"), create_target("arm64")),
+        );
+
+        // this is a check to ensure that a different build target does invalidate the cache
+        assert_ne!(
+            NodejsLayerMetadata::new(create_nodejs_artifact("22.0.0"), create_target("arm64")),
+            NodejsLayerMetadata::new(create_nodejs_artifact("22.0.0"), create_target("amd64")),
+        );
+    }
+
+    #[test]
+    fn distribution_extractor_matches_artifact_format() {
+        let mut artifact = create_nodejs_artifact("22.0.0");
+        assert!(matches!(
+            distribution_extractor(&artifact, None),
+            Extractor::Gzip(TarOptions {
+                strip_components: 1,
+                ..
+            })
+        ));
+
+        artifact.url = artifact.url.replace(".tar.gz", ".tar.xz");
+        assert!(matches!(
+            distribution_extractor(&artifact, None),
+            Extractor::Xz(TarOptions {
+                strip_components: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn slim_exclude_keeps_build_only_paths() {
+        assert!(slim_exclude(Path::new("share/doc/node/gdbinit")));
+        assert!(slim_exclude(Path::new("share/man/man1/node.1")));
+        assert!(slim_exclude(Path::new(
+            "lib/node_modules/corepack/package.json"
+        )));
+        assert!(slim_exclude(Path::new("bin/corepack")));
+        assert!(!slim_exclude(Path::new("bin/node")));
+        // headers and npm are extracted then moved into the build-only layer
+        assert!(!slim_exclude(Path::new("include/node/node.h")));
+        assert!(!slim_exclude(Path::new(
+            "lib/node_modules/npm/package.json"
+        )));
+        assert!(!slim_exclude(Path::new("bin/npm")));
+    }
+
+    fn create_distribution_dir() -> tempfile::TempDir {
+        let distribution_dir = tempfile::tempdir().unwrap();
+        let npm_dir = distribution_dir.path().join("lib/node_modules/npm");
+        fs::create_dir_all(distribution_dir.path().join("include/node")).unwrap();
+        fs::write(distribution_dir.path().join("include/node/node.h"), "12345").unwrap();
+        fs::create_dir_all(npm_dir.join("bin")).unwrap();
+        fs::write(npm_dir.join("bin/npm-cli.js"), "123").unwrap();
+        fs::create_dir_all(distribution_dir.path().join("bin")).unwrap();
+        std::os::unix::fs::symlink(
+            "../lib/node_modules/npm/bin/npm-cli.js",
+            distribution_dir.path().join("bin/npm"),
+        )
+        .unwrap();
+        fs::write(distribution_dir.path().join("bin/node"), "node").unwrap();
+        distribution_dir
+    }
+
+    #[test]
+    fn move_build_only_paths_replaces_previous_headers() {
+        let distribution_dir = create_distribution_dir();
+        let build_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(build_dir.path().join("include/node")).unwrap();
+        fs::write(build_dir.path().join("include/node/stale.h"), "stale").unwrap();
+
+        assert_eq!(
+            move_build_only_paths(
+                distribution_dir.path(),
+                build_dir.path(),
+                SlimRuntime { keep_npm: true }
+            )
+            .unwrap(),
+            5
+        );
+        assert!(!distribution_dir.path().join("include").exists());
+        assert!(build_dir.path().join("include/node/node.h").is_file());
+        assert!(!build_dir.path().join("include/node/stale.h").exists());
+        assert!(distribution_dir.path().join("bin/npm").is_file());
+        assert!(!build_dir.path().join("bin/npm").exists());
+    }
+
+    #[test]
+    fn move_build_only_paths_moves_npm_out_of_launch_layer() {
+        let distribution_dir = create_distribution_dir();
+        let build_dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(
+            move_build_only_paths(
+                distribution_dir.path(),
+                build_dir.path(),
+                SlimRuntime { keep_npm: false }
+            )
+            .unwrap(),
+            8
+        );
+        assert!(!distribution_dir.path().join("bin/npm").exists());
+        assert!(
+            !distribution_dir
+                .path()
+                .join("lib/node_modules/npm")
+                .exists()
+        );
+        assert!(distribution_dir.path().join("bin/node").is_file());
+        // the relative symlink still resolves to the moved npm
+        assert_eq!(
+            fs::read_to_string(build_dir.path().join("bin/npm")).unwrap(),
+            "123"
         );
     }
 
     #[test]
     fn metadata_guard() {
-        let metadata = NodejsLayerMetadata::from(create_nodejs_artifact("22.0.0"));
+        let metadata =
+            NodejsLayerMetadata::new(create_nodejs_artifact("22.0.0"), create_target("arm64"));
         let actual = toml::to_string(&metadata).unwrap();
         let expected = r#"
-layer_version = "1"
+layer_version = "2"
 
 [artifact]
 version = "22.0.0"
@@ -264,6 +607,12 @@ os = "linux"
 arch = "arm64"
 url = "https://nodejs.org/download/release/v22.0.0/node-v22.0.0-linux-arm64.tar.gz"
 checksum = "sha256:0000000000000000000000000000000000000000000000000000000000000000"
+
+[target]
+os = "linux"
+arch = "arm64"
+distro_name = "ubuntu"
+distro_version = "24.04"
 "#
         .trim();
         assert_eq!(expected, actual.trim());
@@ -290,6 +639,14 @@ checksum = "sha256:0000000000000000000000000000000000000000000000000000000000000
         }));
     }
 
+    #[test]
+    fn download_read_file_error() {
+        assert_error_snapshot(&create_downloader_error(DownloadError::ReadFile {
+            url: "file:///workspace/.heroku/vendor/node-v23.6.0-linux-arm64.tar.gz".into(),
+            source: std::io::Error::from(std::io::ErrorKind::NotFound),
+        }));
+    }
+
     #[test]
     fn download_checksum_error() {
         assert_error_snapshot(&create_downloader_error(DownloadError::ChecksumMismatch {
@@ -300,6 +657,13 @@ checksum = "sha256:0000000000000000000000000000000000000000000000000000000000000
         }));
     }
 
+    #[test]
+    fn slim_move_build_only_paths_error() {
+        assert_error_snapshot(&create_move_build_only_paths_error(&std::io::Error::other(
+            "Test I/O error",
+        )));
+    }
+
     #[test]
     fn test_get_node_version_parse_error() {
         assert_error_snapshot(&create_get_node_version_command_error(
@@ -0,0 +1,57 @@
+use libcnb::Target;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The CNB target of the build, recorded in the metadata of cached layers so contents installed for
+/// one target are never restored into a build for another.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TargetMetadata {
+    pub(crate) os: String,
+    pub(crate) arch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) arch_variant: Option<String>,
+    pub(crate) distro_name: String,
+    pub(crate) distro_version: String,
+}
+
+impl From<&Target> for TargetMetadata {
+    fn from(value: &Target) -> Self {
+        Self {
+            os: value.os.clone(),
+            arch: value.arch.clone(),
+            arch_variant: value.arch_variant.clone(),
+            distro_name: value.distro_name.clone(),
+            distro_version: value.distro_version.clone(),
+        }
+    }
+}
+
+impl fmt::Display for TargetMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.arch)?;
+        if let Some(arch_variant) = &self.arch_variant {
+            write!(f, "/{arch_variant}")?;
+        }
+        write!(f, " ({} {})", self.distro_name, self.distro_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_target() {
+        let mut target = TargetMetadata {
+            os: "linux".to_string(),
+            arch: "arm64".to_string(),
+            arch_variant: None,
+            distro_name: "ubuntu".to_string(),
+            distro_version: "24.04".to_string(),
+        };
+        assert_eq!(target.to_string(), "linux/arm64 (ubuntu 24.04)");
+        target.arch_variant = Some("v8".to_string());
+        assert_eq!(target.to_string(), "linux/arm64/v8 (ubuntu 24.04)");
+    }
+}
@@ -35,6 +35,14 @@ pub(crate) fn set_default_env_var(
     Ok(())
 }
 
+/// An explicit heap limit sized from the build container's memory limit. How V8 sizes its default
+/// heap varies between Node.js versions and doesn't always account for the cgroup limit, so builds
+/// could grow past it and be killed. Leaving a quarter of the available memory unallocated gives
+/// headroom for the package manager process and native allocations.
+pub(crate) fn max_old_space_size_node_options(available_memory_mb: usize) -> String {
+    format!("--max-old-space-size={}", available_memory_mb / 4 * 3)
+}
+
 pub(crate) fn node_gyp_env() -> Vec<(String, String)> {
     vec![
         // If this is set to a non-empty string, Python won’t try to write .pyc files on the import of source modules.
@@ -45,3 +53,22 @@ pub(crate) fn node_gyp_env() -> Vec<(String, String)> {
         ("PYTHONDONTWRITEBYTECODE".to_string(), "1".to_string()),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_old_space_size_node_options() {
+        // heroku standard-1x
+        assert_eq!(
+            max_old_space_size_node_options(512),
+            "--max-old-space-size=384"
+        );
+        // heroku performance-l
+        assert_eq!(
+            max_old_space_size_node_options(14336),
+            "--max-old-space-size=10752"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// The total size of the regular files under `path`. Symlinks are not followed and a missing
+/// directory has a size of zero.
+pub(crate) fn directory_size(path: &Path) -> io::Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in WalkDir::new(path) {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Copies a directory tree, recreating symlinks rather than following them since package managers
+/// link packages to each other within the tree. A missing source is copied as nothing.
+pub(crate) fn copy_dir(source: &Path, destination: &Path) -> io::Result<()> {
+    if !source.try_exists()? {
+        return Ok(());
+    }
+    for entry in WalkDir::new(source) {
+        let entry = entry?;
+        let target = destination.join(
+            entry
+                .path()
+                .strip_prefix(source)
+                .expect("Walked path should be within the source directory"),
+        );
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_size_counts_nested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("one.txt"), "12345").unwrap();
+        fs::write(dir.path().join("a/b/two.txt"), "123").unwrap();
+        assert_eq!(directory_size(dir.path()).unwrap(), 8);
+        assert_eq!(directory_size(&dir.path().join("missing")).unwrap(), 0);
+    }
+
+    #[test]
+    fn format_bytes_as_mebibytes() {
+        assert_eq!(format_bytes(0), "0.0 MiB");
+        assert_eq!(format_bytes(55_050_240), "52.5 MiB");
+    }
+}
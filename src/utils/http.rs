@@ -1,18 +1,24 @@
 use bullet_stream::global::print;
 use bullet_stream::{GlobalTimer, style};
-use digest_io::IoWrapper;
 use flate2::read::MultiGzDecoder;
-use http::{HeaderMap, StatusCode};
-use reqwest::blocking::Response;
-use retry::delay::Fixed;
-use retry::{OperationResult, retry_with_index};
-use sha2::{Digest, Sha256};
+use http::header::{
+    ACCEPT_RANGES, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE, RETRY_AFTER,
+};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use lzma_rust2::XzReader;
+use reqwest::blocking::{Client, Response};
+use reqwest::{Certificate, Proxy};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
 use std::fmt;
-use std::fs::File;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use tempfile::NamedTempFile;
+use std::sync::{LazyLock, Mutex, OnceLock, PoisonError, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tempfile::{NamedTempFile, TempDir};
 use tracing::instrument;
 
 pub(crate) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
@@ -21,70 +27,426 @@ pub(crate) const DEFAULT_MAX_RETRIES: usize = 5;
 
 pub(crate) const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500); // half a second
 
+/// Retry delays double after each attempt up to this limit.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The longest `Retry-After` delay that will be honored so a server can't stall the build.
+const MAX_RETRY_AFTER: Duration = Duration::from_mins(1);
+
 pub(crate) fn get(request: &GetRequest) -> Result<GetResponse, GetError> {
-    let client = reqwest::blocking::ClientBuilder::new()
-        .connect_timeout(request.connect_timeout)
-        .use_rustls_tls()
-        .build()
-        .expect("Should be able to create the HTTP client");
+    let mut attempts = Attempts::new(request);
+    loop {
+        let response = attempts.next_response()?;
+        let status = response.status();
+        match response.buffer() {
+            Ok(response) => {
+                attempts.finish(status);
+                return Ok(response);
+            }
+            Err(e) => attempts.fail(e)?,
+        }
+    }
+}
+
+/// Tracks the attempts made for a request and prints a timer for each one. The timer keeps running
+/// until the attempt is finished or fails, so reading the response body counts towards the attempt
+/// and a body that fails partway through can be retried.
+struct Attempts<'a> {
+    request: &'a GetRequest,
+    client: Client,
+    prefetched_response: Option<PrefetchedResponse>,
+    attempt: usize,
+    timer: Option<GlobalTimer>,
+    retry_after: Option<Duration>,
+    // a response received while resuming a body that has to be read again from the start
+    restarted_response: Option<PendingResponse>,
+}
 
-    let retry_strategy = Fixed::from(request.retry_delay).take(request.max_retries);
+impl<'a> Attempts<'a> {
+    fn new(request: &'a GetRequest) -> Self {
+        Self {
+            request,
+            client: http_client(request.connect_timeout),
+            prefetched_response: take_prefetched_response(request),
+            attempt: 0,
+            timer: None,
+            retry_after: None,
+            restarted_response: None,
+        }
+    }
 
-    retry_with_index(retry_strategy, |index| {
-        let attempt = index - 1;
+    fn next_response(&mut self) -> Result<PendingResponse, GetError> {
+        match self.restarted_response.take() {
+            Some(response) => Ok(response),
+            None => self.send(&self.request.headers.clone()),
+        }
+    }
 
-        // fail early if the temporary download file can't be created
-        let mut response_file = match tempfile::NamedTempFile::new() {
-            Ok(named_temp_file) => named_temp_file,
-            Err(e) => return OperationResult::Err(GetError::Write(e)),
-        };
+    /// Sends the request with the given headers, retrying until a response is received.
+    fn send(&mut self, headers: &HeaderMap) -> Result<PendingResponse, GetError> {
+        loop {
+            self.retry_after = None;
+            self.timer = Some(print::sub_start_timer(if self.attempt == 0 {
+                format!("GET {}", style::url(&self.request.url))
+            } else {
+                format!(
+                    "Retry attempt {} of {}",
+                    self.attempt, self.request.max_retries
+                )
+            }));
+
+            // a prefetched response takes the place of the first attempt
+            let response = match self
+                .prefetched_response
+                .take()
+                .and_then(|prefetched_response| prefetched_response.recv().ok())
+            {
+                Some(response) => response.map(PendingResponse::Prefetched),
+                None => send(&self.client, &self.request.url, headers)
+                    .and_then(|response| {
+                        self.retry_after = retry_after(response.headers());
+                        response.error_for_status()
+                    })
+                    .map(PendingResponse::Live)
+                    .map_err(GetError::Request),
+            };
+
+            match response {
+                Ok(response) => return Ok(response),
+                Err(e) => self.fail(e)?,
+            }
+        }
+    }
 
-        let timer = print::sub_start_timer(if attempt == 0 {
-            format!("GET {}", style::url(&request.url))
+    /// Marks the current attempt as done once its response has been read.
+    fn finish(&mut self, status: StatusCode) {
+        let Some(timer) = self.timer.take() else {
+            return;
+        };
+        if status.is_success() {
+            timer.done();
+        } else if let Some(reason) = status.canonical_reason() {
+            timer.cancel(reason);
         } else {
-            format!("Retry attempt {attempt} of {}", request.max_retries)
-        });
+            timer.cancel(status.as_str());
+        }
+    }
 
-        // helper function to provide a short reason to the timer before returning the error
-        let report_error = |timer: GlobalTimer, error: GetError| {
+    /// Reports the error against the current attempt and waits before the next one, or returns the
+    /// error if it can't be retried.
+    fn fail(&mut self, error: GetError) -> Result<(), GetError> {
+        if let Some(timer) = self.timer.take() {
             timer.cancel(error.cancellation_reason());
-            if error.retry() {
-                OperationResult::Retry(error)
-            } else {
-                OperationResult::Err(error)
-            }
+        }
+        if !error.retry() || self.attempt >= self.request.max_retries {
+            return Err(error);
+        }
+        let delay = match self.retry_after.take() {
+            Some(retry_after) => retry_after.min(MAX_RETRY_AFTER),
+            None => self.backoff(),
         };
+        thread::sleep(delay);
+        self.attempt += 1;
+        Ok(())
+    }
 
-        let mut response = match client
-            .get(&request.url)
-            .headers(request.headers.clone())
-            .send()
-            .and_then(Response::error_for_status)
-        {
-            Ok(response) => response,
-            Err(e) => {
-                return report_error(timer, GetError::Request(e));
-            }
+    /// Handles an error from reading a [`ResumableBody`], which may already have been reported.
+    fn fail_body(&mut self, error: io::Error) -> Result<(), GetError> {
+        match error.downcast::<BodyError>() {
+            Ok(BodyError::Reported(error)) => Err(error),
+            Ok(BodyError::Restarted) => Ok(()),
+            Err(error) => self.fail(GetError::Write(error)),
+        }
+    }
+
+    /// Doubles the retry delay after each attempt, with jitter so clients that failed at the same
+    /// time don't all retry at the same time.
+    fn backoff(&self) -> Duration {
+        let delay = self
+            .request
+            .retry_delay
+            .saturating_mul(2_u32.saturating_pow(u32::try_from(self.attempt).unwrap_or(u32::MAX)))
+            .min(MAX_RETRY_DELAY);
+        delay / 2 + delay.mul_f64(fastrand::f64() / 2.0)
+    }
+}
+
+/// Sends the request once without reading the response body.
+fn send(client: &Client, url: &str, headers: &HeaderMap) -> reqwest::Result<Response> {
+    client.get(url).headers(headers.clone()).send()
+}
+
+/// Reads the `Retry-After` header, which is either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let retry_after = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match retry_after.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(retry_after)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
+
+/// Buffers the response body into a temporary file.
+fn buffer_response(mut response: Response) -> Result<GetResponse, GetError> {
+    let mut response_file = NamedTempFile::new().map_err(GetError::Write)?;
+    io::copy(&mut response, response_file.as_file_mut()).map_err(GetError::Write)?;
+    Ok(GetResponse {
+        response,
+        response_file,
+    })
+}
+
+/// A response that is either still being received or was already buffered by [`prefetch`].
+enum PendingResponse {
+    Live(Response),
+    Prefetched(GetResponse),
+}
+
+impl PendingResponse {
+    fn status(&self) -> StatusCode {
+        match self {
+            PendingResponse::Live(response) => response.status(),
+            PendingResponse::Prefetched(response) => response.status(),
+        }
+    }
+
+    fn buffer(self) -> Result<GetResponse, GetError> {
+        match self {
+            PendingResponse::Live(response) => buffer_response(response),
+            PendingResponse::Prefetched(response) => Ok(response),
+        }
+    }
+
+    /// The `If-Range` value to resume the body with, if the server supports range requests.
+    fn resume_validator(&self) -> Option<HeaderValue> {
+        let PendingResponse::Live(response) = self else {
+            return None;
         };
+        let headers = response.headers();
+        if headers.get(ACCEPT_RANGES)? != "bytes" {
+            return None;
+        }
+        // weak validators can't be used to combine ranges
+        headers
+            .get(ETAG)
+            .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+            .or_else(|| headers.get(LAST_MODIFIED))
+            .cloned()
+    }
 
-        if let Err(e) = io::copy(&mut response, response_file.as_file_mut()) {
-            return report_error(timer, GetError::Write(e));
+    fn into_body(self) -> Result<Box<dyn Read>, GetError> {
+        match self {
+            PendingResponse::Live(response) => Ok(Box::new(response)),
+            PendingResponse::Prefetched(response) => response
+                .body_as_file()
+                .map(|file| Box::new(file) as Box<dyn Read>)
+                .map_err(GetError::Write),
         }
+    }
+}
 
-        if response.status().is_success() {
-            timer.done();
-        } else if let Some(reason) = response.status().canonical_reason() {
-            timer.cancel(reason);
+/// Reads a response body and, when the connection fails partway through, requests the rest of it
+/// with a `Range` request so the bytes already read aren't downloaded again. The body is read
+/// again from the start if the server doesn't support ranges or the content has changed.
+struct ResumableBody<'a, 'b> {
+    attempts: &'a mut Attempts<'b>,
+    body: Box<dyn Read>,
+    validator: Option<HeaderValue>,
+    position: u64,
+}
+
+impl<'a, 'b> ResumableBody<'a, 'b> {
+    fn new(attempts: &'a mut Attempts<'b>, response: PendingResponse) -> Result<Self, GetError> {
+        Ok(Self {
+            attempts,
+            validator: response.resume_validator(),
+            body: response.into_body()?,
+            position: 0,
+        })
+    }
+
+    fn resume(&mut self, error: io::Error, validator: HeaderValue) -> Result<(), BodyError> {
+        self.attempts
+            .fail(GetError::Write(error))
+            .map_err(BodyError::Reported)?;
+
+        let mut headers = self.attempts.request.headers.clone();
+        headers.insert(
+            RANGE,
+            HeaderValue::from_str(&format!("bytes={}-", self.position))
+                .expect("Range header should be a valid header value"),
+        );
+        headers.insert(IF_RANGE, validator);
+        let response = self.attempts.send(&headers).map_err(BodyError::Reported)?;
+
+        let PendingResponse::Live(response) = response else {
+            unreachable!("Prefetched responses are only used for the first attempt");
+        };
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT
+            && response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|content_range| content_range.to_str().ok())
+                .is_some_and(|content_range| {
+                    content_range.starts_with(&format!("bytes {}-", self.position))
+                });
+        if resumed {
+            self.body = Box::new(response);
+            Ok(())
         } else {
-            timer.cancel(response.status().as_str());
+            self.attempts.restarted_response = Some(PendingResponse::Live(response));
+            Err(BodyError::Restarted)
+        }
+    }
+}
+
+impl Read for ResumableBody<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let error = match self.body.read(buf) {
+                Ok(read) => {
+                    self.position += read as u64;
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return Err(e),
+                Err(e) => e,
+            };
+            let Some(validator) = self.validator.clone() else {
+                return Err(error);
+            };
+            self.resume(error, validator).map_err(io::Error::other)?;
+        }
+    }
+}
+
+/// Errors from a [`ResumableBody`] that have already been reported against an attempt.
+#[derive(Debug)]
+enum BodyError {
+    Reported(GetError),
+    Restarted,
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::Reported(e) => write!(f, "{e}"),
+            BodyError::Restarted => write!(f, "The response body has to be read from the start"),
         }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// Proxy and certificate settings applied to every HTTP client.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientConfig {
+    pub(crate) proxies: Vec<Proxy>,
+    pub(crate) ca_certificates: Vec<Certificate>,
+}
+
+static CLIENT_CONFIG: OnceLock<ClientConfig> = OnceLock::new();
+
+/// Sets the proxy and certificate settings for every HTTP client. This has to be called before the
+/// first request is sent because clients are shared once they've been created.
+pub(crate) fn configure_clients(client_config: ClientConfig) {
+    CLIENT_CONFIG
+        .set(client_config)
+        .expect("HTTP clients should only be configured once");
+}
 
-        OperationResult::Ok(GetResponse {
-            response,
-            response_file,
+/// Clients are shared so connections to the same host are reused between requests. The connect
+/// timeout can only be configured on the client so one is kept for each timeout in use.
+fn http_client(connect_timeout: Duration) -> Client {
+    static HTTP_CLIENTS: LazyLock<Mutex<HashMap<Duration, Client>>> = LazyLock::new(Mutex::default);
+
+    HTTP_CLIENTS
+        .lock()
+        // the map is only ever inserted into so a panic while holding the lock can't corrupt it
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(connect_timeout)
+        .or_insert_with(|| {
+            build_client(
+                connect_timeout,
+                CLIENT_CONFIG.get_or_init(ClientConfig::default),
+            )
+            .expect("Should be able to create the HTTP client")
         })
-    })
-    .map_err(|operation| operation.error)
+        .clone()
+}
+
+pub(crate) fn build_client(
+    connect_timeout: Duration,
+    client_config: &ClientConfig,
+) -> reqwest::Result<Client> {
+    client_config
+        .proxies
+        .iter()
+        .cloned()
+        .fold(
+            reqwest::blocking::ClientBuilder::new()
+                .connect_timeout(connect_timeout)
+                .use_rustls_tls()
+                // proxies are read from the build environment instead of the process environment
+                .no_proxy()
+                .tls_certs_merge(client_config.ca_certificates.clone()),
+            reqwest::blocking::ClientBuilder::proxy,
+        )
+        .build()
+}
+
+type PrefetchedResponse = mpsc::Receiver<Result<GetResponse, GetError>>;
+
+static PREFETCHED_RESPONSES: LazyLock<Mutex<Vec<(GetRequest, PrefetchedResponse)>>> =
+    LazyLock::new(Mutex::default);
+
+/// Sends the request on a background thread so the response is ready by the time [`get`] or
+/// [`download`] is called with the same url and headers. Nothing is printed until the response is
+/// picked up, where it is reported as the first attempt, so the output is the same as if the
+/// request had been sent from there.
+///
+/// The `on_response` callback runs on the background thread before the response is handed off and
+/// can be used to chain further prefetches that depend on the response.
+pub(crate) fn prefetch(
+    request: GetRequest,
+    on_response: impl FnOnce(&GetResponse) + Send + 'static,
+) {
+    let (sender, receiver) = mpsc::channel();
+
+    PREFETCHED_RESPONSES
+        .lock()
+        .expect("Prefetched responses lock should not be poisoned")
+        .push((request.clone(), receiver));
+
+    thread::spawn(move || {
+        // a `Retry-After` delay isn't kept for failed prefetches so the retry falls back to backoff
+        let response = send(
+            &http_client(request.connect_timeout),
+            &request.url,
+            &request.headers,
+        )
+        .and_then(Response::error_for_status)
+        .map_err(GetError::Request)
+        .and_then(buffer_response);
+        if let Ok(response) = &response {
+            on_response(response);
+        }
+        // the receiver is gone if the build finished without requesting this response
+        sender.send(response).ok();
+    });
+}
+
+fn take_prefetched_response(request: &GetRequest) -> Option<PrefetchedResponse> {
+    let mut prefetched_responses = PREFETCHED_RESPONSES
+        .lock()
+        .expect("Prefetched responses lock should not be poisoned");
+    let index = prefetched_responses
+        .iter()
+        .position(|(prefetched_request, _)| {
+            prefetched_request.url == request.url && prefetched_request.headers == request.headers
+        })?;
+    Some(prefetched_responses.swap_remove(index).1)
 }
 
 #[derive(Debug)]
@@ -118,12 +480,27 @@ impl GetError {
 
     fn retry(&self) -> bool {
         match self {
-            GetError::Request(error) => !error.is_builder(),
+            GetError::Request(error) => {
+                !error.is_builder() && error.status().is_none_or(is_retryable_status)
+            }
             GetError::Write(_) => true,
         }
     }
 }
 
+/// Statuses that may succeed if the request is sent again. Other client errors, like a 404, won't.
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
 impl fmt::Display for GetError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -166,21 +543,29 @@ impl GetResponse {
     }
 }
 
+/// Downloads the source into the destination in a single pass over the response body. The body is
+/// hashed as it is saved or extracted into a staging area next to the destination, which is only
+/// moved into place once the checksum has been validated.
 #[instrument(skip_all)]
-pub(crate) fn download(download_task: &DownloadTask) -> Result<(), DownloadError> {
-    let response = get(&GetRequest::builder(&download_task.source_url)
-        .connect_timeout(download_task.connect_timeout)
-        .retry_delay(download_task.retry_delay)
-        .max_retries(download_task.max_retries)
-        .build())
-    .map_err(|source| DownloadError::Download {
-        url: download_task.source_url.clone(),
-        source,
-    })?;
+pub(crate) fn download(download_task: &DownloadTask) -> Result<DownloadSummary, DownloadError> {
+    let staged_download = if let Some(path) = download_task.source_url.strip_prefix("file://") {
+        print::sub_bullet(format!("Reading {}", style::url(&download_task.source_url)));
+        File::open(path)
+            .and_then(|file| stage_download(file, download_task))
+            .map_err(|source| DownloadError::ReadFile {
+                url: download_task.source_url.clone(),
+                source,
+            })?
+    } else {
+        fetch_download(download_task).map_err(|source| DownloadError::Download {
+            url: download_task.source_url.clone(),
+            source,
+        })?
+    };
 
-    if let Some(ChecksumValidator::Sha256(checksum)) = &download_task.checksum_validator {
+    if let Some(checksum_validator) = &download_task.checksum_validator {
         let timer = print::sub_start_timer("Validating");
-        match validate_sha256_checksum(&response, checksum, download_task) {
+        match validate_checksum(&staged_download, checksum_validator, download_task) {
             Ok(()) => timer.done(),
             Err(e) => {
                 timer.cancel("error");
@@ -189,49 +574,217 @@ pub(crate) fn download(download_task: &DownloadTask) -> Result<(), DownloadError
         }
     }
 
-    match &download_task.extractor {
-        Some(Extractor::Gzip(gzip_options)) => {
-            let timer = print::sub_start_timer("Extracting");
-            match gzip_extract_to_destination(
-                &response,
-                &download_task.destination,
-                gzip_options,
-                download_task,
-            ) {
-                Ok(()) => timer.done(),
-                Err(e) => {
-                    timer.cancel("error");
-                    return Err(e);
+    let summary = DownloadSummary {
+        excluded_bytes: staged_download.excluded_bytes,
+    };
+
+    let timer = print::sub_start_timer(match download_task.extractor {
+        Some(Extractor::Gzip(_) | Extractor::Xz(_)) => "Extracting",
+        None => "Saving",
+    });
+    match staged_download
+        .staging
+        .and_then(|staging| staging.promote(&download_task.destination))
+    {
+        Ok(()) => timer.done(),
+        Err(e) => {
+            timer.cancel("error");
+            return Err(create_write_error(download_task, e));
+        }
+    }
+
+    Ok(summary)
+}
+
+#[derive(Debug)]
+pub(crate) struct DownloadSummary {
+    /// The uncompressed size of archive entries skipped by [`TarOptions::exclude`].
+    pub(crate) excluded_bytes: u64,
+}
+
+fn fetch_download(download_task: &DownloadTask) -> Result<StagedDownload, GetError> {
+    let request = GetRequest::builder(&download_task.source_url)
+        .connect_timeout(download_task.connect_timeout)
+        .retry_delay(download_task.retry_delay)
+        .max_retries(download_task.max_retries)
+        .build();
+    let mut attempts = Attempts::new(&request);
+    loop {
+        let response = attempts.next_response()?;
+        let body = ResumableBody::new(&mut attempts, response)?;
+        // a body that can't be resumed is staged again from the start into a fresh location
+        match stage_download(body, download_task) {
+            Ok(staged_download) => {
+                attempts.finish(StatusCode::OK);
+                return Ok(staged_download);
+            }
+            Err(e) => attempts.fail_body(e)?,
+        }
+    }
+}
+
+struct StagedDownload {
+    digest: Option<Box<[u8]>>,
+    // saving or extracting can fail without the body failing to read, in which case the error is
+    // held until the checksum has been checked so a corrupt download is reported as a mismatch
+    staging: io::Result<Staging>,
+    excluded_bytes: u64,
+}
+
+/// Reads the body once, hashing it while it is saved or extracted into a new staging area. Only
+/// errors reading the body are returned directly.
+fn stage_download(body: impl Read, download_task: &DownloadTask) -> io::Result<StagedDownload> {
+    let mut body = HashingReader {
+        inner: body,
+        hasher: download_task
+            .checksum_validator
+            .as_ref()
+            .map(ChecksumValidator::hasher),
+        read_error: None,
+    };
+
+    let mut excluded_bytes = 0;
+    let staging = match &download_task.extractor {
+        Some(Extractor::Gzip(tar_options)) => {
+            staging_dir(&download_task.destination).and_then(|staging_dir| {
+                tar_extract(
+                    MultiGzDecoder::new(&mut body),
+                    staging_dir.path(),
+                    tar_options,
+                )
+                .map(|excluded| {
+                    excluded_bytes = excluded;
+                    Staging::Directory(staging_dir)
+                })
+            })
+        }
+        Some(Extractor::Xz(tar_options)) => {
+            staging_dir(&download_task.destination).and_then(|staging_dir| {
+                tar_extract(
+                    XzReader::new(&mut body, true),
+                    staging_dir.path(),
+                    tar_options,
+                )
+                .map(|excluded| {
+                    excluded_bytes = excluded;
+                    Staging::Directory(staging_dir)
+                })
+            })
+        }
+        None => staging_file(&download_task.destination).and_then(|mut staging_file| {
+            io::copy(&mut body, staging_file.as_file_mut()).map(|_| Staging::File(staging_file))
+        }),
+    };
+
+    if let Some(read_error) = body.read_error.take() {
+        return Err(read_error);
+    }
+
+    // anything left after the end of the archive, or after a failed write, still needs hashing
+    if let Err(e) = io::copy(&mut body, &mut io::sink()) {
+        return Err(body.read_error.take().unwrap_or(e));
+    }
+
+    Ok(StagedDownload {
+        digest: body.hasher.map(sha2::digest::DynDigest::finalize),
+        staging,
+        excluded_bytes,
+    })
+}
+
+/// Updates the hash with everything read from the inner reader and keeps the first read error so
+/// it can be told apart from errors writing what was read.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Box<dyn sha2::digest::DynDigest>>,
+    read_error: Option<io::Error>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(read) => {
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.update(&buf[..read]);
                 }
+                Ok(read)
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Err(e),
+            Err(e) => {
+                let error = io::Error::new(e.kind(), e.to_string());
+                self.read_error.get_or_insert(e);
+                Err(error)
             }
         }
-        None => {
-            let timer = print::sub_start_timer("Saving");
-            match save_to_destination(&response, &download_task.destination, download_task) {
-                Ok(()) => timer.done(),
-                Err(e) => {
-                    timer.cancel("error");
-                    return Err(e);
+    }
+}
+
+enum Staging {
+    File(NamedTempFile),
+    Directory(TempDir),
+}
+
+impl Staging {
+    /// Moves the staged contents to the destination. Extracted entries are moved into the
+    /// destination directory, replacing any entries with the same name.
+    fn promote(self, destination: &Path) -> io::Result<()> {
+        match self {
+            Staging::File(staging_file) => staging_file
+                .persist(destination)
+                .map(|_| ())
+                .map_err(|e| e.error),
+            Staging::Directory(staging_dir) => {
+                fs::create_dir_all(destination)?;
+                for entry in fs::read_dir(staging_dir.path())? {
+                    let entry = entry?;
+                    let target = destination.join(entry.file_name());
+                    match fs::symlink_metadata(&target) {
+                        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&target)?,
+                        Ok(_) => fs::remove_file(&target)?,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                    fs::rename(entry.path(), target)?;
                 }
+                Ok(())
             }
         }
     }
+}
+
+// staging next to the destination keeps it on the same filesystem so promoting it is a rename
+fn staging_parent(destination: &Path) -> io::Result<&Path> {
+    let parent = destination
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    Ok(parent)
+}
+
+fn staging_file(destination: &Path) -> io::Result<NamedTempFile> {
+    tempfile::Builder::new()
+        .prefix(STAGING_PREFIX)
+        .permissions(fs::Permissions::from_mode(0o644))
+        .tempfile_in(staging_parent(destination)?)
+}
 
-    Ok(())
+fn staging_dir(destination: &Path) -> io::Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix(STAGING_PREFIX)
+        .tempdir_in(staging_parent(destination)?)
 }
 
-fn validate_sha256_checksum(
-    response: &GetResponse,
-    checksum: &[u8],
+const STAGING_PREFIX: &str = ".download-";
+
+fn validate_checksum(
+    staged_download: &StagedDownload,
+    checksum_validator: &ChecksumValidator,
     download_task: &DownloadTask,
 ) -> Result<(), DownloadError> {
-    let mut response_body = response
-        .body_as_file()
-        .map_err(|e| create_write_error(download_task, e))?;
-    let mut hasher = IoWrapper(Sha256::new());
-    io::copy(&mut response_body, &mut hasher).map_err(|e| create_write_error(download_task, e))?;
-    let digest = hasher.0.finalize();
-    if checksum == digest.as_slice() {
+    let checksum = checksum_validator.checksum();
+    let digest = staged_download.digest.as_deref().unwrap_or_default();
+    if checksum == digest {
         Ok(())
     } else {
         Err(DownloadError::ChecksumMismatch {
@@ -242,48 +795,30 @@ fn validate_sha256_checksum(
     }
 }
 
-fn save_to_destination(
-    response: &GetResponse,
-    destination: &Path,
-    download_task: &DownloadTask,
-) -> Result<(), DownloadError> {
-    let mut response_body = response
-        .body_as_file()
-        .map_err(|e| create_write_error(download_task, e))?;
-    let mut destination_file =
-        File::create(destination).map_err(|e| create_write_error(download_task, e))?;
-    std::io::copy(&mut response_body, &mut destination_file)
-        .map_err(|e| create_write_error(download_task, e))?;
-    Ok(())
-}
-
-fn gzip_extract_to_destination(
-    response: &GetResponse,
+/// Returns the size of the entries skipped by the exclude filter.
+///
+/// Archives are extracted before their checksum can be validated, so entries that would be written
+/// outside the destination directory, either directly or through a symlink or hard link from an
+/// earlier entry, are rejected instead of trusting the archive.
+fn tar_extract(
+    tar_file: impl Read,
     destination_dir: &Path,
-    gzip_options: &GzipOptions,
-    download_task: &DownloadTask,
-) -> Result<(), DownloadError> {
-    let gzip_file = response
-        .body_as_file()
-        .map_err(|e| create_write_error(download_task, e))?;
-
-    let mut archive = tar::Archive::new(MultiGzDecoder::new(gzip_file));
-
-    let entries = archive
-        .entries()
-        .map_err(|e| create_write_error(download_task, e))?;
+    tar_options: &TarOptions,
+) -> io::Result<u64> {
+    let mut archive = tar::Archive::new(tar_file);
+    let mut excluded_bytes = 0;
+    let destination_dir = destination_dir.canonicalize()?;
+    let destination_dir = destination_dir.as_path();
 
-    for entry in entries {
-        let mut entry = entry.map_err(|e| create_write_error(download_task, e))?;
-        let path = entry
-            .path()
-            .map_err(|e| create_write_error(download_task, e))?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?;
 
         // Get the path components
         let path_components: Vec<_> = path.components().collect();
 
         // Skip if we don't have enough components after stripping
-        if path_components.len() <= gzip_options.strip_components {
+        if path_components.len() <= tar_options.strip_components {
             continue;
         }
 
@@ -300,7 +835,7 @@ fn gzip_extract_to_destination(
         // Build the stripped path
         let stripped_path: PathBuf = path_components
             .into_iter()
-            .skip(gzip_options.strip_components)
+            .skip(tar_options.strip_components)
             .collect();
 
         // Skip empty paths
@@ -309,27 +844,105 @@ fn gzip_extract_to_destination(
         }
 
         // Skip excluded paths
-        let exclude = &gzip_options.exclude;
+        let exclude = &tar_options.exclude;
         if exclude(&stripped_path) {
+            excluded_bytes += entry.size();
             continue;
         }
 
         let entry_type = entry.header().entry_type();
         let dest_path = destination_dir.join(&stripped_path);
+        let parent = dest_path.parent().unwrap_or(destination_dir);
 
         // Create parent directories for regular files and symlinks
-        if (entry_type.is_file() || entry_type.is_symlink() || entry_type.is_hard_link())
-            && let Some(parent) = dest_path.parent()
-        {
-            std::fs::create_dir_all(parent).map_err(|e| create_write_error(download_task, e))?;
+        ensure_inside_destination(destination_dir, parent)?;
+        if entry_type.is_file() || entry_type.is_symlink() || entry_type.is_hard_link() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // replace an earlier symlink entry instead of writing through it
+        if fs::symlink_metadata(&dest_path).is_ok_and(|metadata| metadata.is_symlink()) {
+            fs::remove_file(&dest_path)?;
+        }
+
+        if entry_type.is_symlink() {
+            let link_target = entry.link_name()?.unwrap_or_default();
+            if link_target.is_absolute()
+                || !normalize_path(&parent.join(&link_target)).starts_with(destination_dir)
+            {
+                return Err(create_outside_destination_error(&stripped_path));
+            }
+        } else if entry_type.is_hard_link() {
+            // hard link targets are other entries in the archive so they are stripped the same way
+            let link_target = entry.link_name()?.unwrap_or_default();
+            let link_components = link_target.components().collect::<Vec<_>>();
+            if link_components.len() <= tar_options.strip_components
+                || link_components.iter().any(|c| {
+                    matches!(
+                        c,
+                        std::path::Component::ParentDir | std::path::Component::RootDir
+                    )
+                })
+            {
+                return Err(create_outside_destination_error(&stripped_path));
+            }
+            let source = destination_dir.join(
+                link_components
+                    .into_iter()
+                    .skip(tar_options.strip_components)
+                    .collect::<PathBuf>(),
+            );
+            ensure_inside_destination(destination_dir, &source)?;
+            fs::hard_link(source, dest_path)?;
+            continue;
         }
 
-        entry
-            .unpack(dest_path)
-            .map_err(|e| create_write_error(download_task, e))?;
+        entry.unpack(dest_path)?;
+    }
+
+    Ok(excluded_bytes)
+}
+
+/// Errors unless the path, with any symlinks in its existing ancestors resolved, is inside the
+/// destination directory.
+fn ensure_inside_destination(destination_dir: &Path, path: &Path) -> io::Result<()> {
+    let existing_ancestor = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(destination_dir);
+    if existing_ancestor
+        .canonicalize()?
+        .starts_with(destination_dir)
+    {
+        Ok(())
+    } else {
+        Err(create_outside_destination_error(path))
+    }
+}
+
+/// Resolves `.` and `..` components without touching the filesystem.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
     }
+    normalized
+}
 
-    Ok(())
+fn create_outside_destination_error(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Archive entry {} resolves outside of the extraction directory",
+            path.display()
+        ),
+    )
 }
 
 fn create_write_error(download_task: &DownloadTask, source: io::Error) -> DownloadError {
@@ -358,24 +971,25 @@ pub(crate) struct DownloadTask<'a> {
 
 #[derive(Debug)]
 pub(crate) enum Extractor {
-    Gzip(GzipOptions),
+    Gzip(TarOptions),
+    Xz(TarOptions),
 }
 
-pub(crate) struct GzipOptions {
+pub(crate) struct TarOptions {
     pub(crate) strip_components: usize,
     pub(crate) exclude: Box<dyn Fn(&Path) -> bool>,
 }
 
-impl core::fmt::Debug for GzipOptions {
+impl core::fmt::Debug for TarOptions {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("GzipOptions")
+        f.debug_struct("TarOptions")
             .field("strip_components", &self.strip_components)
             .field("exclude", &"<closure>")
             .finish()
     }
 }
 
-impl Default for GzipOptions {
+impl Default for TarOptions {
     fn default() -> Self {
         Self {
             strip_components: 0,
@@ -387,6 +1001,22 @@ impl Default for GzipOptions {
 #[derive(Debug)]
 pub(crate) enum ChecksumValidator<'a> {
     Sha256(&'a [u8]),
+    Sha512(&'a [u8]),
+}
+
+impl ChecksumValidator<'_> {
+    fn checksum(&self) -> &[u8] {
+        match self {
+            ChecksumValidator::Sha256(checksum) | ChecksumValidator::Sha512(checksum) => checksum,
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn sha2::digest::DynDigest> {
+        match self {
+            ChecksumValidator::Sha256(_) => Box::new(Sha256::new()),
+            ChecksumValidator::Sha512(_) => Box::new(Sha512::new()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -395,6 +1025,10 @@ pub(crate) enum DownloadError {
         url: String,
         source: GetError,
     },
+    ReadFile {
+        url: String,
+        source: io::Error,
+    },
     ChecksumMismatch {
         url: String,
         expected_checksum: String,
@@ -413,6 +1047,7 @@ mod test {
     use bullet_stream::{global, strip_ansi};
     use flate2::Compression;
     use flate2::write::GzEncoder;
+    use lzma_rust2::{XzOptions, XzWriter};
     use regex::Regex;
     use std::fs;
     use std::io::Write;
@@ -435,6 +1070,46 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_download_from_file_source() {
+        let src = tempfile::NamedTempFile::new().unwrap();
+        fs::write(src.path(), "test").unwrap();
+        let source_url = format!("file://{}", src.path().display());
+        let dst = tempfile::NamedTempFile::new().unwrap();
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            download(
+                &DownloadTask::builder(&source_url, dst.path())
+                    .checksum_validator(ChecksumValidator::Sha512(&Sha512::digest("test")))
+                    .build(),
+            )
+            .unwrap();
+        });
+
+        assert_log_contains_matches(
+            &log,
+            &[
+                reading_matcher(&source_url),
+                validating_matcher(),
+                saving_matcher(),
+            ],
+        );
+        assert_eq!(fs::read_to_string(dst.path()).unwrap(), "test");
+    }
+
+    #[test]
+    fn test_download_from_missing_file_source() {
+        let dst = tempfile::NamedTempFile::new().unwrap();
+        global::with_locked_writer(Vec::<u8>::new(), || {
+            match download(&DownloadTask::builder("file:///does/not/exist.tgz", dst.path()).build())
+                .unwrap_err()
+            {
+                DownloadError::ReadFile { .. } => {}
+                e => panic!("Not the expected error: {e:?}"),
+            }
+        });
+    }
+
     #[test]
     fn test_download_success() {
         let server = tokio::runtime::Builder::new_current_thread()
@@ -527,7 +1202,7 @@ mod test {
 
                 Mock::given(method("GET"))
                     .and(path("/"))
-                    .respond_with(ResponseTemplate::new(400))
+                    .respond_with(ResponseTemplate::new(503))
                     .up_to_n_times(1)
                     .expect(1)
                     .mount(&server)
@@ -535,7 +1210,7 @@ mod test {
 
                 Mock::given(method("GET"))
                     .and(path("/"))
-                    .respond_with(ResponseTemplate::new(404))
+                    .respond_with(ResponseTemplate::new(502))
                     .up_to_n_times(1)
                     .expect(1)
                     .mount(&server)
@@ -563,14 +1238,14 @@ mod test {
             &log,
             &[
                 request_failed_matcher(server.uri(), "Internal Server Error"),
-                retry_attempt_failed_matcher(1, 2, "Bad Request"),
-                retry_attempt_failed_matcher(2, 2, "Not Found"),
+                retry_attempt_failed_matcher(1, 2, "Service Unavailable"),
+                retry_attempt_failed_matcher(2, 2, "Bad Gateway"),
             ],
         );
     }
 
     #[test]
-    fn test_download_will_not_retry_after_checksum_mismatch() {
+    fn test_download_will_not_retry_client_errors() {
         let server = tokio::runtime::Builder::new_current_thread()
             .build()
             .unwrap()
@@ -578,9 +1253,8 @@ mod test {
                 let server = MockServer::start().await;
 
                 Mock::given(method("GET"))
-                    .and(path("/"))
-                    .respond_with(ResponseTemplate::new(200).set_body_string("test"))
-                    .up_to_n_times(1)
+                    .and(path("/not-found"))
+                    .respond_with(ResponseTemplate::new(404))
                     .expect(1)
                     .mount(&server)
                     .await;
@@ -588,32 +1262,242 @@ mod test {
                 server
             });
 
+        let url = format!("{}/not-found", server.uri());
         let dst = tempfile::NamedTempFile::new().unwrap();
 
-        let mut sha256 = Sha256::new();
-        sha256.update(b"checksum-mismatch");
-        let digest = sha256.finalize().to_vec();
-
         let log = global::with_locked_writer(Vec::<u8>::new(), || {
-            match download(
-                &DownloadTask::builder(server.uri(), dst.path())
-                    .checksum_validator(ChecksumValidator::Sha256(&digest))
-                    .build(),
-            )
-            .unwrap_err()
-            {
-                DownloadError::ChecksumMismatch { .. } => {}
+            match download(&DownloadTask::builder(&url, dst.path()).build()).unwrap_err() {
+                DownloadError::Download { .. } => {}
                 e => panic!("Not the expected error: {e:?}"),
             }
         });
 
-        assert_log_contains_matches(
-            &log,
-            &[
-                request_success_matcher(server.uri()),
-                validating_failed_matcher(),
-            ],
-        );
+        assert_log_contains_matches(&log, &[request_failed_matcher(&url, "Not Found")]);
+    }
+
+    #[test]
+    fn test_download_honors_retry_after() {
+        let server = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+
+                Mock::given(method("GET"))
+                    .and(path("/retry-after"))
+                    .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+                    .up_to_n_times(1)
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+
+                Mock::given(method("GET"))
+                    .and(path("/retry-after"))
+                    .respond_with(ResponseTemplate::new(200).set_body_string("test"))
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+
+                server
+            });
+
+        let url = format!("{}/retry-after", server.uri());
+        let dst = tempfile::NamedTempFile::new().unwrap();
+        let start = std::time::Instant::now();
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            // the retry delay would hold the build up if the `Retry-After` header were ignored
+            download(
+                &DownloadTask::builder(&url, dst.path())
+                    .retry_delay(Duration::from_mins(1))
+                    .build(),
+            )
+            .unwrap();
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_failed_matcher(&url, "Too Many Requests"),
+                retry_attempt_success_matcher(1, DEFAULT_MAX_RETRIES),
+                saving_matcher(),
+            ],
+        );
+        assert_eq!(fs::read_to_string(dst.path()).unwrap(), "test");
+    }
+
+    #[test]
+    fn test_download_resumes_interrupted_body_with_range_request() {
+        let (url, server) = start_scripted_server([
+            truncated_response("Accept-Ranges: bytes\r\nETag: \"v1\"\r\n", "test", 8),
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 4-7/8\r\nContent-Length: 4\r\n\r\n1234".into(),
+        ]);
+        let dst = tempfile::NamedTempFile::new().unwrap();
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            download(
+                &DownloadTask::builder(&url, dst.path())
+                    .checksum_validator(ChecksumValidator::Sha256(&Sha256::digest("test1234")))
+                    .retry_delay(Duration::ZERO)
+                    .build(),
+            )
+            .unwrap();
+        });
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains("range: bytes=4-\r\n"));
+        assert!(requests[1].contains("if-range: \"v1\"\r\n"));
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_failed_matcher(&url, "write response error"),
+                retry_attempt_success_matcher(1, DEFAULT_MAX_RETRIES),
+                validating_matcher(),
+                saving_matcher(),
+            ],
+        );
+        assert_eq!(fs::read_to_string(dst.path()).unwrap(), "test1234");
+    }
+
+    #[test]
+    fn test_download_restarts_interrupted_body_when_content_changed() {
+        let (url, server) = start_scripted_server([
+            truncated_response("Accept-Ranges: bytes\r\nETag: \"v1\"\r\n", "test", 8),
+            "HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nchanged!".into(),
+        ]);
+        let dst = tempfile::NamedTempFile::new().unwrap();
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            download(
+                &DownloadTask::builder(&url, dst.path())
+                    .retry_delay(Duration::ZERO)
+                    .build(),
+            )
+            .unwrap();
+        });
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains("range: bytes=4-\r\n"));
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_failed_matcher(&url, "write response error"),
+                retry_attempt_success_matcher(1, DEFAULT_MAX_RETRIES),
+                saving_matcher(),
+            ],
+        );
+        assert_eq!(fs::read_to_string(dst.path()).unwrap(), "changed!");
+    }
+
+    #[test]
+    fn test_download_restarts_interrupted_body_without_range_support() {
+        let (url, server) = start_scripted_server([
+            truncated_response("", "test", 8),
+            "HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\ntest1234".into(),
+        ]);
+        let dst = tempfile::NamedTempFile::new().unwrap();
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            download(
+                &DownloadTask::builder(&url, dst.path())
+                    .retry_delay(Duration::ZERO)
+                    .build(),
+            )
+            .unwrap();
+        });
+
+        let requests = server.join().unwrap();
+        assert!(!requests[1].contains("range:"));
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_failed_matcher(&url, "write response error"),
+                retry_attempt_success_matcher(1, DEFAULT_MAX_RETRIES),
+                saving_matcher(),
+            ],
+        );
+        assert_eq!(fs::read_to_string(dst.path()).unwrap(), "test1234");
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_with_jitter() {
+        let request = GetRequest::builder("https://example.com/backoff")
+            .retry_delay(Duration::from_secs(1))
+            .build();
+        let mut attempts = Attempts::new(&request);
+
+        for (attempt, min, max) in [(0, 500, 1_000), (2, 2_000, 4_000), (8, 5_000, 10_000)] {
+            attempts.attempt = attempt;
+            let delay = attempts.backoff();
+            assert!(
+                (Duration::from_millis(min)..=Duration::from_millis(max)).contains(&delay),
+                "Unexpected delay for attempt {attempt}: {delay:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_mins(2)));
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_mins(5));
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+        assert!(retry_after(&headers).is_some_and(|delay| delay > Duration::from_mins(4)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_download_will_not_retry_after_checksum_mismatch() {
+        let server = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+
+                Mock::given(method("GET"))
+                    .and(path("/"))
+                    .respond_with(ResponseTemplate::new(200).set_body_string("test"))
+                    .up_to_n_times(1)
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+
+                server
+            });
+
+        let dst = tempfile::NamedTempFile::new().unwrap();
+
+        let mut sha256 = Sha256::new();
+        sha256.update(b"checksum-mismatch");
+        let digest = sha256.finalize().to_vec();
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            match download(
+                &DownloadTask::builder(server.uri(), dst.path())
+                    .checksum_validator(ChecksumValidator::Sha256(&digest))
+                    .build(),
+            )
+            .unwrap_err()
+            {
+                DownloadError::ChecksumMismatch { .. } => {}
+                e => panic!("Not the expected error: {e:?}"),
+            }
+        });
+
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_success_matcher(server.uri()),
+                validating_failed_matcher(),
+            ],
+        );
     }
 
     #[test]
@@ -731,7 +1615,7 @@ mod test {
         let log = global::with_locked_writer(Vec::<u8>::new(), || {
             download(
                 &DownloadTask::builder(server.uri(), dst.path())
-                    .extractor(Extractor::Gzip(GzipOptions {
+                    .extractor(Extractor::Gzip(TarOptions {
                         strip_components: 1,
                         exclude: Box::new(|path| {
                             path.components().any(|c| c.as_os_str() == "child-b")
@@ -762,6 +1646,102 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_download_success_with_tar_xz_extraction_strip_and_filter() {
+        let mut tar_contents = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(7);
+        header.set_mode(0o644);
+        // written directly since `append_data` refuses paths that escape the archive root
+        header.as_gnu_mut().unwrap().name[..21].copy_from_slice(b"parent/../escaped.txt");
+        header.set_cksum();
+        tar_contents.append(&header, "escaped".as_bytes()).unwrap();
+        let traversal_entry = tar_contents.into_inner().unwrap();
+
+        let tarball = create_xz_archive([
+            ("parent/child-a/name.txt", "child-a"),
+            ("parent/child-b/name.txt", "child-b"),
+        ]);
+        let traversal_tarball = {
+            let mut writer = XzWriter::new(Vec::new(), XzOptions::with_preset(1)).unwrap();
+            writer.write_all(&traversal_entry).unwrap();
+            writer.write_all(&[0; 1024]).unwrap();
+            writer.finish().unwrap()
+        };
+
+        let server = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+
+                Mock::given(method("GET"))
+                    .and(path("/"))
+                    .respond_with(
+                        ResponseTemplate::new(200).set_body_raw(tarball, "application/x-xz"),
+                    )
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+
+                Mock::given(method("GET"))
+                    .and(path("/traversal"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_raw(traversal_tarball, "application/x-xz"),
+                    )
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+
+                server
+            });
+
+        let root = tempfile::tempdir().unwrap();
+        let dst = root.path().join("nested/dst");
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            let summary = download(
+                &DownloadTask::builder(server.uri(), &dst)
+                    .extractor(Extractor::Xz(TarOptions {
+                        strip_components: 1,
+                        exclude: Box::new(|path| {
+                            path.components().any(|c| c.as_os_str() == "child-b")
+                        }),
+                    }))
+                    .build(),
+            )
+            .unwrap();
+            assert_eq!(summary.excluded_bytes, "child-b".len() as u64);
+            download(
+                &DownloadTask::builder(format!("{}/traversal", server.uri()), &dst)
+                    .extractor(Extractor::Xz(TarOptions {
+                        strip_components: 1,
+                        ..TarOptions::default()
+                    }))
+                    .build(),
+            )
+            .unwrap();
+        });
+
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_success_matcher(server.uri()),
+                extracting_matcher(),
+                request_success_matcher(format!("{}/traversal", server.uri())),
+                extracting_matcher(),
+            ],
+        );
+        assert_eq!(
+            fs::read_to_string(dst.join("child-a/name.txt")).unwrap(),
+            "child-a"
+        );
+        assert!(!dst.join("child-b").exists());
+        assert!(!dst.join("escaped.txt").exists());
+        assert!(!root.path().join("nested/escaped.txt").exists());
+    }
+
     #[test]
     fn test_download_will_not_retry_with_tar_gz_extraction_error() {
         let tarball = create_archive([("parent/child-a/name.txt", "child-a")]);
@@ -791,7 +1771,7 @@ mod test {
         let log = global::with_locked_writer(Vec::<u8>::new(), || {
             match download(
                 &DownloadTask::builder(server.uri(), dst.path())
-                    .extractor(Extractor::Gzip(GzipOptions::default()))
+                    .extractor(Extractor::Gzip(TarOptions::default()))
                     .build(),
             )
             .unwrap_err()
@@ -844,7 +1824,7 @@ mod test {
         let log = global::with_locked_writer(Vec::<u8>::new(), || {
             download(
                 &DownloadTask::builder(server.uri(), dst.path())
-                    .extractor(Extractor::Gzip(GzipOptions::default()))
+                    .extractor(Extractor::Gzip(TarOptions::default()))
                     .build(),
             )
             .unwrap();
@@ -877,6 +1857,303 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_download_does_not_extract_to_destination_after_checksum_mismatch() {
+        let tarball = create_archive([("parent/child-a/name.txt", "child-a")]);
+        let source_url = format!("file://{}", tarball.display());
+        let layers_dir = tempfile::tempdir().unwrap();
+        let dst = layers_dir.path().join("dist");
+
+        global::with_locked_writer(Vec::<u8>::new(), || {
+            match download(
+                &DownloadTask::builder(&source_url, &dst)
+                    .checksum_validator(ChecksumValidator::Sha256(&Sha256::digest("mismatch")))
+                    .extractor(Extractor::Gzip(TarOptions::default()))
+                    .build(),
+            )
+            .unwrap_err()
+            {
+                DownloadError::ChecksumMismatch { .. } => {}
+                e => panic!("Not the expected error: {e:?}"),
+            }
+        });
+
+        assert_eq!(fs::read_dir(layers_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_download_does_not_write_outside_staging_from_tampered_archive() {
+        let outside_dir = tempfile::tempdir().unwrap();
+        let tarball = create_archive_with_links(&[
+            (
+                "parent/escape",
+                tar::EntryType::Symlink,
+                outside_dir.path().to_str().unwrap(),
+            ),
+            ("parent/escape/pwned.txt", tar::EntryType::Regular, "pwned"),
+            (
+                "parent/relative-escape",
+                tar::EntryType::Symlink,
+                "../../../../../../../..",
+            ),
+            (
+                "parent/hard-link-escape",
+                tar::EntryType::Link,
+                "../../etc/passwd",
+            ),
+        ]);
+
+        let server = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+
+                Mock::given(method("GET"))
+                    .and(path("/"))
+                    .respond_with(
+                        ResponseTemplate::new(200).set_body_raw(tarball, "application/gzip"),
+                    )
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+
+                server
+            });
+
+        let layers_dir = tempfile::tempdir().unwrap();
+        let dst = layers_dir.path().join("dist");
+
+        global::with_locked_writer(Vec::<u8>::new(), || {
+            match download(
+                &DownloadTask::builder(server.uri(), &dst)
+                    .checksum_validator(ChecksumValidator::Sha256(&Sha256::digest("mismatch")))
+                    .extractor(Extractor::Gzip(TarOptions::default()))
+                    .build(),
+            )
+            .unwrap_err()
+            {
+                DownloadError::ChecksumMismatch { .. } => {}
+                e => panic!("Not the expected error: {e:?}"),
+            }
+        });
+
+        assert_eq!(fs::read_dir(outside_dir.path()).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(layers_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_tar_extract_rejects_links_outside_destination() {
+        for entries in [
+            vec![("parent/escape", tar::EntryType::Symlink, "/etc")],
+            vec![("parent/escape", tar::EntryType::Symlink, "../..")],
+            vec![("parent/escape", tar::EntryType::Link, "../etc/passwd")],
+            vec![
+                ("parent/dir", tar::EntryType::Symlink, ".."),
+                ("parent/dir/name.txt", tar::EntryType::Regular, "escaped"),
+            ],
+        ] {
+            let destination = tempfile::tempdir().unwrap();
+            let error = tar_extract(
+                MultiGzDecoder::new(create_archive_with_links(&entries).as_slice()),
+                destination.path(),
+                &TarOptions {
+                    strip_components: 1,
+                    exclude: Box::new(|_| false),
+                },
+            )
+            .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{entries:?}");
+        }
+    }
+
+    #[test]
+    fn test_tar_extract_keeps_links_inside_destination() {
+        let destination = tempfile::tempdir().unwrap();
+        tar_extract(
+            MultiGzDecoder::new(
+                create_archive_with_links(&[
+                    ("node/lib/cli.js", tar::EntryType::Regular, "cli"),
+                    ("node/bin/npm", tar::EntryType::Symlink, "../lib/cli.js"),
+                    ("node/bin/npm-copy", tar::EntryType::Link, "node/lib/cli.js"),
+                ])
+                .as_slice(),
+            ),
+            destination.path(),
+            &TarOptions {
+                strip_components: 1,
+                exclude: Box::new(|_| false),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_link(destination.path().join("bin/npm")).unwrap(),
+            Path::new("../lib/cli.js")
+        );
+        assert_eq!(
+            fs::read_to_string(destination.path().join("bin/npm")).unwrap(),
+            "cli"
+        );
+        assert_eq!(
+            fs::read_to_string(destination.path().join("bin/npm-copy")).unwrap(),
+            "cli"
+        );
+    }
+
+    #[test]
+    fn test_stage_download_discards_staging_after_read_error() {
+        struct FailingReader(Option<io::Cursor<Vec<u8>>>);
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.as_mut().map(|body| body.read(buf)) {
+                    Some(Ok(0)) | None => {
+                        self.0 = None;
+                        Err(io::ErrorKind::ConnectionReset.into())
+                    }
+                    Some(read) => read,
+                }
+            }
+        }
+
+        let tarball = fs::read(create_archive([("parent/child-a/name.txt", "child-a")])).unwrap();
+        let layers_dir = tempfile::tempdir().unwrap();
+        let download_task =
+            DownloadTask::builder("https://example.com", layers_dir.path().join("dist"))
+                .extractor(Extractor::Gzip(TarOptions::default()))
+                .build();
+
+        let error = stage_download(
+            FailingReader(Some(io::Cursor::new(tarball[..tarball.len() / 2].to_vec()))),
+            &download_task,
+        )
+        .err()
+        .expect("Staging should fail when the body can't be read");
+
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(fs::read_dir(layers_dir.path()).unwrap().count(), 0);
+    }
+
+    fn start_prefetch_server(responses: &[u16]) -> MockServer {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+                for status_code in responses {
+                    Mock::given(method("GET"))
+                        .and(path("/prefetch"))
+                        .respond_with(ResponseTemplate::new(*status_code).set_body_string("test"))
+                        .up_to_n_times(1)
+                        .expect(1)
+                        .mount(&server)
+                        .await;
+                }
+                server
+            })
+    }
+
+    #[test]
+    fn test_get_uses_prefetched_response() {
+        let server = start_prefetch_server(&[200]);
+        let url = format!("{}/prefetch", server.uri());
+        let request = GetRequest::builder(&url).build();
+
+        let (sender, receiver) = mpsc::channel();
+        prefetch(request.clone(), move |response| {
+            sender.send(response.status()).unwrap();
+        });
+        assert_eq!(receiver.recv().unwrap(), StatusCode::OK);
+
+        let mut body = String::new();
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            let response = get(&request).unwrap();
+            io::Read::read_to_string(&mut response.body_as_file().unwrap(), &mut body).unwrap();
+        });
+
+        assert_log_contains_matches(&log, &[request_success_matcher(&url)]);
+        assert_eq!(body, "test");
+    }
+
+    #[test]
+    fn test_get_retries_after_failed_prefetch() {
+        let server = start_prefetch_server(&[500, 200]);
+        let url = format!("{}/prefetch", server.uri());
+        let request = GetRequest::builder(&url)
+            .max_retries(1)
+            .retry_delay(Duration::ZERO)
+            .build();
+
+        prefetch(request.clone(), |_| {});
+
+        let log = global::with_locked_writer(Vec::<u8>::new(), || {
+            get(&request).unwrap();
+        });
+
+        assert_log_contains_matches(
+            &log,
+            &[
+                request_failed_matcher(&url, "Internal Server Error"),
+                retry_attempt_success_matcher(1, 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_get_ignores_prefetched_response_with_different_headers() {
+        let server = start_prefetch_server(&[200, 200]);
+        let url = format!("{}/prefetch", server.uri());
+        let mut headers = HeaderMap::new();
+        headers.insert("If-None-Match", "\"etag\"".parse().unwrap());
+        let prefetched_request = GetRequest::builder(&url).headers(headers).build();
+
+        prefetch(prefetched_request.clone(), |_| {});
+
+        global::with_locked_writer(Vec::<u8>::new(), || {
+            get(&GetRequest::builder(&url).build()).unwrap();
+        });
+
+        assert!(
+            take_prefetched_response(&prefetched_request)
+                .unwrap()
+                .recv()
+                .unwrap()
+                .is_ok()
+        );
+    }
+
+    /// Serves one scripted response per connection and returns the requests that were received,
+    /// with lowercase header names. wiremock can't close a connection partway through a body.
+    fn start_scripted_server(
+        responses: impl IntoIterator<Item = String> + Send + 'static,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let read = stream.read(&mut buffer).unwrap();
+                        request.extend_from_slice(&buffer[..read]);
+                    }
+                    stream.write_all(response.as_bytes()).unwrap();
+                    String::from_utf8(request).unwrap().to_lowercase()
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    /// A response that claims to be longer than its body so the connection closes partway through.
+    fn truncated_response(headers: &str, body: &str, content_length: usize) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {content_length}\r\n{headers}\r\n{body}")
+    }
+
     fn assert_log_contains_matches(log: &[u8], matchers: &[Regex]) {
         let output = strip_ansi(String::from_utf8_lossy(log));
         let actual_lines = output.lines().map(str::trim).collect::<Vec<_>>();
@@ -924,6 +2201,11 @@ mod test {
         .unwrap()
     }
 
+    fn reading_matcher(url: impl AsRef<str>) -> Regex {
+        let url = url.as_ref().replace('.', r"\.");
+        Regex::new(&format!(r"- Reading {url}$")).unwrap()
+    }
+
     fn validating_matcher() -> Regex {
         Regex::new(&format!(r"- Validating {PROGRESS_DOTS} {TIMER}")).unwrap()
     }
@@ -951,15 +2233,7 @@ mod test {
     fn create_archive<'a>(
         files: impl IntoIterator<Item = (impl Into<PathBuf>, &'a str)>,
     ) -> PathBuf {
-        let archive_folder = tempfile::tempdir().unwrap();
-        for (path, contents) in files {
-            let path = path.into();
-            assert!(!path.is_absolute(), "Only relative paths allowed");
-            let archive_path = archive_folder.path().join(path);
-            fs::create_dir_all(archive_path.parent().unwrap()).unwrap();
-            fs::write(archive_path, contents).unwrap();
-        }
-
+        let archive_folder = create_archive_folder(files);
         let (archive_file, archive_path) = tempfile::NamedTempFile::new().unwrap().keep().unwrap();
         let mut tarball = tar::Builder::new(GzEncoder::new(archive_file, Compression::default()));
         tarball.append_dir_all("", archive_folder.path()).unwrap();
@@ -971,4 +2245,48 @@ mod test {
 
         archive_path
     }
+
+    /// Builds a gzipped tarball entry by entry. The contents of link entries are their targets.
+    fn create_archive_with_links(entries: &[(&str, tar::EntryType, &str)]) -> Vec<u8> {
+        let mut tarball = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, entry_type, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            if entry_type.is_file() {
+                header.set_size(contents.len() as u64);
+                tarball
+                    .append_data(&mut header, path, contents.as_bytes())
+                    .unwrap();
+            } else {
+                header.set_size(0);
+                tarball.append_link(&mut header, path, contents).unwrap();
+            }
+        }
+        tarball.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn create_xz_archive<'a>(
+        files: impl IntoIterator<Item = (impl Into<PathBuf>, &'a str)>,
+    ) -> Vec<u8> {
+        let archive_folder = create_archive_folder(files);
+        let mut tarball =
+            tar::Builder::new(XzWriter::new(Vec::new(), XzOptions::with_preset(1)).unwrap());
+        tarball.append_dir_all("", archive_folder.path()).unwrap();
+        tarball.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn create_archive_folder<'a>(
+        files: impl IntoIterator<Item = (impl Into<PathBuf>, &'a str)>,
+    ) -> tempfile::TempDir {
+        let archive_folder = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = path.into();
+            assert!(!path.is_absolute(), "Only relative paths allowed");
+            let archive_path = archive_folder.path().join(path);
+            fs::create_dir_all(archive_path.parent().unwrap()).unwrap();
+            fs::write(archive_path, contents).unwrap();
+        }
+        archive_folder
+    }
 }
@@ -1,5 +1,6 @@
 pub(crate) mod build_env;
 pub(crate) mod error_handling;
+pub(crate) mod fs;
 pub(crate) mod http;
 pub(crate) mod npm_registry;
 pub(crate) mod runtime_env;
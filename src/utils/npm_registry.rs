@@ -1,13 +1,18 @@
+use crate::target::TargetMetadata;
 use crate::utils::error_handling::ErrorType::UserFacing;
 use crate::utils::error_handling::{
     ErrorMessage, SuggestRetryBuild, SuggestSubmitIssue, error_message, file_value,
 };
 use crate::utils::http::{
-    DownloadError, DownloadTask, Extractor, GetError, GetRequest, GzipOptions, download, get,
+    ChecksumValidator, DownloadError, DownloadTask, Extractor, GetError, GetRequest, TarOptions,
+    download, get, prefetch,
 };
 use crate::{BuildpackBuildContext, BuildpackError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use bullet_stream::global::print;
 use bullet_stream::style;
+use http::header::ACCEPT;
 use http::{HeaderMap, HeaderValue, StatusCode};
 use indoc::formatdoc;
 use libcnb::Env;
@@ -17,6 +22,7 @@ use libcnb::layer::{
 };
 use libcnb::layer_env::Scope;
 use nodejs_data::{Version, VersionRange};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
@@ -25,8 +31,24 @@ use std::{fs, io};
 
 const NPMJS_ORG_HOST: &str = "https://registry.npmjs.org";
 
+/// Requests the abbreviated ("corgi") packument format, which only contains the fields needed to
+/// install a package, while still accepting the full document from registries without support.
+/// See <https://github.com/npm/registry/blob/main/docs/responses/package-metadata.md>.
+const ABBREVIATED_PACKUMENT_ACCEPT: &str =
+    "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*";
+
 const NPM_STATUS_URL: &str = "https://status.npmjs.org/";
 
+/// Directory in the application where packument snapshots and package tarballs can be vendored
+/// for builds that cannot reach the npm registry. Each package is stored in its own directory
+/// (e.g. `.heroku/vendor/pnpm/` or `.heroku/vendor/@yarnpkg/cli-dist/`) containing a
+/// `packument.json` file and the tarballs as produced by `npm pack` (e.g. `pnpm-9.15.0.tgz` or `yarnpkg-cli-dist-4.5.0.tgz`).
+const VENDOR_DIR: &str = ".heroku/vendor";
+
+const VENDORED_PACKUMENT_FILENAME: &str = "packument.json";
+
+const PACKUMENT_FILENAME: &str = "contents.json";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct PackumentMetadata {
@@ -39,18 +61,13 @@ pub(crate) fn packument_layer(
     context: &BuildpackBuildContext,
     package_name: impl AsRef<str>,
 ) -> Result<Packument, PackumentLayerError> {
-    let packument_filename = "contents.json";
-
     let package_name = package_name.as_ref();
 
-    let parse_packument = |packument_path: &Path| {
-        fs::read_to_string(packument_path)
-            .map_err(|e| PackumentLayerError::ReadPackument(package_name.to_string(), e))
-            .and_then(|packument_contents| {
-                serde_json::from_str::<Packument>(&packument_contents)
-                    .map_err(|e| PackumentLayerError::ParsePackument(package_name.to_string(), e))
-            })
-    };
+    if let Some(packument) = read_vendored_packument(&context.app_dir, package_name)? {
+        return Ok(packument);
+    }
+
+    let parse_packument = |packument_path: &Path| read_packument(packument_path, package_name);
 
     let packument_layer = context
         .cached_layer(
@@ -61,7 +78,7 @@ pub(crate) fn packument_layer(
                 invalid_metadata_action: &|_| InvalidMetadataAction::DeleteLayer,
                 restored_layer_action: &|packument_metadata: &PackumentMetadata, layer_dir| {
                     // make sure we can deserialize the packument file stored in the layer
-                    if parse_packument(&layer_dir.join(packument_filename)).is_ok() {
+                    if parse_packument(&layer_dir.join(PACKUMENT_FILENAME)).is_ok() {
                         (RestoredLayerAction::KeepLayer, packument_metadata.clone())
                     } else {
                         (
@@ -84,9 +101,48 @@ pub(crate) fn packument_layer(
         LayerState::Empty { .. } => None,
     };
 
+    let packument_file = packument_layer.path().join(PACKUMENT_FILENAME);
+
+    let request = GetRequest::builder(format!("{NPMJS_ORG_HOST}/{package_name}"))
+        .headers(packument_request_headers(packument_metadata))
+        .build();
+
+    tracing::info_span!("fetch_packument").in_scope(|| {
+        match fetch_packument(
+            &request,
+            package_name,
+            &packument_file,
+            packument_metadata.is_some(),
+        )? {
+            FetchPackumentOutcome::Updated(packument_metadata) => {
+                packument_layer
+                    .write_metadata(packument_metadata)
+                    .map_err(|e| PackumentLayerError::Layer(Box::new(e)))?;
+            }
+            FetchPackumentOutcome::NotModified => {
+                print::sub_bullet(format!("Using cached packument for {package_name}"));
+            }
+            FetchPackumentOutcome::Stale(error) => {
+                print::warning(create_stale_packument_warning(package_name));
+                return parse_packument(&packument_file).map(|packument| Packument {
+                    source: PackumentSource::StaleCache(error.to_string()),
+                    ..packument
+                });
+            }
+        }
+
+        parse_packument(&packument_file)
+    })
+}
+
+fn packument_request_headers(packument_metadata: Option<&PackumentMetadata>) -> HeaderMap {
     let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static(ABBREVIATED_PACKUMENT_ACCEPT),
+    );
 
-    if let Some(packument_metadata) = &packument_metadata {
+    if let Some(packument_metadata) = packument_metadata {
         if let Some(etag) = &packument_metadata.etag
             && let Ok(etag) = HeaderValue::from_str(etag)
         {
@@ -99,53 +155,244 @@ pub(crate) fn packument_layer(
         }
     }
 
-    tracing::info_span!("fetch_packument").in_scope(|| {
-        let packument_response = get(&GetRequest::builder(format!(
-            "{NPMJS_ORG_HOST}/{package_name}"
+    headers
+}
+
+enum FetchPackumentOutcome {
+    /// A new packument was written, along with the validators to store in the layer metadata.
+    Updated(PackumentMetadata),
+    NotModified,
+    /// The registry is unavailable so the previously cached packument should be used instead.
+    Stale(GetError),
+}
+
+fn fetch_packument(
+    request: &GetRequest,
+    package_name: &str,
+    packument_file: &Path,
+    has_cached_packument: bool,
+) -> Result<FetchPackumentOutcome, PackumentLayerError> {
+    let packument_response = match get(request) {
+        Ok(packument_response) => packument_response,
+        Err(error) if has_cached_packument && is_registry_unavailable(&error) => {
+            return Ok(FetchPackumentOutcome::Stale(error));
+        }
+        Err(error) => {
+            return Err(PackumentLayerError::FetchPackument(
+                package_name.to_string(),
+                error,
+            ));
+        }
+    };
+
+    // only update the metadata if we have a 200 response
+    if packument_response.status() == StatusCode::OK {
+        let etag = packument_response
+            .headers()
+            .get("ETag")
+            .and_then(|value| value.to_str().map(ToString::to_string).ok());
+
+        let last_modified = packument_response
+            .headers()
+            .get("Last-Modified")
+            .and_then(|value| value.to_str().map(ToString::to_string).ok());
+
+        let mut response_body = packument_response
+            .body_as_file()
+            .map_err(|e| PackumentLayerError::WritePackument(package_name.to_string(), e))?;
+
+        File::create(packument_file)
+            .and_then(|mut packument_file| io::copy(&mut response_body, &mut packument_file))
+            .map_err(|e| PackumentLayerError::WritePackument(package_name.to_string(), e))?;
+
+        Ok(FetchPackumentOutcome::Updated(PackumentMetadata {
+            etag,
+            last_modified,
+        }))
+    } else if packument_response.status() == StatusCode::NOT_MODIFIED {
+        Ok(FetchPackumentOutcome::NotModified)
+    } else {
+        Err(PackumentLayerError::UnexpectedResponse(
+            package_name.to_string(),
+            packument_response.status(),
         ))
-        .headers(headers)
-        .build())
-        .map_err(|e| PackumentLayerError::FetchPackument(package_name.to_string(), e))?;
-
-        let packument_file = packument_layer.path().join(packument_filename);
-
-        // only update the metadata if we have a 200 response
-        if packument_response.status() == StatusCode::OK {
-            let etag = packument_response
-                .headers()
-                .get("ETag")
-                .and_then(|value| value.to_str().map(ToString::to_string).ok());
-
-            let last_modified = packument_response
-                .headers()
-                .get("Last-Modified")
-                .and_then(|value| value.to_str().map(ToString::to_string).ok());
-
-            let mut response_body = packument_response
-                .body_as_file()
-                .map_err(|e| PackumentLayerError::WritePackument(package_name.to_string(), e))?;
-
-            File::create(&packument_file)
-                .and_then(|mut packument_file| io::copy(&mut response_body, &mut packument_file))
-                .map_err(|e| PackumentLayerError::WritePackument(package_name.to_string(), e))?;
-
-            packument_layer
-                .write_metadata(PackumentMetadata {
-                    etag,
-                    last_modified,
-                })
-                .map_err(|e| PackumentLayerError::Layer(Box::new(e)))?;
-        } else if packument_response.status() == StatusCode::NOT_MODIFIED {
-            print::sub_bullet(format!("Using cached packument for {package_name}"));
+    }
+}
+
+/// Connection failures, timeouts, and server errors mean the registry is unavailable. Client errors
+/// (e.g. a `404` for an unknown package) won't be fixed by using a cached packument.
+fn is_registry_unavailable(error: &GetError) -> bool {
+    match error {
+        GetError::Request(error) => {
+            !error.is_builder()
+                && error
+                    .status()
+                    .is_none_or(|status_code| status_code.is_server_error())
+        }
+        GetError::Write(_) => false,
+    }
+}
+
+fn create_stale_packument_warning(package_name: &str) -> String {
+    let package_name = style::value(package_name);
+    let npm_status_url = style::url(NPM_STATUS_URL);
+    formatdoc! { "
+        The npm registry could not be reached so the available {package_name} versions cached from a \
+        previous build will be used. Any {package_name} releases published since then won't be \
+        available until the registry can be reached.
+
+        Check the npm status page for any ongoing incidents ({npm_status_url}).
+    " }
+}
+
+fn create_missing_integrity_warning(package_packument: &PackagePackument) -> String {
+    let package_name = style::value(&package_packument.name);
+    let version = style::value(package_packument.version.to_string());
+    formatdoc! { "
+        The packument for {package_name} version {version} doesn't declare a sha512 integrity value \
+        so the downloaded tarball can't be verified before it's installed.
+    " }
+}
+
+/// Starts downloading the packument for the package, and the tarball for the version it resolves
+/// to, in the background. The requests match the ones [`packument_layer`] and
+/// [`install_package_layer`] will send, based on the metadata of their cached layers, so the
+/// responses are ready by the time those run. Nothing is prefetched for vendored packages, when
+/// the requested version is already installed, or when its `engines.node` doesn't allow the
+/// given Node.js version (the build fails on that check before installing anything).
+pub(crate) fn prefetch_package(
+    context: &BuildpackBuildContext,
+    packument_layer_name: &LayerName,
+    install_layer_name: &LayerName,
+    package_name: &str,
+    requirement: &VersionRange,
+    node_version: &Version,
+) {
+    if context
+        .app_dir
+        .join(VENDOR_DIR)
+        .join(package_name)
+        .join(VENDORED_PACKUMENT_FILENAME)
+        .is_file()
+    {
+        return;
+    }
+
+    let packument_metadata =
+        read_cached_layer_metadata::<PackumentMetadata>(&context.layers_dir, packument_layer_name);
+    let cached_packument_file = context
+        .layers_dir
+        .join(packument_layer_name.as_str())
+        .join(PACKUMENT_FILENAME);
+    let installed_package = read_cached_layer_metadata::<InstallPackageLayerMetadata>(
+        &context.layers_dir,
+        install_layer_name,
+    );
+    let requirement = requirement.clone();
+    let node_version = node_version.clone();
+
+    prefetch(
+        GetRequest::builder(format!("{NPMJS_ORG_HOST}/{package_name}"))
+            .headers(packument_request_headers(packument_metadata.as_ref()))
+            .build(),
+        move |packument_response| {
+            let packument_file = match packument_response.status() {
+                StatusCode::OK => packument_response.body_as_file(),
+                StatusCode::NOT_MODIFIED => File::open(cached_packument_file),
+                _ => return,
+            };
+            let Some(package_packument) = packument_file
+                .ok()
+                .and_then(|file| serde_json::from_reader(io::BufReader::new(file)).ok())
+                .and_then(|packument| resolve_package_packument(&packument, &requirement).ok())
+            else {
+                return;
+            };
+            if package_packument
+                .engines
+                .node
+                .as_ref()
+                .is_some_and(|node_engine| !node_engine.satisfies(&node_version))
+            {
+                return;
+            }
+            let already_installed = installed_package.is_some_and(|installed_package| {
+                installed_package.layer_version == INSTALL_PACKAGE_LAYER_VERSION
+                    && installed_package.package_name == package_packument.name
+                    && installed_package.package_version == package_packument.version.to_string()
+            });
+            if !already_installed {
+                prefetch(
+                    GetRequest::builder(&package_packument.dist.tarball).build(),
+                    |_| {},
+                );
+            }
+        },
+    );
+}
+
+/// Reads the metadata stored for a layer by a previous build without restoring it.
+fn read_cached_layer_metadata<M: DeserializeOwned>(
+    layers_dir: &Path,
+    layer_name: &LayerName,
+) -> Option<M> {
+    fs::read_to_string(layers_dir.join(format!("{layer_name}.toml")))
+        .ok()
+        .and_then(|layer_toml| toml::from_str::<toml::Table>(&layer_toml).ok())
+        .and_then(|mut layer_toml| layer_toml.remove("metadata"))
+        .and_then(|metadata| metadata.try_into().ok())
+}
+
+/// Loads the vendored packument for the package, if one exists. Only the versions with a vendored
+/// tarball are kept and their tarball urls are rewritten to point at the local file.
+fn read_vendored_packument(
+    app_dir: &Path,
+    package_name: &str,
+) -> Result<Option<Packument>, PackumentLayerError> {
+    let vendor_dir = app_dir.join(VENDOR_DIR).join(package_name);
+    let packument_path = vendor_dir.join(VENDORED_PACKUMENT_FILENAME);
+    if !packument_path.is_file() {
+        return Ok(None);
+    }
+
+    print::sub_bullet(format!(
+        "Using vendored packument for {package_name} from {}",
+        file_value(&packument_path)
+    ));
+
+    let mut packument = read_packument(&packument_path, package_name)?;
+
+    packument.versions.retain(|version, package_packument| {
+        let tarball = vendor_dir.join(vendored_tarball_filename(package_name, version));
+        if tarball.is_file() {
+            package_packument.dist.tarball = format!("file://{}", tarball.display());
+            true
         } else {
-            Err(PackumentLayerError::UnexpectedResponse(
-                package_name.to_string(),
-                packument_response.status(),
-            ))?;
+            false
         }
+    });
+    packument.source = PackumentSource::Vendored(vendor_dir);
 
-        parse_packument(&packument_file)
-    })
+    Ok(Some(packument))
+}
+
+/// Packuments are parsed straight from the file so only the fields declared on [`Packument`] are
+/// materialized, instead of first buffering the whole document into memory.
+fn read_packument(
+    packument_path: &Path,
+    package_name: &str,
+) -> Result<Packument, PackumentLayerError> {
+    let packument_file = File::open(packument_path)
+        .map_err(|e| PackumentLayerError::ReadPackument(package_name.to_string(), e))?;
+    serde_json::from_reader(io::BufReader::new(packument_file))
+        .map_err(|e| PackumentLayerError::ParsePackument(package_name.to_string(), e))
+}
+
+/// Tarballs are named the same way `npm pack` names them, so `@scope/name` becomes
+/// `scope-name-<version>.tgz`.
+fn vendored_tarball_filename(package_name: &str, version: &Version) -> String {
+    let name = package_name.trim_start_matches('@').replace('/', "-");
+    format!("{name}-{version}.tgz")
 }
 
 #[derive(Debug)]
@@ -271,6 +518,20 @@ fn create_packument_layer_internal_error(
 pub(crate) struct Packument {
     pub(crate) name: String,
     pub(crate) versions: HashMap<Version, PackagePackument>,
+    #[serde(skip)]
+    pub(crate) source: PackumentSource,
+}
+
+/// Where a packument was loaded from, which determines how resolution failures are reported.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) enum PackumentSource {
+    #[default]
+    Registry,
+    /// The application's vendor directory for the package.
+    Vendored(PathBuf),
+    /// The layer cache from a previous build, used because the registry request failed with the
+    /// contained error.
+    StaleCache(String),
 }
 
 #[derive(Deserialize, Clone)]
@@ -279,11 +540,43 @@ pub(crate) struct PackagePackument {
     pub(crate) version: Version,
     pub(crate) dist: PackagePackumentDist,
     pub(crate) bin: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_engines")]
+    pub(crate) engines: PackagePackumentEngines,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub(crate) struct PackagePackumentEngines {
+    pub(crate) node: Option<VersionRange>,
+}
+
+/// Some old releases declare `engines` as an array or with ranges that don't parse. These are
+/// treated as having no engine requirements rather than failing to read the whole packument.
+fn deserialize_engines<'de, D>(deserializer: D) -> Result<PackagePackumentEngines, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    if !value.is_object() {
+        return Ok(PackagePackumentEngines::default());
+    }
+    Ok(serde_json::from_value(value).unwrap_or_default())
 }
 
 #[derive(Deserialize, Clone)]
 pub(crate) struct PackagePackumentDist {
     pub(crate) tarball: String,
+    pub(crate) integrity: Option<String>,
+}
+
+impl PackagePackumentDist {
+    /// Decodes the digest from a `sha512-<base64>` Subresource Integrity value. Other algorithms
+    /// (e.g. the `sha1` used by very old packages) are not verified.
+    fn sha512_integrity(&self) -> Option<Vec<u8>> {
+        self.integrity
+            .as_deref()
+            .and_then(|integrity| integrity.strip_prefix("sha512-"))
+            .and_then(|digest| STANDARD.decode(digest).ok())
+    }
 }
 
 pub(crate) fn resolve_package_packument(
@@ -298,7 +591,158 @@ pub(crate) fn resolve_package_packument(
     package_packuments
         .into_iter()
         .find(|package_packument| requirement.satisfies(&package_packument.version))
-        .ok_or_else(|| create_resolve_package_packument_error(packument, requirement))
+        .ok_or_else(|| match &packument.source {
+            PackumentSource::Registry => {
+                create_resolve_package_packument_error(packument, requirement)
+            }
+            PackumentSource::Vendored(vendor_dir) => {
+                create_resolve_vendored_package_packument_error(packument, vendor_dir, requirement)
+            }
+            PackumentSource::StaleCache(fetch_error) => {
+                create_resolve_stale_package_packument_error(packument, fetch_error, requirement)
+            }
+        })
+}
+
+/// Verifies the resolved package supports the installed Node.js version according to its
+/// `engines.node` field so an incompatible package manager isn't downloaded.
+pub(crate) fn check_node_engine(
+    packument: &Packument,
+    package_packument: &PackagePackument,
+    node_version: &Version,
+) -> Result<(), ErrorMessage> {
+    match &package_packument.engines.node {
+        Some(node_engine) if !node_engine.satisfies(node_version) => {
+            Err(create_node_engine_mismatch_error(
+                packument,
+                package_packument,
+                node_engine,
+                node_version,
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn create_node_engine_mismatch_error(
+    packument: &Packument,
+    package_packument: &PackagePackument,
+    node_engine: &VersionRange,
+    node_version: &Version,
+) -> ErrorMessage {
+    // prereleases aren't suggested since they don't satisfy the `*` range
+    let stable_releases = VersionRange::parse("*").expect("Wildcard range should be valid");
+    let compatible_version = packument
+        .versions
+        .values()
+        .filter(|candidate| {
+            stable_releases.satisfies(&candidate.version)
+                && candidate
+                    .engines
+                    .node
+                    .as_ref()
+                    .is_some_and(|node| node.satisfies(node_version))
+        })
+        .map(|candidate| &candidate.version)
+        .max();
+
+    let package = style::value(format!(
+        "{}@{}",
+        package_packument.name, package_packument.version
+    ));
+    let package_name = style::value(&packument.name);
+    let node_engine = style::value(node_engine.to_string());
+    let node_version = style::value(node_version.to_string());
+    let compatible_version_suggestion = match compatible_version {
+        Some(compatible_version) => formatdoc! { "
+            - Request {package_name} version {compatible_version}, the latest release that supports \
+            Node.js {node_version}.
+        ", compatible_version = style::value(compatible_version.to_string()) },
+        None => formatdoc! { "
+            - Request an older {package_name} version that supports Node.js {node_version}.
+        " },
+    };
+
+    error_message()
+        .id(format!("npm_registry/{}/engines/node", packument.name))
+        .error_type(UserFacing(SuggestRetryBuild::No, SuggestSubmitIssue::No))
+        .header(format!("{package} does not support Node.js {node_version}"))
+        .body(formatdoc! { "
+            The resolved version {package} requires Node.js {node_engine} but Node.js \
+            {node_version} is installed. Running it on this Node.js version would fail.
+
+            Suggestions:
+            - Request a Node.js version that satisfies {node_engine} with {engines_node} in {package_json}.
+            {compatible_version_suggestion}
+        ", engines_node = style::value("engines.node"), package_json = file_value("package.json") })
+        .create()
+}
+
+fn create_resolve_vendored_package_packument_error(
+    packument: &Packument,
+    vendor_dir: &Path,
+    requirement: &VersionRange,
+) -> ErrorMessage {
+    let mut vendored_versions = packument.versions.keys().collect::<Vec<_>>();
+    vendored_versions.sort();
+    let vendored_versions = if vendored_versions.is_empty() {
+        "none".to_string()
+    } else {
+        vendored_versions
+            .iter()
+            .map(|version| style::value(version.to_string()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let npm_pack_command = style::command(format!(
+        "npm pack '{}@{requirement}' --pack-destination {VENDOR_DIR}/{}",
+        packument.name, packument.name
+    ));
+    let vendored_packument = file_value(vendor_dir.join(VENDORED_PACKUMENT_FILENAME));
+    let package_name = style::value(&packument.name);
+    let requested_version = style::value(requirement.to_string());
+    let vendor_dir = file_value(vendor_dir);
+
+    error_message()
+        .id(format!("npm_registry/{}/packument/resolve_vendored", packument.name))
+        .error_type(UserFacing(SuggestRetryBuild::No, SuggestSubmitIssue::No))
+        .header(format!("Requested {package_name} version {requested_version} is not vendored"))
+        .body(formatdoc! { "
+            A vendored packument for {package_name} was found at {vendored_packument} but none of the \
+            tarballs in {vendor_dir} match the requested version.
+
+            Vendored versions: {vendored_versions}
+
+            Suggestions:
+            - Add a matching tarball by running {npm_pack_command}
+            - Confirm the matching version is listed in {vendored_packument}
+        " })
+        .create()
+}
+
+fn create_resolve_stale_package_packument_error(
+    packument: &Packument,
+    fetch_error: &str,
+    requirement: &VersionRange,
+) -> ErrorMessage {
+    let package_name = style::value(&packument.name);
+    let requested_version = style::value(requirement.to_string());
+    let npm_status_url = style::url(NPM_STATUS_URL);
+
+    error_message()
+        .id(format!("npm_registry/{}/packument/resolve_stale", packument.name))
+        .error_type(UserFacing(SuggestRetryBuild::Yes, SuggestSubmitIssue::No))
+        .header(format!("Error resolving requested {package_name} version {requested_version}"))
+        .body(formatdoc! { "
+            The npm registry could not be reached and none of the {package_name} versions cached from \
+            a previous build match the requested version. This error can occur due to an unstable \
+            network connection or an issue with the npm registry.
+
+            Suggestions:
+            - Check the npm status page for any ongoing incidents ({npm_status_url})
+        " })
+        .debug_info(fetch_error)
+        .create()
 }
 
 fn create_resolve_package_packument_error(
@@ -344,8 +788,7 @@ pub(crate) fn install_package_layer(
         package_name: package_name.clone(),
         package_version: package_version.to_string(),
         layer_version: INSTALL_PACKAGE_LAYER_VERSION.to_string(),
-        arch: context.target.arch.clone(),
-        os: context.target.os.clone(),
+        target: TargetMetadata::from(&context.target),
     };
 
     let install_package_layer = context
@@ -381,12 +824,17 @@ pub(crate) fn install_package_layer(
                 ));
             }
 
+            let integrity = package_packument.dist.sha512_integrity();
+            if integrity.is_none() {
+                print::warning(create_missing_integrity_warning(package_packument));
+            }
             download(
                 &DownloadTask::builder(
                     &package_packument.dist.tarball,
                     install_package_layer.path(),
                 )
-                .extractor(Extractor::Gzip(GzipOptions {
+                .maybe_checksum_validator(integrity.as_deref().map(ChecksumValidator::Sha512))
+                .extractor(Extractor::Gzip(TarOptions {
                     strip_components: 1,
                     exclude: Box::new(|path| {
                         path.components()
@@ -483,8 +931,46 @@ fn create_install_package_download_error(
                 .create()
         }
 
-        DownloadError::ChecksumMismatch { .. } => {
-            unreachable!("The package installation layer does not perform checksums currently")
+        DownloadError::ReadFile { url, source } => {
+            let package_name = style::value(&package_packument.name);
+            let url = style::url(url);
+            error_message()
+                .id(format!("npm_registry/{}/download/read_file", package_packument.name))
+                .error_type(UserFacing(SuggestRetryBuild::No, SuggestSubmitIssue::No))
+                .header(format!("Failed to read vendored {package_name} package"))
+                .body(formatdoc! {"
+                    An unexpected error occurred while reading the vendored {package_name} package from {url}.
+
+                    Suggestions:
+                    - Confirm the file exists and has read permissions.
+                " })
+                .debug_info(source.to_string())
+                .create()
+        }
+
+        DownloadError::ChecksumMismatch {
+            url,
+            expected_checksum,
+            actual_checksum,
+        } => {
+            let package_name = style::value(&package_packument.name);
+            let url = style::url(url);
+            let expected = style::value(expected_checksum);
+            let actual = style::value(actual_checksum);
+            error_message()
+                .id(format!("npm_registry/{}/download/checksum", package_packument.name))
+                .error_type(UserFacing(SuggestRetryBuild::Yes, SuggestSubmitIssue::No))
+                .header(format!("{package_name} package integrity verification failed"))
+                .body(formatdoc! {"
+                    The {package_name} package from {url} does not match the integrity value declared in \
+                    its packument. If this package was vendored, the tarball may be corrupt or may not \
+                    match the vendored packument.
+
+                    Checksum (sha512):
+                    - Expected: {expected}
+                    - Actual: {actual}
+                " })
+                .create()
         }
 
         DownloadError::Write {
@@ -561,17 +1047,14 @@ fn install_layer_changed_metadata_fields(
     if old.layer_version != new.layer_version {
         changed.push("layer version".to_string());
     }
-    if old.os != new.os {
-        changed.push("operating system".to_string());
-    }
-    if old.arch != new.arch {
-        changed.push("cpu architecture".to_string());
+    if old.target != new.target {
+        changed.push("build target".to_string());
     }
     changed.sort();
     changed
 }
 
-const INSTALL_PACKAGE_LAYER_VERSION: &str = "1";
+const INSTALL_PACKAGE_LAYER_VERSION: &str = "2";
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
@@ -580,8 +1063,7 @@ pub(crate) struct InstallPackageLayerMetadata {
     package_name: String,
     package_version: String,
     node_version: String,
-    arch: String,
-    os: String,
+    target: TargetMetadata,
 }
 
 #[cfg(test)]
@@ -589,6 +1071,8 @@ mod test {
     use super::*;
     use crate::utils::error_handling::test_util::{assert_error_snapshot, create_reqwest_error};
     use std::str::FromStr;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
     #[test]
     fn test_packument_metadata() {
@@ -613,18 +1097,27 @@ last_modified = "Fri, 02 May 2025 13:50:44 GMT"
             package_name: "test".to_string(),
             package_version: "1.0.0".to_string(),
             node_version: "18.16.0".to_string(),
-            arch: "x64".to_string(),
-            os: "linux".to_string(),
+            target: TargetMetadata {
+                os: "linux".to_string(),
+                arch: "amd64".to_string(),
+                arch_variant: None,
+                distro_name: "ubuntu".to_string(),
+                distro_version: "24.04".to_string(),
+            },
         };
 
         let actual = toml::to_string(&metadata).unwrap();
         let expected = r#"
-layer_version = "1"
+layer_version = "2"
 package_name = "test"
 package_version = "1.0.0"
 node_version = "18.16.0"
-arch = "x64"
+
+[target]
 os = "linux"
+arch = "amd64"
+distro_name = "ubuntu"
+distro_version = "24.04"
 "#
         .trim();
         assert_eq!(expected, actual.trim());
@@ -639,13 +1132,20 @@ os = "linux"
         version: String,
         #[builder(default = HashMap::new())] //
         bin: HashMap<String, String>,
+        node_engine: Option<String>,
     ) -> PackagePackument {
         let tarball = format!("https://registry.npmjs.org/{name}/-/{name}-{version}.tgz");
         PackagePackument {
             name,
             version: Version::from_str(&version).unwrap(),
-            dist: PackagePackumentDist { tarball },
+            dist: PackagePackumentDist {
+                tarball,
+                integrity: None,
+            },
             bin: Some(bin),
+            engines: PackagePackumentEngines {
+                node: node_engine.map(|node_engine| VersionRange::parse(&node_engine).unwrap()),
+            },
         }
     }
 
@@ -673,4 +1173,446 @@ os = "linux"
             },
         ));
     }
+
+    #[test]
+    fn test_install_package_download_read_file_error() {
+        let package_packument = package_packument("pnpm").build();
+        assert_error_snapshot(&create_install_package_download_error(
+            &package_packument,
+            &DownloadError::ReadFile {
+                url: "file:///workspace/.heroku/vendor/pnpm/pnpm-1.0.0.tgz".to_string(),
+                source: io::Error::from(io::ErrorKind::NotFound),
+            },
+        ));
+    }
+
+    #[test]
+    fn test_install_package_download_checksum_error() {
+        let package_packument = package_packument("pnpm").build();
+        assert_error_snapshot(&create_install_package_download_error(
+            &package_packument,
+            &DownloadError::ChecksumMismatch {
+                url: "file:///workspace/.heroku/vendor/pnpm/pnpm-1.0.0.tgz".to_string(),
+                expected_checksum: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+                actual_checksum: "e62ff0123a74adfc6903d59a449cbdb0".to_string(),
+            },
+        ));
+    }
+
+    #[test]
+    fn test_read_cached_layer_metadata() {
+        let layers_dir = tempfile::tempdir().unwrap();
+        let layer_name = LayerName::from_str("pnpm_packument").unwrap();
+        assert!(
+            read_cached_layer_metadata::<PackumentMetadata>(layers_dir.path(), &layer_name)
+                .is_none()
+        );
+
+        fs::write(
+            layers_dir.path().join("pnpm_packument.toml"),
+            indoc::indoc! { r#"
+                [types]
+                build = true
+
+                [metadata]
+                etag = '"fd20872fa73c2b790cc0eb7ff1bb42da"'
+            "# },
+        )
+        .unwrap();
+        let metadata =
+            read_cached_layer_metadata::<PackumentMetadata>(layers_dir.path(), &layer_name)
+                .unwrap();
+        assert_eq!(
+            metadata.etag,
+            Some("\"fd20872fa73c2b790cc0eb7ff1bb42da\"".to_string())
+        );
+        assert_eq!(metadata.last_modified, None);
+    }
+
+    #[test]
+    fn test_sha512_integrity() {
+        let dist = PackagePackumentDist {
+            tarball: String::new(),
+            integrity: Some(format!("sha512-{}", STANDARD.encode([1, 2, 3]))),
+        };
+        assert_eq!(dist.sha512_integrity(), Some(vec![1, 2, 3]));
+
+        let dist = PackagePackumentDist {
+            tarball: String::new(),
+            integrity: Some("sha1-AQID".to_string()),
+        };
+        assert_eq!(dist.sha512_integrity(), None);
+    }
+
+    #[test]
+    fn test_package_packument_engines() {
+        let package_packument = |engines: serde_json::Value| {
+            serde_json::from_value::<PackagePackument>(serde_json::json!({
+                "name": "npm",
+                "version": "11.0.0",
+                "dist": { "tarball": "https://registry.npmjs.org/npm/-/npm-11.0.0.tgz" },
+                "engines": engines,
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            package_packument(serde_json::json!({ "node": "^20.17.0 || >=22.9.0" }))
+                .engines
+                .node
+                .map(|node| node.to_string()),
+            Some("^20.17.0 || >=22.9.0".to_string())
+        );
+        assert!(
+            package_packument(serde_json::json!(["node >=0.4"]))
+                .engines
+                .node
+                .is_none()
+        );
+        assert!(
+            package_packument(serde_json::json!({ "node": "not a range" }))
+                .engines
+                .node
+                .is_none()
+        );
+    }
+
+    fn npm_packument() -> Packument {
+        Packument {
+            name: "npm".to_string(),
+            versions: [
+                ("10.9.2", "^18.17.0 || >=20.5.0"),
+                ("11.0.0-pre.1", "^18.17.0 || >=20.5.0"),
+                ("11.6.2", "^20.17.0 || >=22.9.0"),
+            ]
+            .into_iter()
+            .map(|(version, node_engine)| {
+                (
+                    Version::from_str(version).unwrap(),
+                    package_packument("npm")
+                        .version(version)
+                        .node_engine(node_engine.to_string())
+                        .build(),
+                )
+            })
+            .collect(),
+            source: PackumentSource::default(),
+        }
+    }
+
+    #[test]
+    fn test_check_node_engine() {
+        let packument = npm_packument();
+        let npm_11 = &packument.versions[&Version::from_str("11.6.2").unwrap()];
+        assert!(check_node_engine(&packument, npm_11, &Version::new(22, 20, 0)).is_ok());
+        assert!(
+            check_node_engine(
+                &packument,
+                &package_packument("npm").build(),
+                &Version::new(18, 20, 0)
+            )
+            .is_ok()
+        );
+        assert_error_snapshot(
+            &check_node_engine(&packument, npm_11, &Version::new(18, 20, 0))
+                .expect_err("npm 11 should not support Node.js 18"),
+        );
+    }
+
+    fn write_vendored_packument(app_dir: &Path, package_name: &str, versions: &[&str]) -> PathBuf {
+        let vendor_dir = app_dir.join(VENDOR_DIR).join(package_name);
+        fs::create_dir_all(&vendor_dir).unwrap();
+        let versions = versions
+            .iter()
+            .map(|version| {
+                (
+                    (*version).to_string(),
+                    serde_json::json!({
+                        "name": package_name,
+                        "version": version,
+                        "dist": {
+                            "tarball": format!("https://registry.npmjs.org/{package_name}/-/{package_name}-{version}.tgz"),
+                        },
+                    }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        fs::write(
+            vendor_dir.join(VENDORED_PACKUMENT_FILENAME),
+            serde_json::json!({ "name": package_name, "versions": versions }).to_string(),
+        )
+        .unwrap();
+        vendor_dir
+    }
+
+    #[test]
+    fn test_read_vendored_packument() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let vendor_dir = write_vendored_packument(app_dir.path(), "pnpm", &["9.0.0", "9.1.0"]);
+        fs::write(vendor_dir.join("pnpm-9.0.0.tgz"), "").unwrap();
+
+        let packument = read_vendored_packument(app_dir.path(), "pnpm")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            packument.source,
+            PackumentSource::Vendored(vendor_dir.clone())
+        );
+        assert_eq!(
+            packument.versions.keys().collect::<Vec<_>>(),
+            vec![&Version::from_str("9.0.0").unwrap()]
+        );
+
+        let package_packument =
+            resolve_package_packument(&packument, &VersionRange::parse("9.x").unwrap()).unwrap();
+        assert_eq!(
+            package_packument.dist.tarball,
+            format!("file://{}", vendor_dir.join("pnpm-9.0.0.tgz").display())
+        );
+    }
+
+    #[test]
+    fn test_read_vendored_packument_uses_npm_pack_tarball_name_for_scoped_package() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let vendor_dir = write_vendored_packument(app_dir.path(), "@yarnpkg/cli-dist", &["4.5.0"]);
+        fs::write(vendor_dir.join("yarnpkg-cli-dist-4.5.0.tgz"), "").unwrap();
+
+        let packument = read_vendored_packument(app_dir.path(), "@yarnpkg/cli-dist")
+            .unwrap()
+            .unwrap();
+        assert_eq!(packument.versions.len(), 1);
+    }
+
+    #[test]
+    fn test_read_vendored_packument_not_vendored() {
+        let app_dir = tempfile::tempdir().unwrap();
+        assert!(
+            read_vendored_packument(app_dir.path(), "pnpm")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_resolve_vendored_package_packument_error() {
+        let packument = Packument {
+            name: "pnpm".to_string(),
+            versions: HashMap::from([(
+                Version::from_str("9.0.0").unwrap(),
+                package_packument("pnpm").version("9.0.0").build(),
+            )]),
+            source: PackumentSource::default(),
+        };
+        assert_error_snapshot(&create_resolve_vendored_package_packument_error(
+            &packument,
+            Path::new("/workspace/.heroku/vendor/pnpm"),
+            &VersionRange::parse("10.x").unwrap(),
+        ));
+    }
+
+    #[test]
+    fn test_resolve_vendored_scoped_package_packument_error() {
+        let packument = Packument {
+            name: "@yarnpkg/cli-dist".to_string(),
+            versions: HashMap::new(),
+            source: PackumentSource::default(),
+        };
+        assert_error_snapshot(&create_resolve_vendored_package_packument_error(
+            &packument,
+            Path::new("/workspace/.heroku/vendor/@yarnpkg/cli-dist"),
+            &VersionRange::parse("4.x").unwrap(),
+        ));
+    }
+
+    #[test]
+    fn test_resolve_stale_package_packument_error() {
+        let packument = Packument {
+            name: "pnpm".to_string(),
+            versions: HashMap::from([(
+                Version::from_str("9.0.0").unwrap(),
+                package_packument("pnpm").version("9.0.0").build(),
+            )]),
+            source: PackumentSource::StaleCache(
+                "error sending request for url (https://registry.npmjs.org/pnpm)".to_string(),
+            ),
+        };
+        assert!(
+            resolve_package_packument(&packument, &VersionRange::parse("9.x").unwrap()).is_ok()
+        );
+        assert_error_snapshot(
+            &resolve_package_packument(&packument, &VersionRange::parse("10.x").unwrap())
+                .err()
+                .expect("requirement should not resolve from the stale packument"),
+        );
+    }
+
+    fn start_registry(status_code: u16) -> MockServer {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let server = MockServer::start().await;
+                Mock::given(method("GET"))
+                    .and(path("/pnpm"))
+                    .and(header("If-None-Match", "\"new-etag\""))
+                    .respond_with(ResponseTemplate::new(304))
+                    .with_priority(1)
+                    .mount(&server)
+                    .await;
+                Mock::given(method("GET"))
+                    .and(path("/pnpm"))
+                    .and(|request: &Request| {
+                        request.headers.get(ACCEPT)
+                            == Some(&HeaderValue::from_static(ABBREVIATED_PACKUMENT_ACCEPT))
+                    })
+                    .respond_with(
+                        ResponseTemplate::new(status_code)
+                            .insert_header("ETag", "\"new-etag\"")
+                            .set_body_string(r#"{"name":"pnpm","versions":{}}"#),
+                    )
+                    .mount(&server)
+                    .await;
+                server
+            })
+    }
+
+    fn cached_metadata() -> PackumentMetadata {
+        PackumentMetadata {
+            etag: Some("\"old-etag\"".to_string()),
+            last_modified: None,
+        }
+    }
+
+    fn fetch_from_registry(
+        registry_url: &str,
+        packument_file: &Path,
+        packument_metadata: Option<&PackumentMetadata>,
+    ) -> Result<FetchPackumentOutcome, PackumentLayerError> {
+        rustls::crypto::ring::default_provider()
+            .install_default()
+            .ok();
+        let request = GetRequest::builder(format!("{registry_url}/pnpm"))
+            .headers(packument_request_headers(packument_metadata))
+            .max_retries(0)
+            .build();
+        let mut result = None;
+        bullet_stream::global::with_locked_writer(Vec::<u8>::new(), || {
+            result = Some(fetch_packument(
+                &request,
+                "pnpm",
+                packument_file,
+                packument_metadata.is_some(),
+            ));
+        });
+        result.unwrap()
+    }
+
+    #[test]
+    fn test_fetch_packument_updates_cache() {
+        let registry = start_registry(200);
+        let packument_file = tempfile::NamedTempFile::new().unwrap();
+
+        match fetch_from_registry(
+            &registry.uri(),
+            packument_file.path(),
+            Some(&cached_metadata()),
+        )
+        .unwrap()
+        {
+            FetchPackumentOutcome::Updated(packument_metadata) => {
+                assert_eq!(packument_metadata.etag, Some("\"new-etag\"".to_string()));
+            }
+            _ => panic!("Expected the packument to be updated"),
+        }
+        assert_eq!(
+            fs::read_to_string(packument_file.path()).unwrap(),
+            r#"{"name":"pnpm","versions":{}}"#
+        );
+    }
+
+    #[test]
+    fn test_fetch_packument_not_modified() {
+        let registry = start_registry(200);
+        let packument_file = tempfile::NamedTempFile::new().unwrap();
+
+        assert!(matches!(
+            fetch_from_registry(
+                &registry.uri(),
+                packument_file.path(),
+                Some(&PackumentMetadata {
+                    etag: Some("\"new-etag\"".to_string()),
+                    last_modified: None,
+                })
+            )
+            .unwrap(),
+            FetchPackumentOutcome::NotModified
+        ));
+    }
+
+    #[test]
+    fn test_fetch_packument_uses_stale_cache_on_server_error() {
+        let registry = start_registry(503);
+        let packument_file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(packument_file.path(), "cached").unwrap();
+
+        assert!(matches!(
+            fetch_from_registry(
+                &registry.uri(),
+                packument_file.path(),
+                Some(&cached_metadata())
+            )
+            .unwrap(),
+            FetchPackumentOutcome::Stale(_)
+        ));
+        assert_eq!(fs::read_to_string(packument_file.path()).unwrap(), "cached");
+    }
+
+    #[test]
+    fn test_fetch_packument_uses_stale_cache_when_registry_is_unreachable() {
+        // binding then dropping a listener leaves a local port that refuses connections
+        let registry_url = format!(
+            "http://{}",
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        );
+        let packument_file = tempfile::NamedTempFile::new().unwrap();
+
+        assert!(matches!(
+            fetch_from_registry(
+                &registry_url,
+                packument_file.path(),
+                Some(&cached_metadata())
+            )
+            .unwrap(),
+            FetchPackumentOutcome::Stale(_)
+        ));
+    }
+
+    #[test]
+    fn test_fetch_packument_fails_on_server_error_without_cache() {
+        let registry = start_registry(503);
+        let packument_file = tempfile::NamedTempFile::new().unwrap();
+
+        assert!(matches!(
+            fetch_from_registry(&registry.uri(), packument_file.path(), None),
+            Err(PackumentLayerError::FetchPackument(..))
+        ));
+    }
+
+    #[test]
+    fn test_fetch_packument_fails_on_client_error_with_cache() {
+        let registry = start_registry(404);
+        let packument_file = tempfile::NamedTempFile::new().unwrap();
+
+        assert!(matches!(
+            fetch_from_registry(
+                &registry.uri(),
+                packument_file.path(),
+                Some(&cached_metadata())
+            ),
+            Err(PackumentLayerError::FetchPackument(..))
+        ));
+    }
 }
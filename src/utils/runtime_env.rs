@@ -1,6 +1,8 @@
 use crate::{BuildpackBuildContext, BuildpackResult};
 use libcnb::data::layer::LayerName;
 use libcnb::layer::UncachedLayerDefinition;
+use libcnb::layer_env::{LayerEnv, ModificationBehavior, Scope};
+use std::ffi::OsString;
 use std::path::PathBuf;
 
 pub(crate) fn register_execd_script(
@@ -19,3 +21,30 @@ pub(crate) fn register_execd_script(
     layer.write_exec_d_programs([(program_name, script)])?;
     Ok(())
 }
+
+pub(crate) fn set_default_launch_env_var(
+    context: &BuildpackBuildContext,
+    name: impl Into<OsString>,
+    value: impl Into<OsString>,
+) -> BuildpackResult<()> {
+    let name = name.into();
+    let value = value.into();
+    let layer_name = format!("launch_default_{}", name.to_string_lossy().to_lowercase())
+        .parse::<LayerName>()
+        .expect("Layer name should be valid");
+
+    let layer = context.uncached_layer(
+        layer_name,
+        UncachedLayerDefinition {
+            build: false,
+            launch: true,
+        },
+    )?;
+
+    let mut layer_env = LayerEnv::new();
+    layer_env.insert(Scope::Launch, ModificationBehavior::Default, name, value);
+
+    layer.write_env(layer_env)?;
+
+    Ok(())
+}
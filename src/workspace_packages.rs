@@ -0,0 +1,210 @@
+use crate::package_json::PackageJson;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Finds the workspace package directories, relative to the app directory, matched by the npm and
+/// Yarn `workspaces` field and the `packages` listed in `pnpm-workspace.yaml`.
+///
+/// Patterns support `*` and `?` within a path segment, `**` across any number of segments, and
+/// exclusions prefixed with `!`. Directories named `node_modules` or starting with `.` are never
+/// searched.
+pub(crate) fn find_workspace_package_dirs(
+    app_dir: &Path,
+    package_json: &PackageJson,
+) -> Vec<PathBuf> {
+    let mut patterns = package_json.workspace_patterns();
+    patterns.extend(read_pnpm_workspace_patterns(app_dir));
+    if patterns.is_empty() {
+        return vec![];
+    }
+
+    let (exclude, include): (Vec<_>, Vec<_>) = patterns
+        .iter()
+        .map(|pattern| {
+            pattern
+                .strip_prefix('!')
+                .map_or((false, pattern.as_str()), |pattern| (true, pattern))
+        })
+        .map(|(is_exclude, pattern)| (is_exclude, pattern_segments(pattern)))
+        .partition(|(is_exclude, _)| *is_exclude);
+
+    WalkDir::new(app_dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.file_type().is_dir() && name != "node_modules" && !name.starts_with('.')
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().join("package.json").is_file())
+        .filter_map(|entry| {
+            entry
+                .path()
+                .strip_prefix(app_dir)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .filter(|dir| {
+            let dir_segments = dir
+                .iter()
+                .map(|segment| segment.to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            include
+                .iter()
+                .any(|(_, pattern)| matches_segments(pattern, &dir_segments))
+                && !exclude
+                    .iter()
+                    .any(|(_, pattern)| matches_segments(pattern, &dir_segments))
+        })
+        .collect()
+}
+
+/// Errors reading `pnpm-workspace.yaml` are ignored here since they are reported when pnpm is
+/// configured.
+fn read_pnpm_workspace_patterns(app_dir: &Path) -> Vec<String> {
+    std::fs::read_to_string(app_dir.join("pnpm-workspace.yaml"))
+        .ok()
+        .and_then(|contents| yaml_rust2::YamlLoader::load_from_str(&contents).ok())
+        .and_then(|docs| docs.into_iter().next())
+        .and_then(|doc| doc["packages"].as_vec().cloned())
+        .map(|packages| {
+            packages
+                .iter()
+                .filter_map(|pattern| pattern.as_str().map(ToString::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn pattern_segments(pattern: &str) -> Vec<String> {
+    pattern
+        .trim_start_matches("./")
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn matches_segments(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((segment, rest)) if segment == "**" => {
+            (0..=path.len()).any(|skipped| matches_segments(rest, &path[skipped..]))
+        }
+        Some((segment, rest)) => path.split_first().is_some_and(|(name, path)| {
+            matches_wildcards(segment.as_bytes(), name.as_bytes()) && matches_segments(rest, path)
+        }),
+    }
+}
+
+fn matches_wildcards(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=name.len()).any(|skipped| matches_wildcards(rest, &name[skipped..]))
+        }
+        Some((b'?', rest)) => !name.is_empty() && matches_wildcards(rest, &name[1..]),
+        Some((byte, rest)) => name
+            .split_first()
+            .is_some_and(|(first, name)| first == byte && matches_wildcards(rest, name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_package_json(dir: &Path, package_json: &serde_json::Value) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("package.json"), package_json.to_string()).unwrap();
+    }
+
+    fn workspace_package_dirs(app_dir: &Path) -> Vec<String> {
+        let package_json = PackageJson::try_from(app_dir.join("package.json")).unwrap();
+        find_workspace_package_dirs(app_dir, &package_json)
+            .iter()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn find_npm_workspace_package_dirs() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let app_dir = app_dir.path();
+        write_package_json(
+            app_dir,
+            &serde_json::json!({ "workspaces": ["packages/*", "apps/web", "!packages/ignored"] }),
+        );
+        for dir in [
+            "packages/api",
+            "packages/ignored",
+            "packages/api/node_modules/dep",
+            "apps/web",
+            "apps/docs",
+            "tools/cli",
+        ] {
+            write_package_json(&app_dir.join(dir), &serde_json::json!({}));
+        }
+        fs::create_dir_all(app_dir.join("packages/empty")).unwrap();
+
+        assert_eq!(
+            workspace_package_dirs(app_dir),
+            vec!["apps/web", "packages/api"]
+        );
+    }
+
+    #[test]
+    fn find_yarn_workspace_package_dirs_from_object_form() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let app_dir = app_dir.path();
+        write_package_json(
+            app_dir,
+            &serde_json::json!({ "workspaces": { "packages": ["./libs/**"] } }),
+        );
+        for dir in ["libs/a", "libs/nested/b"] {
+            write_package_json(&app_dir.join(dir), &serde_json::json!({}));
+        }
+
+        assert_eq!(
+            workspace_package_dirs(app_dir),
+            vec!["libs/a", "libs/nested/b"]
+        );
+    }
+
+    #[test]
+    fn find_pnpm_workspace_package_dirs() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let app_dir = app_dir.path();
+        write_package_json(app_dir, &serde_json::json!({}));
+        fs::write(
+            app_dir.join("pnpm-workspace.yaml"),
+            "packages:\n  - 'services/svc-?'\n  - 'tools/**'\n  - '!**/test/**'\n",
+        )
+        .unwrap();
+        for dir in [
+            "services/svc-a",
+            "services/svc-ab",
+            "tools/cli",
+            "tools/cli/test/fixture",
+        ] {
+            write_package_json(&app_dir.join(dir), &serde_json::json!({}));
+        }
+
+        assert_eq!(
+            workspace_package_dirs(app_dir),
+            vec!["services/svc-a", "tools/cli"]
+        );
+    }
+
+    #[test]
+    fn find_workspace_package_dirs_without_workspaces() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let app_dir = app_dir.path();
+        write_package_json(app_dir, &serde_json::json!({}));
+        write_package_json(&app_dir.join("packages/api"), &serde_json::json!({}));
+
+        assert!(workspace_package_dirs(app_dir).is_empty());
+    }
+}
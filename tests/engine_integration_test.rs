@@ -1,8 +1,11 @@
 // Required due to: https://github.com/rust-lang/rust/issues/95513
 #![allow(unused_crate_dependencies)]
 
+use indoc::indoc;
 use libcnb::data::buildpack_id;
-use libcnb_test::{BuildpackReference, PackResult, assert_contains, assert_contains_match};
+use libcnb_test::{
+    BuildpackReference, PackResult, assert_contains, assert_contains_match, assert_not_contains,
+};
 use nodejs_data::SUPPORTED_NODEJS_VERSIONS;
 use test_support::{
     assert_web_response, create_build_snapshot, integration_test_with_config,
@@ -69,6 +72,7 @@ fn node_build_and_runtime_env() {
             );
             assert_contains!(env, "LD_LIBRARY_PATH=/layers/heroku_nodejs/dist/lib");
             assert_contains_match!(env, "HEROKU_AVAILABLE_PARALLELISM=\\d+");
+            assert_contains!(env, "NODE_ENV=production");
         },
         &[
             BuildpackReference::WorkspaceBuildpack(buildpack_id!("heroku/nodejs")),
@@ -77,6 +81,70 @@ fn node_build_and_runtime_env() {
     );
 }
 
+#[test]
+#[ignore = "integration test"]
+fn node_env_runtime_config() {
+    nodejs_integration_test_with_config(
+        "./fixtures/node-with-indexjs",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                std::fs::write(
+                    app_dir.join("project.toml"),
+                    indoc! { "
+                        [com.heroku.buildpacks.nodejs]
+                        runtime.node_env = \"staging\"
+                    " },
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            let env = ctx.run_shell_command("env").stdout;
+            assert_contains!(env, "NODE_ENV=staging");
+        },
+    );
+}
+
+#[test]
+#[ignore = "integration test"]
+fn compile_cache_is_warmed_at_build_time() {
+    nodejs_integration_test_with_config(
+        "./fixtures/node-with-indexjs",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                std::fs::write(
+                    app_dir.join("project.toml"),
+                    indoc! { r#"
+                        [com.heroku.buildpacks.nodejs]
+                        actions.compile_cache = true
+                        compile_cache.modules = ["./greeting.js"]
+                    "# },
+                )
+                .unwrap();
+                std::fs::write(
+                    app_dir.join("greeting.js"),
+                    "module.exports = 'node-with-indexjs';\n",
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            assert_contains!(ctx.pack_stdout, "Warming V8 compile cache");
+            assert_not_contains!(ctx.pack_stdout, "could not be warmed");
+            let env = ctx.run_shell_command("env").stdout;
+            assert_contains!(
+                env,
+                "NODE_COMPILE_CACHE=/layers/heroku_nodejs/compile_cache"
+            );
+            let cache_files = ctx
+                .run_shell_command("find /layers/heroku_nodejs/compile_cache -type f")
+                .stdout;
+            assert!(!cache_files.trim().is_empty());
+            assert_web_response(&ctx, "node-with-indexjs");
+        },
+    );
+}
+
 #[test]
 #[ignore = "integration test"]
 fn node_24() {
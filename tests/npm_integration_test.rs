@@ -212,6 +212,25 @@ fn detect_rejects_non_npm_project() {
     );
 }
 
+#[test]
+#[ignore = "integration test"]
+fn npm_engine_incompatible_with_node_version() {
+    nodejs_integration_test_with_config(
+        "./fixtures/npm-project",
+        |config| {
+            config.expected_pack_result(PackResult::Failure);
+            config.app_dir_preprocessor(|app_dir| {
+                set_node_engine(&app_dir, "18.x");
+                set_npm_engine(&app_dir, "11.x");
+            });
+        },
+        |ctx| {
+            assert_contains!(ctx.pack_stdout, "does not support Node.js");
+            assert_contains!(ctx.pack_stdout, "the latest release that supports Node.js");
+        },
+    );
+}
+
 #[test]
 #[ignore = "integration test"]
 fn npm_runtime_settings_are_set() {
@@ -240,6 +259,106 @@ fn test_npm_native_modules_are_recompiled_even_on_cache_restore() {
     );
 }
 
+#[test]
+#[ignore = "integration test"]
+fn test_npm_native_modules_cache() {
+    nodejs_integration_test_with_config(
+        "./fixtures/npm-project-with-native-module",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                std::fs::write(
+                    app_dir.join("project.toml"),
+                    indoc! { "
+                        [com.heroku.buildpacks.nodejs]
+                        actions.cache_native_modules = true
+                    " },
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            assert_contains!(ctx.pack_stdout, "Running dependency install scripts");
+            assert_contains!(ctx.pack_stdout, "Built packages: `dtrace-provider`");
+            let config = ctx.config.clone();
+            ctx.rebuild(config, |ctx| {
+                assert_contains!(
+                    ctx.pack_stdout,
+                    "Restored compiled native modules from cache: `dtrace-provider`"
+                );
+                assert_contains!(ctx.pack_stdout, "No packages require building");
+            });
+        },
+    );
+}
+
+#[test]
+#[ignore = "integration test"]
+fn test_npm_committed_node_modules_are_reinstalled() {
+    nodejs_integration_test_with_config(
+        "./fixtures/npm-project",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                let stale_package = app_dir.join("node_modules/stale-package");
+                std::fs::create_dir_all(&stale_package).unwrap();
+                std::fs::write(
+                    stale_package.join("package.json"),
+                    json!({ "name": "stale-package", "version": "1.0.0" }).to_string(),
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            assert_contains!(
+                ctx.pack_stdout,
+                "A `node_modules` directory was found in the app source"
+            );
+            assert_contains!(ctx.pack_stdout, "Removing committed node_modules");
+            assert_contains!(ctx.pack_stdout, "npm ci");
+            assert_eq!(
+                ctx.run_shell_command("test -d node_modules/stale-package || echo removed")
+                    .stdout
+                    .trim(),
+                "removed"
+            );
+        },
+    );
+}
+
+#[test]
+#[ignore = "integration test"]
+fn test_npm_committed_node_modules_are_rebuilt() {
+    nodejs_integration_test_with_config(
+        "./fixtures/npm-project-with-native-module",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                std::fs::write(
+                    app_dir.join("project.toml"),
+                    indoc! { "
+                        [com.heroku.buildpacks.nodejs]
+                        actions.committed_node_modules = \"rebuild\"
+                    " },
+                )
+                .unwrap();
+                let committed_package = app_dir.join("node_modules/committed-package");
+                std::fs::create_dir_all(&committed_package).unwrap();
+                std::fs::write(
+                    committed_package.join("package.json"),
+                    json!({ "name": "committed-package", "version": "1.0.0" }).to_string(),
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            assert_contains!(
+                ctx.pack_stdout,
+                "A `node_modules` directory was found in the app source"
+            );
+            assert_contains!(ctx.pack_stdout, "Rebuilding committed node_modules");
+            assert_contains!(ctx.pack_stdout, "npm rebuild");
+        },
+    );
+}
+
 #[test]
 #[ignore = "integration test"]
 fn test_npm_engine_native_modules_are_recompiled_even_on_cache_restore() {
@@ -110,6 +110,35 @@ fn test_pnpm_native_modules_are_recompiled_even_on_cache_restore() {
     });
 }
 
+#[test]
+#[ignore = "integration test"]
+fn test_pnpm_native_modules_with_slim_runtime() {
+    nodejs_integration_test_with_config(
+        "./fixtures/pnpm-project-with-native-module",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                std::fs::write(
+                    app_dir.join("project.toml"),
+                    indoc! { "
+                        [com.heroku.buildpacks.nodejs]
+                        runtime.slim = true
+                    " },
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            assert_contains!(ctx.pack_stdout, "from the launch image");
+            let dist = ctx
+                .run_shell_command("ls /layers/heroku_nodejs/dist /layers/heroku_nodejs/dist/bin")
+                .stdout;
+            assert!(!dist.contains("include"));
+            assert!(!dist.contains("npm"));
+            assert!(!dist.contains("corepack"));
+        },
+    );
+}
+
 #[test]
 #[ignore = "integration test"]
 fn test_pnpm_skip_build_scripts_from_buildplan() {
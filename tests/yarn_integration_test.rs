@@ -3,7 +3,7 @@
 
 use indoc::indoc;
 use libcnb::data::buildpack_id;
-use libcnb_test::{BuildpackReference, assert_contains};
+use libcnb_test::{BuildpackReference, assert_contains, assert_not_contains};
 use std::os::unix::fs::PermissionsExt;
 use test_support::{
     add_build_script, assert_web_response, create_build_snapshot, custom_buildpack,
@@ -121,6 +121,62 @@ fn test_yarn_1_native_modules_are_recompiled_even_on_cache_restore() {
     });
 }
 
+#[test]
+#[ignore = "integration test"]
+fn test_yarn_1_allow_list_rebuild_with_slim_runtime() {
+    nodejs_integration_test_with_config(
+        "./fixtures/yarn-1-project-with-native-module",
+        |config| {
+            config.app_dir_preprocessor(|app_dir| {
+                std::fs::write(
+                    app_dir.join("project.toml"),
+                    indoc! { r#"
+                        [com.heroku.buildpacks.nodejs]
+                        actions.install_scripts = "allow_list"
+                        install_scripts.allowed = ["dtrace-provider"]
+                        runtime.slim = true
+                    "# },
+                )
+                .unwrap();
+            });
+        },
+        |ctx| {
+            assert_contains!(ctx.pack_stdout, "headers and npm are kept for the build");
+            assert_contains!(
+                ctx.pack_stdout,
+                "Built allow-listed packages: `dtrace-provider`"
+            );
+            let dist = ctx
+                .run_shell_command("ls /layers/heroku_nodejs/dist/bin")
+                .stdout;
+            assert!(!dist.contains("npm"));
+        },
+    );
+}
+
+#[test]
+#[ignore = "integration test"]
+fn test_yarn_cache_archives_survive_rebuild() {
+    for fixture in [
+        "./fixtures/yarn-3-pnp-nonzero",
+        "./fixtures/yarn-4-pnp-nonzero",
+    ] {
+        nodejs_integration_test(fixture, |ctx| {
+            // the Plug'n'Play cache is a launch layer so it can be inspected in the app image
+            let count_archives = "ls /layers/heroku_nodejs/yarn_cache | grep -c '\\.zip$'";
+            let archive_count = ctx.run_shell_command(count_archives).stdout;
+            assert_ne!(archive_count.trim(), "0");
+
+            let config = ctx.config.clone();
+            ctx.rebuild(config, |ctx| {
+                assert_contains!(ctx.pack_stdout, "Restoring yarn dependency cache");
+                assert_not_contains!(ctx.pack_stdout, "unreferenced packages");
+                assert_eq!(ctx.run_shell_command(count_archives).stdout, archive_count);
+            });
+        });
+    }
+}
+
 #[test]
 #[ignore = "integration test"]
 fn test_yarn_4_modules_native_modules_are_recompiled_even_on_cache_restore() {